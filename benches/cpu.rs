@@ -0,0 +1,61 @@
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use nes::CPU;
+
+/* keep going until a benchmark has run for at least this long */
+const TARGET: Duration = Duration::from_millis(500);
+
+fn bench(name: &str, mut f: impl FnMut()) {
+    /* warm up, and find out roughly how long one call takes */
+    let start = Instant::now();
+    f();
+    let once = start.elapsed().max(Duration::from_nanos(1));
+    let iterations = (TARGET.as_nanos() / once.as_nanos()).clamp(1, 1_000_000) as u32;
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let per_iter = start.elapsed() / iterations;
+    println!(
+        "{:<28} {:>12?}/iter ({} iterations)",
+        name, per_iter, iterations
+    );
+}
+
+/* INX; JMP $8000, forever */
+fn looping_cpu() -> CPU {
+    let mut cpu = CPU::new();
+    cpu.init(vec![0xE8, 0x4C, 0x00, 0x80]).unwrap();
+    cpu
+}
+
+fn main() {
+    const STEPS: u32 = 100_000;
+
+    let mut cpu = looping_cpu();
+    bench("dispatch (100k instructions)", || {
+        for _ in 0..STEPS {
+            black_box(cpu.step()).unwrap();
+        }
+    });
+
+    let mut cpu = looping_cpu();
+    cpu.enable_history(1024);
+    bench("dispatch with history", || {
+        for _ in 0..STEPS {
+            black_box(cpu.step()).unwrap();
+        }
+    });
+
+    let mut cpu = looping_cpu();
+    bench("save state", || {
+        black_box(cpu.save_state());
+    });
+
+    let state = cpu.save_state();
+    bench("load state", || {
+        cpu.load_state(black_box(&state)).unwrap();
+    });
+}
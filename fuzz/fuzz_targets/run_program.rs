@@ -0,0 +1,24 @@
+#![no_main]
+
+/*
+ * Loads arbitrary bytes as a program at $8000 and runs a bounded number of
+ * instructions. Any panic (overflow, out of bounds indexing) is a
+ * finding. Run with `cargo fuzz run run_program` from the repo root.
+ */
+
+use libfuzzer_sys::fuzz_target;
+use nes::CPU;
+
+const MAX_STEPS: usize = 10_000;
+
+fuzz_target!(|data: &[u8]| {
+    /* $8000-$FFFF, leaving room for the reset vector load() writes */
+    let program = data[..data.len().min(0x7FFC)].to_vec();
+    let mut cpu = CPU::new();
+    cpu.init(program);
+    for _ in 0..MAX_STEPS {
+        if cpu.step().is_err() {
+            break;
+        }
+    }
+});
@@ -0,0 +1,43 @@
+/*
+ * C interface to the nes shared library (src/ffi.rs). Written by hand, keep
+ * it in step with the Rust side.
+ *
+ * A handle can be used from any thread, one at a time. Frames, the
+ * framebuffer and controller input aren't emulated yet, so hosts step
+ * instructions.
+ */
+
+#ifndef NES_H
+#define NES_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct nes nes;
+
+nes *nes_create(void);
+void nes_destroy(nes *nes);
+
+/* an iNES image; 0 once loaded and reset, -1 if it can't be */
+int nes_load_rom(nes *nes, const uint8_t *data, size_t len);
+void nes_reset(nes *nes);
+
+/* 1 if running, 0 if stopped at BRK, -1 on an unimplemented opcode */
+int nes_step(nes *nes);
+
+/* the state's size; it's only written when len is big enough */
+size_t nes_save_state(const nes *nes, uint8_t *out, size_t len);
+/* 0 on success, -1 if the state is bad and nothing changed */
+int nes_load_state(nes *nes, const uint8_t *data, size_t len);
+
+uint8_t nes_peek(const nes *nes, uint16_t addr);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
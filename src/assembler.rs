@@ -0,0 +1,604 @@
+/*
+ * A small two-pass 6502 assembler, for writing tests as source instead of
+ * hex and for patching code into memory. Operands use the usual syntax:
+ * #$10 immediate, $10 zero page and $1234 absolute (four hex digits keep a
+ * $00xx address absolute), ,X and ,Y indexing, ($10,X), ($10),Y and
+ * ($1234), and A or nothing for the accumulator. Numbers are $hex, %binary
+ * or decimal.
+ *
+ * `name:` defines a label, which can be used anywhere a number can, with
+ * <name and >name for its low and high bytes. Labels defined further down
+ * are assumed to be absolute, since their size has to be picked on the
+ * first pass. `.org $nnnn` moves ahead, padding with zeros, and `.byte`
+ * takes a comma separated list. Comments start with ';'.
+ *
+ * Statements are split on mnemonics, labels and directives rather than on
+ * lines, so the tokens of an `asm!` invocation can be passed through
+ * stringify! as is.
+ */
+
+use std::collections::HashMap;
+
+use crate::opcodes::{Opcode, OPCODES};
+use crate::AddressingMode::{self, *};
+
+/* where CPU::load puts programs */
+pub const ORIGIN: u16 = 0x8000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    /* the value and whether it was written too wide for the zero page */
+    Number(u16, bool),
+    Punct(char),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Number(u16, bool),
+    Label(String),
+    Low(String),
+    High(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    Implied,
+    Immediate(Value),
+    Address(Value, Option<char>),
+    Indirect(Value),
+    IndirectX(Value),
+    IndirectY(Value),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Statement {
+    Label(String),
+    Instruction(String, Operand),
+    Org(u16),
+    Byte(Vec<Value>),
+}
+
+fn is_mnemonic(word: &str) -> bool {
+    OPCODES
+        .iter()
+        .any(|op| op.mnemonic.name().eq_ignore_ascii_case(word))
+}
+
+fn lex(src: &str) -> Result<Vec<(usize, Token)>, String> {
+    let mut tokens = Vec::new();
+    for (i, line) in src.lines().enumerate() {
+        let line_no = i + 1;
+        let code = line.split(';').next().unwrap();
+        let mut chars = code.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+            let token = if c == '$' || c == '%' || c.is_ascii_digit() {
+                let radix = match c {
+                    '$' => 16,
+                    '%' => 2,
+                    _ => 10,
+                };
+                if radix != 10 {
+                    chars.next();
+                }
+                let mut digits = String::new();
+                while let Some(&d) = chars.peek().filter(|d| d.is_ascii_alphanumeric()) {
+                    digits.push(d);
+                    chars.next();
+                }
+                let value = u16::from_str_radix(&digits, radix)
+                    .map_err(|_| format!("line {}: bad number '{}'", line_no, digits))?;
+                Token::Number(value, value > 0xFF || (radix == 16 && digits.len() > 2))
+            } else if c.is_ascii_alphabetic() || c == '_' {
+                let mut word = String::new();
+                while let Some(&d) = chars
+                    .peek()
+                    .filter(|d| d.is_ascii_alphanumeric() || **d == '_')
+                {
+                    word.push(d);
+                    chars.next();
+                }
+                Token::Ident(word)
+            } else {
+                chars.next();
+                Token::Punct(c)
+            };
+            tokens.push((line_no, token));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + offset).map(|(_, token)| token)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.peek_at(0)
+    }
+
+    /* the line of the last token taken, for errors */
+    fn line(&self) -> usize {
+        self.tokens
+            .get(
+                self.pos
+                    .saturating_sub(1)
+                    .min(self.tokens.len().saturating_sub(1)),
+            )
+            .map_or(0, |(line, _)| *line)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(&Token::Punct(c));
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Punct(p)) if p == c => Ok(()),
+            _ => Err(format!("line {}: expected '{}'", self.line(), c)),
+        }
+    }
+
+    /* an operand ends where the next statement starts */
+    fn at_statement_end(&self) -> bool {
+        match self.peek() {
+            None | Some(Token::Punct('.')) => true,
+            Some(Token::Ident(word)) => {
+                is_mnemonic(word) || self.peek_at(1) == Some(&Token::Punct(':'))
+            }
+            _ => false,
+        }
+    }
+
+    fn label(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Ident(name)) => Ok(name),
+            _ => Err(format!("line {}: expected a label", self.line())),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        if self.eat('<') {
+            return Ok(Value::Low(self.label()?));
+        }
+        if self.eat('>') {
+            return Ok(Value::High(self.label()?));
+        }
+        match self.next() {
+            Some(Token::Number(value, wide)) => Ok(Value::Number(value, wide)),
+            Some(Token::Ident(name)) if !is_mnemonic(&name) => Ok(Value::Label(name)),
+            _ => Err(format!("line {}: expected a number or label", self.line())),
+        }
+    }
+
+    fn index(&mut self) -> Result<char, String> {
+        match self.next() {
+            Some(Token::Ident(r)) if r.eq_ignore_ascii_case("x") => Ok('X'),
+            Some(Token::Ident(r)) if r.eq_ignore_ascii_case("y") => Ok('Y'),
+            _ => Err(format!("line {}: expected X or Y", self.line())),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, String> {
+        if self.at_statement_end() {
+            return Ok(Operand::Implied);
+        }
+        match self.peek() {
+            Some(Token::Ident(a)) if a.eq_ignore_ascii_case("a") => {
+                self.next();
+                Ok(Operand::Implied)
+            }
+            Some(Token::Punct('#')) => {
+                self.next();
+                Ok(Operand::Immediate(self.value()?))
+            }
+            Some(Token::Punct('(')) => {
+                self.next();
+                let value = self.value()?;
+                if self.eat(',') {
+                    if self.index()? != 'X' {
+                        return Err(format!("line {}: only ($nn,X) exists", self.line()));
+                    }
+                    self.expect(')')?;
+                    return Ok(Operand::IndirectX(value));
+                }
+                self.expect(')')?;
+                if self.eat(',') {
+                    if self.index()? != 'Y' {
+                        return Err(format!("line {}: only ($nn),Y exists", self.line()));
+                    }
+                    return Ok(Operand::IndirectY(value));
+                }
+                Ok(Operand::Indirect(value))
+            }
+            _ => {
+                let value = self.value()?;
+                let index = if self.eat(',') {
+                    Some(self.index()?)
+                } else {
+                    None
+                };
+                Ok(Operand::Address(value, index))
+            }
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, String> {
+        match self.next() {
+            Some(Token::Punct('.')) => {
+                let directive = self.label()?.to_ascii_lowercase();
+                match directive.as_str() {
+                    "org" => match self.next() {
+                        Some(Token::Number(addr, _)) => Ok(Statement::Org(addr)),
+                        _ => Err(format!("line {}: .org needs an address", self.line())),
+                    },
+                    "byte" => {
+                        let mut values = vec![self.value()?];
+                        while self.eat(',') {
+                            values.push(self.value()?);
+                        }
+                        Ok(Statement::Byte(values))
+                    }
+                    _ => Err(format!(
+                        "line {}: unknown directive .{}",
+                        self.line(),
+                        directive
+                    )),
+                }
+            }
+            Some(Token::Ident(name)) if self.eat(':') => Ok(Statement::Label(name)),
+            Some(Token::Ident(word)) if is_mnemonic(&word) => Ok(Statement::Instruction(
+                word.to_ascii_uppercase(),
+                self.operand()?,
+            )),
+            _ => Err(format!("line {}: expected an instruction", self.line())),
+        }
+    }
+}
+
+/* official encodings win over unofficial ones with the same mnemonic */
+fn find(mnemonic: &str, mode: AddressingMode, len: u8) -> Option<&'static Opcode> {
+    let matches = |op: &&Opcode| op.mnemonic.name() == mnemonic && op.mode == mode && op.len == len;
+    OPCODES
+        .iter()
+        .filter(matches)
+        .find(|op| op.official)
+        .or_else(|| OPCODES.iter().find(matches))
+}
+
+/* the encoding to use, `wide` when the operand might not fit the zero page */
+fn select(mnemonic: &str, operand: &Operand, wide: bool) -> Option<&'static Opcode> {
+    match operand {
+        Operand::Implied => {
+            find(mnemonic, NoneAddressing, 1).or_else(|| find(mnemonic, Accumulator, 1))
+        }
+        Operand::Immediate(_) => find(mnemonic, Immediate, 2),
+        Operand::Indirect(_) => find(mnemonic, Indirect, 3),
+        Operand::IndirectX(_) => find(mnemonic, Indirect_X, 2),
+        Operand::IndirectY(_) => find(mnemonic, Indirect_Y, 2),
+        Operand::Address(_, index) => {
+            let (zero_page, absolute) = match index {
+                None => (ZeroPage, Absolute),
+                Some('X') => (ZeroPage_X, Absolute_X),
+                _ => (ZeroPage_Y, Absolute_Y),
+            };
+            let zero_page = || find(mnemonic, zero_page, 2);
+            let absolute = || find(mnemonic, absolute, 3);
+            let relative = || find(mnemonic, Relative, 2).filter(|_| index.is_none());
+            if wide {
+                relative().or_else(absolute).or_else(zero_page)
+            } else {
+                relative().or_else(zero_page).or_else(absolute)
+            }
+        }
+    }
+}
+
+fn operand_value(operand: &Operand) -> Option<&Value> {
+    match operand {
+        Operand::Implied => None,
+        Operand::Immediate(value)
+        | Operand::Address(value, _)
+        | Operand::Indirect(value)
+        | Operand::IndirectX(value)
+        | Operand::IndirectY(value) => Some(value),
+    }
+}
+
+fn resolve(value: &Value, labels: &HashMap<String, u16>, line: usize) -> Result<u16, String> {
+    let label = |name: &String| {
+        labels
+            .get(name)
+            .copied()
+            .ok_or_else(|| format!("line {}: unknown label '{}'", line, name))
+    };
+    match value {
+        Value::Number(value, _) => Ok(*value),
+        Value::Label(name) => label(name),
+        Value::Low(name) => Ok(label(name)? & 0xFF),
+        Value::High(name) => Ok(label(name)? >> 8),
+    }
+}
+
+fn byte(value: u16, line: usize) -> Result<u8, String> {
+    u8::try_from(value).map_err(|_| format!("line {}: ${:04X} doesn't fit in a byte", line, value))
+}
+
+/* assembles `src` to run at ORIGIN */
+pub fn assemble(src: &str) -> Result<Vec<u8>, String> {
+    assemble_at(src, ORIGIN)
+}
+
+/* assembles `src` to run at `origin`, the output starts there */
+pub fn assemble_at(src: &str, origin: u16) -> Result<Vec<u8>, String> {
+    let mut parser = Parser {
+        tokens: lex(src)?,
+        pos: 0,
+    };
+    let mut statements = Vec::new();
+    while let Some(&(line, _)) = parser.tokens.get(parser.pos) {
+        statements.push((line, parser.statement()?));
+    }
+
+    /* first pass: pick every instruction's encoding and place the labels */
+    let mut labels = HashMap::new();
+    let mut encodings = Vec::new();
+    let mut pc = origin as usize;
+    for (line, statement) in &statements {
+        match statement {
+            Statement::Label(name) => {
+                if labels.insert(name.clone(), pc as u16).is_some() {
+                    return Err(format!("line {}: label '{}' defined twice", line, name));
+                }
+            }
+            Statement::Instruction(mnemonic, operand) => {
+                let wide = match operand_value(operand) {
+                    Some(Value::Number(_, wide)) => *wide,
+                    Some(Value::Label(name)) => labels.get(name).is_none_or(|&addr| addr > 0xFF),
+                    _ => false,
+                };
+                let op = select(mnemonic, operand, wide).ok_or_else(|| {
+                    format!("line {}: {} can't take that operand", line, mnemonic)
+                })?;
+                encodings.push(op);
+                pc += op.len as usize;
+            }
+            Statement::Org(addr) => {
+                if (*addr as usize) < pc {
+                    return Err(format!("line {}: .org ${:04X} is behind", line, addr));
+                }
+                pc = *addr as usize;
+            }
+            Statement::Byte(values) => pc += values.len(),
+        }
+        if pc > 0x10000 {
+            return Err(format!("line {}: past the end of memory", line));
+        }
+    }
+
+    /* second pass: everything is placed, fill in the operands */
+    let mut out = Vec::new();
+    let mut encodings = encodings.into_iter();
+    for (line, statement) in &statements {
+        let line = *line;
+        match statement {
+            Statement::Label(_) => {}
+            Statement::Instruction(_, operand) => {
+                let op = encodings.next().unwrap();
+                let pc = origin.wrapping_add(out.len() as u16);
+                out.push(op.code);
+                let Some(value) = operand_value(operand) else {
+                    continue;
+                };
+                let value = resolve(value, &labels, line)?;
+                match (op.mode, op.len) {
+                    (Relative, _) => {
+                        let offset = value.wrapping_sub(pc.wrapping_add(2)) as i16;
+                        if !(-128..=127).contains(&offset) {
+                            return Err(format!(
+                                "line {}: branch to ${:04X} is out of range",
+                                line, value
+                            ));
+                        }
+                        out.push(offset as u8);
+                    }
+                    (_, 2) => out.push(byte(value, line)?),
+                    _ => out.extend(value.to_le_bytes()),
+                }
+            }
+            Statement::Org(addr) => out.resize((addr - origin) as usize, 0),
+            Statement::Byte(values) => {
+                for value in values {
+                    out.push(byte(resolve(value, &labels, line)?, line)?);
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/*
+ * Assembles at test time and panics on errors:
+ *   cpu.load_and_run(asm! { LDY #$01  LDA #$03  STA $01  BRK }).unwrap();
+ * The source has to get through Rust's tokenizer first, and hex like $8E
+ * reads as a float with a broken exponent, so source with those can be
+ * passed as a string instead: asm!("STX $8E").
+ */
+#[macro_export]
+macro_rules! asm {
+    ($src:literal) => {
+        $crate::assembler::assemble($src).unwrap_or_else(|e| panic!("asm!: {}", e))
+    };
+    ($($source:tt)*) => {
+        $crate::assembler::assemble(stringify!($($source)*))
+            .unwrap_or_else(|e| panic!("asm!: {}", e))
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_addressing_modes() {
+        assert_eq!(
+            assemble(
+                "LDA #$05
+                 LDA $10
+                 LDA $10,X
+                 LDX $10,Y
+                 LDA $1234
+                 LDA $0010
+                 LDA $1234,X
+                 LDA $1234,Y
+                 LDA ($10,X)
+                 LDA ($10),Y
+                 JMP ($1234)
+                 ASL A
+                 ASL
+                 TAX"
+            )
+            .unwrap(),
+            vec![
+                0xA9, 0x05, 0xA5, 0x10, 0xB5, 0x10, 0xB6, 0x10, 0xAD, 0x34, 0x12, 0xAD, 0x10, 0x00,
+                0xBD, 0x34, 0x12, 0xB9, 0x34, 0x12, 0xA1, 0x10, 0xB1, 0x10, 0x6C, 0x34, 0x12, 0x0A,
+                0x0A, 0xAA
+            ]
+        );
+    }
+
+    #[test]
+    fn test_numbers_and_branches() {
+        assert_eq!(
+            assemble("lda #%1010 ldx #200").unwrap(),
+            vec![0xA9, 0x0A, 0xA2, 200]
+        );
+        /* there's no STX $nnnn,Y */
+        assert_eq!(
+            assemble("STX $0310,Y").unwrap_err(),
+            "line 1: $0310 doesn't fit in a byte"
+        );
+        assert_eq!(assemble("INX BNE $8000").unwrap(), vec![0xE8, 0xD0, 0xFD]);
+        assert_eq!(assemble("BEQ $8010").unwrap(), vec![0xF0, 0x0E]);
+        assert_eq!(
+            assemble("BEQ $9000").unwrap_err(),
+            "line 1: branch to $9000 is out of range"
+        );
+        /* an unofficial encoding when that's the only one */
+        assert_eq!(assemble("LAX $10").unwrap(), vec![0xA7, 0x10]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            assemble("LDA #$05\nFOO").unwrap_err(),
+            "line 2: expected an instruction"
+        );
+        assert_eq!(
+            assemble("INX\nLDA ($10,Y)").unwrap_err(),
+            "line 2: only ($nn,X) exists"
+        );
+        assert_eq!(
+            assemble("LDA #$10000").unwrap_err(),
+            "line 1: bad number '10000'"
+        );
+    }
+
+    #[test]
+    fn test_labels() {
+        let program = assemble(
+            "      LDX #0
+             loop: LDA table,X
+                   BEQ done   ; forward, but branches are always relative
+                   STA $0200,X
+                   INX
+                   JMP loop
+             done: BRK
+             table:
+                   .byte $01, 2, %11, 0
+                   .byte <table, >table",
+        )
+        .unwrap();
+        assert_eq!(
+            program,
+            vec![
+                0xA2, 0x00, 0xBD, 0x0F, 0x80, 0xF0, 0x07, 0x9D, 0x00, 0x02, 0xE8, 0x4C, 0x02, 0x80,
+                0x00, 0x01, 0x02, 0x03, 0x00, 0x0F, 0x80
+            ]
+        );
+    }
+
+    #[test]
+    fn test_zero_page_labels() {
+        /* a label known to be in the zero page gets the short form */
+        assert_eq!(
+            assemble_at("ptr: .byte 0\n LDA ptr\n LDA later\n later: BRK", 0x0010).unwrap(),
+            vec![0x00, 0xA5, 0x10, 0xAD, 0x16, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_org() {
+        assert_eq!(
+            assemble_at("LDA #1\n .org $0604\n start: JMP start", 0x0600).unwrap(),
+            vec![0xA9, 0x01, 0x00, 0x00, 0x4C, 0x04, 0x06]
+        );
+        assert_eq!(
+            assemble(".org $8004 NOP .org $8002").unwrap_err(),
+            "line 1: .org $8002 is behind"
+        );
+    }
+
+    #[test]
+    fn test_label_errors() {
+        assert_eq!(
+            assemble("JMP nowhere").unwrap_err(),
+            "line 1: unknown label 'nowhere'"
+        );
+        assert_eq!(
+            assemble("a1: NOP\na1: NOP").unwrap_err(),
+            "line 2: label 'a1' defined twice"
+        );
+        assert_eq!(
+            assemble("here: LDA #here").unwrap_err(),
+            "line 1: $8000 doesn't fit in a byte"
+        );
+        assert_eq!(
+            assemble(".word $1234").unwrap_err(),
+            "line 1: unknown directive .word"
+        );
+    }
+
+    #[test]
+    fn test_macro() {
+        assert_eq!(
+            asm! { LDY #$01  LDA #$03  STA $01  STA ($10),Y  BRK },
+            vec![0xA0, 0x01, 0xA9, 0x03, 0x85, 0x01, 0x91, 0x10, 0x00]
+        );
+        assert_eq!(asm!("STX $8E ; comment"), vec![0x86, 0x8E]);
+        assert_eq!(
+            asm! { loop: INX  BNE loop  .byte $ff },
+            vec![0xE8, 0xD0, 0xFD, 0xFF]
+        );
+    }
+}
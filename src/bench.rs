@@ -0,0 +1,105 @@
+/*
+ * `nes bench <rom>`: runs a ROM headless with nothing pacing it and reports
+ * how fast it went, for comparing builds. It's meant to take `--frames N`
+ * and report FPS, cycles a second and each subsystem's share of the time.
+ * There are no frames or subsystems besides the CPU yet, so it stands in
+ * with `--instructions N`, reporting instructions and CPU cycles a second.
+ */
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::rom::Rom;
+use crate::{CpuError, CPU};
+
+pub const DEFAULT_INSTRUCTIONS: u64 = 10_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub instructions: u64,
+    pub cycles: u64,
+    pub elapsed: Duration,
+    /* why the run ended before the instruction count, if it did */
+    pub stopped: Option<String>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = self.elapsed.as_secs_f64().max(1e-9);
+        write!(
+            f,
+            "{} instructions in {:.3}s, {:.2} million a second, {:.2} million cycles a second",
+            self.instructions,
+            seconds,
+            self.instructions as f64 / seconds / 1e6,
+            self.cycles as f64 / seconds / 1e6
+        )?;
+        if let Some(why) = &self.stopped {
+            write!(f, " (stopped early: {})", why)?;
+        }
+        Ok(())
+    }
+}
+
+/* runs `instructions` from the reset vector, or until the CPU stops */
+pub fn bench(cpu: &mut CPU, instructions: u64) -> Report {
+    cpu.reset();
+    let cycles = cpu.cycles;
+    let mut done = 0;
+    let mut stopped = None;
+    let start = Instant::now();
+    while done < instructions {
+        let result = cpu.step();
+        /* an unimplemented opcode is the one error that leaves it unrun */
+        if !matches!(result, Err(CpuError::UnimplementedOpcode { .. })) {
+            done += 1;
+        }
+        if let Err(e) = result {
+            stopped = Some(e.to_string());
+            break;
+        }
+    }
+    let elapsed = start.elapsed();
+    Report {
+        instructions: done,
+        cycles: cpu.cycles - cycles,
+        elapsed,
+        stopped,
+    }
+}
+
+/* entry point for `nes bench` */
+pub fn run(path: &str, instructions: u64) -> Result<Report, String> {
+    let rom = Rom::load(path)?;
+    let mut cpu = CPU::new();
+    cpu.load_rom(&rom)?;
+    Ok(bench(&mut cpu, instructions))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bench() {
+        let mut cpu = CPU::new();
+        cpu.load(crate::asm! { loop: INX  JMP loop }).unwrap();
+        let report = bench(&mut cpu, 1000);
+        assert_eq!(report.instructions, 1000);
+        /* 2 for each INX, 3 for each JMP */
+        assert_eq!(report.cycles, 2500);
+        assert_eq!(report.stopped, None);
+
+        cpu.load(crate::asm! { INX  INX  BRK }).unwrap();
+        let report = bench(&mut cpu, 1000);
+        assert_eq!(report.instructions, 3);
+        assert_eq!(report.stopped.as_deref(), Some("BRK at $8002"));
+        assert!(report.to_string().starts_with("3 instructions in "));
+
+        /* JAM */
+        cpu.load(vec![0xE8, 0xE8, 0x02]).unwrap();
+        let report = bench(&mut cpu, 1000);
+        assert_eq!(report.instructions, 2);
+        assert_eq!(report.cycles, 4);
+    }
+}
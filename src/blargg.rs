@@ -0,0 +1,251 @@
+/*
+ * Runner for blargg's test ROMs (instr_test-v5 and friends). They report
+ * through PRG RAM: $6001-$6003 hold DE B0 61 once the protocol is active,
+ * $6000 is $80 while running, $81 when the ROM wants a reset pressed and
+ * the result code when done (0 is a pass), and $6004 on is the text the
+ * ROM printed, NUL terminated.
+ */
+
+use std::fmt;
+
+use crate::{CpuError, CPU};
+
+const STATUS: u16 = 0x6000;
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const TEXT: u16 = 0x6004;
+
+const RUNNING: u8 = 0x80;
+const NEEDS_RESET: u8 = 0x81;
+
+/* plenty for any of the CPU tests at a few million instructions a second */
+pub const DEFAULT_MAX_STEPS: u64 = 50_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub code: u8,
+    pub text: String,
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        self.code == 0
+    }
+}
+
+/* why a ROM didn't produce a result */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunError {
+    /* a debugging hook stopped the CPU after `pc` */
+    Stopped { pc: u16 },
+    Crashed { pc: u16, text: String },
+    Timeout { steps: u64 },
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunError::Stopped { pc } => write!(f, "stopped at ${:04X}", pc),
+            RunError::Crashed { pc, text } => {
+                write!(f, "crashed at ${:04X}, output so far: {:?}", pc, text)
+            }
+            RunError::Timeout { steps } => write!(f, "no result after {} instructions", steps),
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.passed() {
+            write!(f, "passed")?;
+        } else {
+            write!(f, "failed with code {}", self.code)?;
+        }
+        let text = self.text.trim();
+        if !text.is_empty() {
+            write!(f, ": {}", text.replace('\n', " / "))?;
+        }
+        Ok(())
+    }
+}
+
+impl CPU {
+    fn blargg_active(&self) -> bool {
+        (0..3).all(|i| self.peek(STATUS + 1 + i) == SIGNATURE[i as usize])
+    }
+
+    fn blargg_text(&self) -> String {
+        let bytes: Vec<u8> = (TEXT..=0x7FFF)
+            .map(|addr| self.peek(addr))
+            .take_while(|&b| b != 0)
+            .collect();
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+
+    /*
+     * Run from the reset vector until the ROM reports a result. A ROM that
+     * asks for a reset gets one, and BRK runs through its handler like on
+     * hardware. An unimplemented opcode is an error carrying whatever text
+     * had been printed, as are a debugging hook stopping the CPU and
+     * running out of `max_steps`.
+     */
+    pub fn run_blargg(&mut self, max_steps: u64) -> Result<Outcome, RunError> {
+        self.reset();
+        let mut started = false;
+        for _ in 0..max_steps {
+            match self.step() {
+                Ok(_) | Err(CpuError::Break { .. }) => {}
+                Err(CpuError::UnimplementedOpcode { pc, .. }) => {
+                    return Err(RunError::Crashed {
+                        pc,
+                        text: self.blargg_text(),
+                    })
+                }
+                Err(CpuError::Stopped { pc }) => return Err(RunError::Stopped { pc }),
+            }
+            if !self.blargg_active() {
+                continue;
+            }
+            match self.peek(STATUS) {
+                RUNNING => started = true,
+                NEEDS_RESET => {
+                    /* cleared so the same request isn't seen twice */
+                    self.poke(STATUS, RUNNING);
+                    self.reset();
+                }
+                code if started => {
+                    return Ok(Outcome {
+                        code,
+                        text: self.blargg_text(),
+                    })
+                }
+                _ => {}
+            }
+        }
+        Err(RunError::Timeout { steps: max_steps })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testrom;
+
+    /* writes the protocol by hand: running, then `code` with "ok" printed */
+    fn reporter(code: u8) -> Vec<u8> {
+        let mut program = Vec::new();
+        for (addr, value) in [
+            (0x6001u16, 0xDE),
+            (0x6002, 0xB0),
+            (0x6003, 0x61),
+            (0x6000, RUNNING),
+            (0x6004, b'o'),
+            (0x6005, b'k'),
+            (0x6006, b'\n'),
+            (0x6000, code),
+        ] {
+            program.extend_from_slice(&[0xA2, value, 0x8E, addr as u8, (addr >> 8) as u8]);
+        }
+        program.push(0x00);
+        program
+    }
+
+    #[test]
+    fn test_pass() {
+        let mut cpu = CPU::new();
+        cpu.load(reporter(0)).unwrap();
+        let outcome = cpu.run_blargg(1000).unwrap();
+        assert!(outcome.passed());
+        assert_eq!(outcome.to_string(), "passed: ok");
+    }
+
+    #[test]
+    fn test_fail() {
+        let mut cpu = CPU::new();
+        cpu.load(reporter(3)).unwrap();
+        let outcome = cpu.run_blargg(1000).unwrap();
+        assert_eq!(outcome.to_string(), "failed with code 3: ok");
+    }
+
+    #[test]
+    fn test_brk_runs_its_handler() {
+        /* BRK and its padding byte, with the IRQ vector pointing past them */
+        let mut program = vec![0x00, 0x00];
+        program.extend(reporter(0));
+        let mut cpu = CPU::new();
+        cpu.load(program).unwrap();
+        cpu.poke(0xFFFE, 0x02);
+        cpu.poke(0xFFFF, 0x80);
+        assert!(cpu.run_blargg(1000).unwrap().passed());
+    }
+
+    #[test]
+    fn test_no_result() {
+        /* JAM */
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xE8, 0x02]).unwrap();
+        assert_eq!(
+            cpu.run_blargg(1000),
+            Err(RunError::Crashed {
+                pc: 0x8001,
+                text: String::new()
+            })
+        );
+
+        /* LDA of RAM nothing wrote, with strict mode on */
+        let mut cpu = CPU::new();
+        cpu.load(vec![0xE8, 0xA5, 0x10]).unwrap();
+        cpu.enable_uninit_tracking(true);
+        assert_eq!(cpu.run_blargg(1000), Err(RunError::Stopped { pc: 0x8001 }));
+
+        /* JMP to itself */
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x4C, 0x00, 0x80]).unwrap();
+        assert_eq!(
+            cpu.run_blargg(100).unwrap_err().to_string(),
+            "no result after 100 instructions"
+        );
+    }
+
+    /* every .nes file in the directory named by `var`, skipped if it isn't set */
+    fn run_suite(var: &str) {
+        let Ok(dir) = std::env::var(var) else {
+            return;
+        };
+        let results = testrom::run_dir(dir).unwrap();
+        print!("{}", testrom::table(&results));
+        let failed = results
+            .iter()
+            .filter(|(_, status)| !status.passed())
+            .count();
+        assert_eq!(failed, 0, "{} of {} ROMs failed", failed, results.len());
+    }
+
+    /* e.g. instr_test-v5/rom_singles */
+    #[test]
+    fn test_blargg_roms() {
+        run_suite("NES_BLARGG_DIR");
+    }
+
+    /*
+     * Suites for hardware that isn't there yet, behind features so they
+     * stay out of a plain `cargo test`: ppu_vbl_nmi and sprite_hit_tests,
+     * apu_test, and cpu_timing_test.
+     */
+    #[cfg(feature = "ppu-tests")]
+    #[test]
+    fn test_blargg_ppu_roms() {
+        run_suite("NES_BLARGG_PPU_DIR");
+    }
+
+    #[cfg(feature = "apu-tests")]
+    #[test]
+    fn test_blargg_apu_roms() {
+        run_suite("NES_BLARGG_APU_DIR");
+    }
+
+    #[cfg(feature = "timing-tests")]
+    #[test]
+    fn test_blargg_timing_roms() {
+        run_suite("NES_BLARGG_TIMING_DIR");
+    }
+}
@@ -0,0 +1,382 @@
+/*
+ * The CPU's view of the address space. Everything it reads or writes goes
+ * through a Bus, which routes it the way the console does:
+ *
+ *   $0000-$1FFF  2KB of internal RAM, mirrored every $0800
+ *   $2000-$3FFF  the 8 PPU registers, mirrored every 8 bytes
+ *   $4000-$4017  APU, OAM DMA and the controller ports
+ *   $4018-$5FFF  unmapped (APU test mode, cartridge expansion)
+ *   $6000-$7FFF  8KB of PRG RAM on the cartridge
+ *   $8000-$FFFF  PRG ROM, mirrored if it's smaller than 32KB
+ *
+ * Cartridge space is whatever Rom is plugged in. Only NROM is understood,
+ * the mappers that bank switch come later.
+ *
+ * Neither the PPU nor the APU exists yet. PPU registers read as 0, and
+ * writes to them are kept so the routing can be checked. ROM ignores writes
+ * until there are mappers to bank switch on them.
+ *
+ * Nothing drives the data bus for a read of unmapped space, or of the APU
+ * and controller ports while they're missing, so it still holds the last
+ * value read or written: open bus. For an instruction's operand that's
+ * usually the high byte of its address, LDA $5000 loads $50. The PPU has a
+ * separate latch of its own that decays over time, that comes with it.
+ */
+
+use crate::rom::Rom;
+
+pub trait Mem {
+    fn mem_read(&mut self, addr: u16) -> u8;
+
+    fn mem_write(&mut self, addr: u16, data: u8);
+
+    /* little endian, wrapping from $FFFF to $0000 */
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
+        let lo = self.mem_read(pos) as u16;
+        let hi = self.mem_read(pos.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    fn mem_write_u16(&mut self, pos: u16, data: u16) {
+        self.mem_write(pos, data as u8);
+        self.mem_write(pos.wrapping_add(1), (data >> 8) as u8);
+    }
+}
+
+const RAM_SIZE: usize = 0x0800;
+const PRG_RAM_START: u16 = 0x6000;
+const PRG_RAM_SIZE: usize = 0x2000;
+const PRG_ROM_START: u16 = 0x8000;
+
+#[derive(Clone)]
+pub struct Bus {
+    ram: [u8; RAM_SIZE],
+    prg_ram: [u8; PRG_RAM_SIZE],
+    cartridge: Option<Rom>,
+    /* the last value on the data bus */
+    pub(crate) open_bus: u8,
+    /* the last value written to each PPU register */
+    ppu_registers: [u8; 8],
+    /* the whole 64KB as plain RAM instead of the memory map, see flat() */
+    pub(crate) flat: Option<Vec<u8>>,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Bus {
+            ram: [0; RAM_SIZE],
+            prg_ram: [0; PRG_RAM_SIZE],
+            cartridge: None,
+            open_bus: 0,
+            ppu_registers: [0; 8],
+            flat: None,
+        }
+    }
+
+    /*
+     * Every address is plain read/write memory, the machine CPU test suites
+     * like SingleStepTests assume. ram() and prg_ram() are still the same
+     * ranges of it, so save states keep working.
+     */
+    pub fn flat() -> Self {
+        Bus {
+            flat: Some(vec![0; 0x10000]),
+            ..Bus::new()
+        }
+    }
+
+    /* replaces the cartridge, refusing ones that need a mapper we don't have */
+    pub fn insert_cartridge(&mut self, rom: Rom) -> Result<(), String> {
+        if rom.mapper != 0 {
+            return Err(format!("mapper {} isn't supported", rom.mapper));
+        }
+        match rom.prg.len() {
+            0x4000 | 0x8000 => self.map(rom),
+            len => return Err(format!("NROM can't hold {} bytes of PRG ROM", len)),
+        }
+        Ok(())
+    }
+
+    /* a bare cartridge with `prg` at $8000, repeating it to fill the 32KB */
+    pub fn load_prg_rom(&mut self, prg: Vec<u8>) {
+        self.map(Rom::from_prg(prg));
+    }
+
+    fn map(&mut self, rom: Rom) {
+        if let Some(memory) = &mut self.flat {
+            for (i, byte) in memory[PRG_ROM_START as usize..].iter_mut().enumerate() {
+                *byte = rom.prg[i % rom.prg.len()];
+            }
+        }
+        self.cartridge = Some(rom);
+    }
+
+    pub fn cartridge(&self) -> Option<&Rom> {
+        self.cartridge.as_ref()
+    }
+
+    pub fn prg_rom(&self) -> &[u8] {
+        self.cartridge.as_ref().map_or(&[], |rom| &rom.prg)
+    }
+
+    /* the console's 2KB of work RAM */
+    pub fn ram(&self) -> &[u8] {
+        match &self.flat {
+            Some(memory) => &memory[..RAM_SIZE],
+            None => &self.ram,
+        }
+    }
+
+    pub(crate) fn ram_mut(&mut self) -> &mut [u8] {
+        match &mut self.flat {
+            Some(memory) => &mut memory[..RAM_SIZE],
+            None => &mut self.ram,
+        }
+    }
+
+    pub fn prg_ram(&self) -> &[u8] {
+        match &self.flat {
+            Some(memory) => &memory[PRG_RAM_START as usize..PRG_ROM_START as usize],
+            None => &self.prg_ram,
+        }
+    }
+
+    pub(crate) fn prg_ram_mut(&mut self) -> &mut [u8] {
+        match &mut self.flat {
+            Some(memory) => &mut memory[PRG_RAM_START as usize..PRG_ROM_START as usize],
+            None => &mut self.prg_ram,
+        }
+    }
+
+    pub fn ppu_registers(&self) -> &[u8; 8] {
+        &self.ppu_registers
+    }
+
+    fn prg_rom_index(&self, addr: u16) -> Option<usize> {
+        match self.prg_rom().len() {
+            0 => None,
+            len => Some((addr - PRG_ROM_START) as usize % len),
+        }
+    }
+
+    pub fn open_bus(&self) -> u8 {
+        self.open_bus
+    }
+
+    /* what a read would return, without any of its side effects */
+    pub fn peek(&self, addr: u16) -> u8 {
+        if let Some(memory) = &self.flat {
+            return memory[addr as usize];
+        }
+        match addr {
+            0x0000..=0x1FFF => self.ram[addr as usize % RAM_SIZE],
+            /* no PPU yet */
+            0x2000..=0x3FFF => 0,
+            /* nor APU or controllers */
+            0x4000..=0x4017 => self.open_bus,
+            0x4018..=0x5FFF => self.open_bus,
+            0x6000..=0x7FFF => self.prg_ram[(addr - PRG_RAM_START) as usize],
+            0x8000..=0xFFFF => self
+                .prg_rom_index(addr)
+                .map_or(self.open_bus, |i| self.prg_rom()[i]),
+        }
+    }
+
+    /*
+     * A write that lands even where the CPU's wouldn't, in ROM, for
+     * debuggers and tests. Registers are still left alone.
+     */
+    pub fn poke(&mut self, addr: u16, data: u8) {
+        match addr {
+            _ if self.flat.is_some() => self.write(addr, data),
+            0x2000..=0x5FFF => {}
+            0x8000..=0xFFFF => {
+                if let (Some(i), Some(rom)) = (self.prg_rom_index(addr), &mut self.cartridge) {
+                    rom.prg[i] = data;
+                }
+            }
+            _ => self.write(addr, data),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if let Some(memory) = &mut self.flat {
+            memory[addr as usize] = data;
+            return;
+        }
+        match addr {
+            0x0000..=0x1FFF => self.ram[addr as usize % RAM_SIZE] = data,
+            0x2000..=0x3FFF => self.ppu_registers[ppu_register(addr)] = data,
+            0x4000..=0x4017 => {}
+            0x4018..=0x5FFF => {}
+            0x6000..=0x7FFF => self.prg_ram[(addr - PRG_RAM_START) as usize] = data,
+            /* no mapper registers yet */
+            0x8000..=0xFFFF => {}
+        }
+    }
+}
+
+/* the PPU only decodes the low 3 bits, so $2008-$3FFF repeat $2000-$2007 */
+fn ppu_register(addr: u16) -> usize {
+    (addr & 0x0007) as usize
+}
+
+impl Mem for Bus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.open_bus = self.peek(addr);
+        self.open_bus
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.open_bus = data;
+        self.write(addr, data);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::Mirroring;
+
+    #[test]
+    fn test_u16_access() {
+        let mut bus = Bus::new();
+        bus.mem_write_u16(0x0200, 0x1234);
+        assert_eq!((bus.peek(0x0200), bus.peek(0x0201)), (0x34, 0x12));
+        assert_eq!(bus.mem_read_u16(0x0200), 0x1234);
+
+        /* the high byte of a read at $FFFF comes from $0000 */
+        bus.load_prg_rom(vec![0xCD; 0x4000]);
+        bus.poke(0x0000, 0xAB);
+        assert_eq!(bus.mem_read_u16(0xFFFF), 0xABCD);
+    }
+
+    #[test]
+    fn test_ram_mirroring() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x0123, 0x42);
+        for addr in [0x0123, 0x0923, 0x1123, 0x1923] {
+            assert_eq!(bus.mem_read(addr), 0x42, "${:04X}", addr);
+        }
+        bus.mem_write(0x1FFF, 0x99);
+        assert_eq!(bus.ram()[0x07FF], 0x99);
+    }
+
+    #[test]
+    fn test_cartridge_space() {
+        let mut bus = Bus::new();
+        assert_eq!(bus.mem_read(0x8000), 0);
+
+        /* a 16KB ROM shows up twice */
+        let mut prg = vec![0; 0x4000];
+        prg[0x0010] = 0xEA;
+        bus.load_prg_rom(prg);
+        assert_eq!(bus.mem_read(0x8010), 0xEA);
+        assert_eq!(bus.mem_read(0xC010), 0xEA);
+        bus.mem_write(0x8010, 0x00);
+        assert_eq!(bus.mem_read(0x8010), 0xEA);
+        bus.poke(0xC010, 0x00);
+        assert_eq!(bus.mem_read(0x8010), 0x00);
+
+        bus.mem_write(0x6000, 0x80);
+        assert_eq!(bus.mem_read(0x6000), 0x80);
+        assert_eq!(bus.prg_ram()[0], 0x80);
+    }
+
+    #[test]
+    fn test_insert_cartridge() {
+        let mut bus = Bus::new();
+        let mut rom = Rom::from_prg(vec![0xEA; 0x8000]);
+        rom.mirroring = Mirroring::Vertical;
+        bus.insert_cartridge(rom).unwrap();
+        assert_eq!(bus.mem_read(0xFFFF), 0xEA);
+        assert_eq!(bus.cartridge().unwrap().mirroring, Mirroring::Vertical);
+
+        let mut mmc1 = Rom::from_prg(vec![0; 0x8000]);
+        mmc1.mapper = 1;
+        assert_eq!(
+            bus.insert_cartridge(mmc1),
+            Err("mapper 1 isn't supported".to_string())
+        );
+        assert_eq!(
+            bus.insert_cartridge(Rom::from_prg(vec![0; 0x1000])),
+            Err("NROM can't hold 4096 bytes of PRG ROM".to_string())
+        );
+        /* the old one stays in */
+        assert_eq!(bus.cartridge().unwrap().mapper, 0);
+    }
+
+    #[test]
+    fn test_io_and_unmapped() {
+        let mut bus = Bus::new();
+        for addr in [0x4016, 0x4018, 0x5FFF] {
+            bus.mem_write(addr, 0x42);
+            bus.poke(addr, 0x42);
+            /* what's read back is open bus, not the write */
+            bus.mem_read(0x0000);
+            assert_eq!(bus.mem_read(addr), 0, "${:04X}", addr);
+        }
+        assert!(bus.ram().iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_ppu_register_mirroring() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x3456, 0x21);
+        assert_eq!(bus.ppu_registers()[6], 0x21);
+        bus.mem_write(0x2006, 0x08);
+        assert_eq!(bus.ppu_registers()[6], 0x08);
+
+        for addr in (0x2000..=0x3FFF).step_by(0x0123) {
+            bus.mem_write(addr, addr as u8);
+            assert_eq!(
+                bus.ppu_registers()[addr as usize % 8],
+                addr as u8,
+                "${:04X}",
+                addr
+            );
+        }
+        assert_eq!(bus.mem_read(0x2002), 0);
+        let before = *bus.ppu_registers();
+        bus.poke(0x2006, 0x99);
+        assert_eq!(*bus.ppu_registers(), before);
+        assert!(bus.ram().iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut bus = Bus::new();
+        bus.poke(0x0001, 0x42);
+        assert_eq!(bus.peek(0x5000), 0x00);
+        assert_eq!(bus.mem_read(0x0001), 0x42);
+        assert_eq!(bus.mem_read(0x5000), 0x42);
+        assert_eq!(bus.mem_read(0x4016), 0x42);
+        /* no cartridge */
+        assert_eq!(bus.mem_read(0x8000), 0x42);
+
+        bus.mem_write(0x0002, 0x17);
+        assert_eq!(bus.peek(0x4018), 0x17);
+        /* peeks and pokes don't drive the bus */
+        bus.peek(0x0001);
+        bus.poke(0x0003, 0x99);
+        assert_eq!(bus.open_bus(), 0x17);
+    }
+
+    #[test]
+    fn test_flat() {
+        let mut bus = Bus::flat();
+        for addr in [0x0923, 0x2000, 0x4016, 0x8000] {
+            bus.mem_write(addr, 0x42);
+            assert_eq!(bus.mem_read(addr), 0x42, "${:04X}", addr);
+        }
+        assert_eq!(bus.mem_read(0x0123), 0);
+        bus.mem_write(0x6001, 0x55);
+        assert_eq!(bus.prg_ram()[1], 0x55);
+    }
+}
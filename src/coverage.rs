@@ -0,0 +1,209 @@
+use std::fmt::Write;
+
+use crate::CPU;
+
+const BYTES_PER_ROW: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteUse {
+    Unreached,
+    Opcode,
+    Operand,
+}
+
+/*
+ * Executed-byte coverage over a region of memory, usually the PRG ROM. A byte
+ * counts as executed when it's fetched as part of the instruction stream:
+ * the opcode itself plus the operand bytes that follow it.
+ */
+pub struct Coverage {
+    start: u16,
+    bytes: Vec<ByteUse>,
+    /* the instruction being run and its length */
+    current: Option<(u16, u8)>,
+}
+
+impl Coverage {
+    pub fn new(start: u16, len: usize) -> Self {
+        Coverage {
+            start,
+            bytes: vec![ByteUse::Unreached; len],
+            current: None,
+        }
+    }
+
+    pub fn get(&self, addr: u16) -> Option<ByteUse> {
+        let idx = addr.checked_sub(self.start)? as usize;
+        self.bytes.get(idx).copied()
+    }
+
+    pub fn executed(&self) -> usize {
+        self.bytes
+            .iter()
+            .filter(|b| **b != ByteUse::Unreached)
+            .count()
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn percentage(&self) -> f64 {
+        if self.bytes.is_empty() {
+            return 0.0;
+        }
+        self.executed() as f64 * 100.0 / self.bytes.len() as f64
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "executed {} of {} bytes ({:.1}%)",
+            self.executed(),
+            self.len(),
+            self.percentage()
+        )
+    }
+
+    /*
+     * One row per 32 bytes: 'O' marks an opcode, 'o' an operand, '.' a byte
+     * that never ran.
+     */
+    pub fn annotated_map(&self) -> String {
+        let mut map = String::new();
+        for (row, chunk) in self.bytes.chunks(BYTES_PER_ROW).enumerate() {
+            let addr = self.start as usize + row * BYTES_PER_ROW;
+            write!(map, "${:04X}  ", addr).unwrap();
+            for byte in chunk {
+                map.push(match byte {
+                    ByteUse::Unreached => '.',
+                    ByteUse::Opcode => 'O',
+                    ByteUse::Operand => 'o',
+                });
+            }
+            map.push('\n');
+        }
+        map
+    }
+
+    pub(crate) fn begin(&mut self, pc: u16, len: u8) {
+        self.current = Some((pc, len));
+    }
+
+    pub(crate) fn end(&mut self) {
+        self.current = None;
+    }
+
+    pub(crate) fn record_read(&mut self, addr: u16) {
+        let Some((pc, len)) = self.current else {
+            return;
+        };
+        /* anything past the opcode table's length is a data read */
+        let offset = addr.wrapping_sub(pc);
+        if offset >= len as u16 {
+            return;
+        }
+        let Some(idx) = addr.checked_sub(self.start).map(|i| i as usize) else {
+            return;
+        };
+        if let Some(byte) = self.bytes.get_mut(idx) {
+            if offset == 0 {
+                *byte = ByteUse::Opcode;
+            } else if *byte == ByteUse::Unreached {
+                *byte = ByteUse::Operand;
+            }
+        }
+    }
+}
+
+impl CPU {
+    pub fn enable_coverage(&mut self, start: u16, len: usize) {
+        self.coverage = Some(Coverage::new(start, len));
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_straight_line_coverage() {
+        let mut cpu = CPU::new();
+        let program = vec![0xa9, 0x05, 0xaa, 0xe8, 0x00];
+        cpu.enable_coverage(0x8000, program.len());
+        cpu.init(program).unwrap();
+        cpu.run();
+
+        let coverage = cpu.coverage().unwrap();
+        assert_eq!(coverage.executed(), 5);
+        assert_eq!(coverage.get(0x8000), Some(ByteUse::Opcode));
+        assert_eq!(coverage.get(0x8001), Some(ByteUse::Operand));
+        assert_eq!(coverage.get(0x8002), Some(ByteUse::Opcode));
+    }
+
+    #[test]
+    fn test_jumped_over_bytes_are_unreached() {
+        let mut cpu = CPU::new();
+        let program = vec![0x4c, 0x05, 0x80, 0xe8, 0xe8, 0x00];
+        cpu.enable_coverage(0x8000, program.len());
+        cpu.init(program).unwrap();
+        cpu.run();
+
+        let coverage = cpu.coverage().unwrap();
+        assert_eq!(coverage.executed(), 4);
+        assert_eq!(coverage.get(0x8003), Some(ByteUse::Unreached));
+        assert_eq!(coverage.get(0x8004), Some(ByteUse::Unreached));
+        assert_eq!(coverage.summary(), "executed 4 of 6 bytes (66.7%)");
+    }
+
+    #[test]
+    fn test_data_reads_are_not_coverage() {
+        let mut cpu = CPU::new();
+        let program = vec![0xad, 0x05, 0x80, 0x00, 0x00, 0x42];
+        cpu.enable_coverage(0x8000, program.len());
+        cpu.init(program).unwrap();
+        cpu.run();
+
+        assert_eq!(cpu.register_a.0, 0x42);
+        assert_eq!(
+            cpu.coverage().unwrap().get(0x8005),
+            Some(ByteUse::Unreached)
+        );
+    }
+
+    #[test]
+    fn test_data_reads_next_to_the_opcode_are_not_operands() {
+        /* RTS at $01F0 pulls its return address from $01F0-$01F1 */
+        let mut cpu = CPU::new();
+        cpu.init(vec![0x4c, 0xf0, 0x01]).unwrap();
+        cpu.poke(0x01f0, 0x60);
+        cpu.poke(0x01f1, 0x90);
+        cpu.enable_coverage(0x01f0, 2);
+        cpu.step().unwrap();
+        cpu.stack_pointer = 0xef;
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.program_counter, 0x9061);
+        let coverage = cpu.coverage().unwrap();
+        assert_eq!(coverage.get(0x01f0), Some(ByteUse::Opcode));
+        assert_eq!(coverage.get(0x01f1), Some(ByteUse::Unreached));
+    }
+
+    #[test]
+    fn test_annotated_map() {
+        let mut cpu = CPU::new();
+        let program = vec![0xa9, 0x05, 0x00, 0xe8];
+        cpu.enable_coverage(0x8000, program.len());
+        cpu.init(program).unwrap();
+        cpu.run();
+
+        assert_eq!(cpu.coverage().unwrap().annotated_map(), "$8000  OoO.\n");
+    }
+}
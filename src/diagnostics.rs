@@ -0,0 +1,272 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::CPU;
+
+const PC_HISTORY_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Problem {
+    StackOverflow,
+    StackUnderflow,
+    RtsWithEmptyCallStack,
+    BrkInZeroedRam,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub problem: Problem,
+    pub pc: u16,
+    pub stack_pointer: u8,
+    /* oldest first, ends with the offending instruction */
+    pub pc_history: Vec<u16>,
+}
+
+/*
+ * Optional runtime checks for the usual ways a program goes off the rails:
+ * the stack pointer wrapping, returning more often than we called, and
+ * running into zeroed RAM where every byte decodes as BRK.
+ */
+pub struct Diagnostics {
+    pc_history: VecDeque<u16>,
+    call_depth: usize,
+    reports: Vec<Diagnostic>,
+}
+
+impl Default for Diagnostics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Diagnostics {
+            pc_history: VecDeque::with_capacity(PC_HISTORY_LEN),
+            call_depth: 0,
+            reports: Vec::new(),
+        }
+    }
+
+    pub fn reports(&self) -> &[Diagnostic] {
+        &self.reports
+    }
+
+    pub fn call_depth(&self) -> usize {
+        self.call_depth
+    }
+
+    pub(crate) fn begin(&mut self, pc: u16) {
+        if self.pc_history.len() == PC_HISTORY_LEN {
+            self.pc_history.pop_front();
+        }
+        self.pc_history.push_back(pc);
+    }
+
+    fn report(&mut self, problem: Problem, stack_pointer: u8) {
+        self.reports.push(Diagnostic {
+            problem,
+            pc: self.pc_history.back().copied().unwrap_or(0),
+            stack_pointer,
+            pc_history: self.pc_history.iter().copied().collect(),
+        });
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            Problem::StackOverflow => "stack overflow",
+            Problem::StackUnderflow => "stack underflow",
+            Problem::RtsWithEmptyCallStack => "RTS with no matching JSR",
+            Problem::BrkInZeroedRam => "BRK while executing zeroed RAM",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at ${:04X} (SP=${:02X}), recent PCs:",
+            self.problem, self.pc, self.stack_pointer
+        )?;
+        for pc in &self.pc_history {
+            write!(f, " ${:04X}", pc)?;
+        }
+        Ok(())
+    }
+}
+
+impl CPU {
+    pub fn enable_diagnostics(&mut self) {
+        self.diagnostics = Some(Diagnostics::new());
+    }
+
+    pub fn diagnostics(&self) -> Option<&Diagnostics> {
+        self.diagnostics.as_ref()
+    }
+
+    pub(crate) fn diagnose_push(&mut self) {
+        let sp = self.stack_pointer;
+        if let Some(diagnostics) = &mut self.diagnostics {
+            if sp == 0x00 {
+                diagnostics.report(Problem::StackOverflow, sp);
+            }
+        }
+    }
+
+    pub(crate) fn diagnose_pop(&mut self) {
+        let sp = self.stack_pointer;
+        if let Some(diagnostics) = &mut self.diagnostics {
+            if sp == 0xFF {
+                diagnostics.report(Problem::StackUnderflow, sp);
+            }
+        }
+    }
+
+    pub(crate) fn diagnose_jsr(&mut self) {
+        if let Some(diagnostics) = &mut self.diagnostics {
+            diagnostics.call_depth += 1;
+        }
+    }
+
+    pub(crate) fn diagnose_rts(&mut self) {
+        let sp = self.stack_pointer;
+        if let Some(diagnostics) = &mut self.diagnostics {
+            if diagnostics.call_depth == 0 {
+                diagnostics.report(Problem::RtsWithEmptyCallStack, sp);
+            } else {
+                diagnostics.call_depth -= 1;
+            }
+        }
+    }
+
+    /* called with program_counter already past the BRK opcode */
+    pub(crate) fn diagnose_brk(&mut self) {
+        if self.diagnostics.is_none() {
+            return;
+        }
+        let pc = self.program_counter.wrapping_sub(1);
+        /* internal RAM and its mirrors, where a zero byte means nothing was ever put there */
+        let in_ram = pc < 0x2000;
+        let padding = self.peek(self.program_counter);
+        let sp = self.stack_pointer;
+        if let Some(diagnostics) = &mut self.diagnostics {
+            if in_ram && padding == 0x00 {
+                diagnostics.report(Problem::BrkInZeroedRam, sp);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_no_reports_for_clean_program() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xa9, 0x05, 0xaa, 0xe8, 0x00]).unwrap();
+        cpu.enable_diagnostics();
+        cpu.run();
+        assert!(cpu.diagnostics().unwrap().reports().is_empty());
+    }
+
+    #[test]
+    fn test_brk_in_zeroed_ram() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0x4c, 0x00, 0x00]).unwrap();
+        cpu.enable_diagnostics();
+        cpu.run();
+
+        let reports = cpu.diagnostics().unwrap().reports();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].problem, Problem::BrkInZeroedRam);
+        assert_eq!(reports[0].pc, 0x0000);
+        assert_eq!(reports[0].pc_history, vec![0x8000, 0x0000]);
+    }
+
+    #[test]
+    fn test_brk_check_does_not_touch_the_bus() {
+        let run = |diagnostics: bool| {
+            let mut cpu = CPU::new();
+            cpu.init(vec![0x4c, 0x00, 0x00]).unwrap();
+            cpu.enable_uninit_tracking(false);
+            if diagnostics {
+                cpu.enable_diagnostics();
+            }
+            cpu.run();
+            (
+                cpu.uninit_tracker().unwrap().reads().len(),
+                cpu.bus.open_bus(),
+            )
+        };
+        assert_eq!(run(true), run(false));
+    }
+
+    #[test]
+    fn test_rts_with_empty_call_stack() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0x60]).unwrap();
+        cpu.enable_diagnostics();
+        cpu.step().unwrap();
+
+        let reports = cpu.diagnostics().unwrap().reports();
+        assert_eq!(reports[0].problem, Problem::RtsWithEmptyCallStack);
+        assert_eq!(reports[0].pc, 0x8000);
+    }
+
+    #[test]
+    fn test_jsr_tracks_call_depth() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0x20, 0x00, 0x90]).unwrap();
+        cpu.enable_diagnostics();
+        cpu.step().unwrap();
+        assert_eq!(cpu.diagnostics().unwrap().call_depth(), 1);
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0x20, 0x00, 0x90]).unwrap();
+        cpu.enable_diagnostics();
+        cpu.stack_pointer = 0x01;
+        cpu.step().unwrap();
+
+        let reports = cpu.diagnostics().unwrap().reports();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].problem, Problem::StackOverflow);
+        assert_eq!(cpu.stack_pointer, 0xFF);
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0x20, 0x00, 0x90]).unwrap();
+        cpu.poke(0x9000, 0x60);
+        cpu.enable_diagnostics();
+        cpu.step().unwrap();
+        cpu.stack_pointer = 0xFF;
+        cpu.step().unwrap();
+
+        let reports = cpu.diagnostics().unwrap().reports();
+        assert_eq!(reports[0].problem, Problem::StackUnderflow);
+        assert_eq!(reports[0].pc, 0x9000);
+    }
+
+    #[test]
+    fn test_diagnostic_display() {
+        let diagnostic = Diagnostic {
+            problem: Problem::StackOverflow,
+            pc: 0x8003,
+            stack_pointer: 0x00,
+            pc_history: vec![0x8000, 0x8003],
+        };
+        assert_eq!(
+            diagnostic.to_string(),
+            "stack overflow at $8003 (SP=$00), recent PCs: $8000 $8003"
+        );
+    }
+}
@@ -0,0 +1,483 @@
+/*
+ * Differential tests: random instruction sequences run on the CPU and on a
+ * small reference model written separately from it, comparing registers
+ * and memory after every instruction. A divergence is shrunk to the fewest
+ * instructions that still diverge and printed ready to paste in as a
+ * regression test. No other 6502 crate can be pulled in here, so the model
+ * only covers the instructions the CPU implements and grows with it.
+ */
+
+use std::num::Wrapping;
+
+use crate::bus::Bus;
+use crate::rng::Rng;
+use crate::status::StatusFlags;
+use crate::CPU;
+
+const CASES: usize = 500;
+const MAX_LEN: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Model {
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    pc: u16,
+    memory: Vec<u8>,
+}
+
+impl Model {
+    fn read(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn read_word(&self, addr: u16) -> u16 {
+        u16::from_le_bytes([self.read(addr), self.read(addr.wrapping_add(1))])
+    }
+
+    /* pointer fetched from the zero page, wrapping within it */
+    fn read_zp_word(&self, addr: u8) -> u16 {
+        u16::from_le_bytes([
+            self.read(addr as u16),
+            self.read(addr.wrapping_add(1) as u16),
+        ])
+    }
+
+    fn set_zn(&mut self, value: u8) -> u8 {
+        self.p = (self.p & !0x82) | (value & 0x80) | ((value == 0) as u8) << 1;
+        value
+    }
+
+    /* runs one instruction, false on BRK or an opcode it doesn't know */
+    fn step(&mut self) -> bool {
+        let opcode = self.read(self.pc);
+        let b = self.read(self.pc.wrapping_add(1));
+        let w = self.read_word(self.pc.wrapping_add(1));
+        let (addr, len) = match opcode {
+            0xA9 | 0xA0 | 0xA2 | 0x69 | 0xE9 | 0x29 | 0x09 | 0x49 | 0xC9 | 0xE0 | 0xC0 | 0x80 => {
+                (self.pc.wrapping_add(1), 2)
+            }
+            0xA5 | 0x85 | 0x86 | 0x84 | 0x65 | 0xE5 | 0x25 | 0x05 | 0x45 | 0x06 | 0x46 | 0x26
+            | 0x66 | 0xC5 | 0xE4 | 0xC4 | 0xE6 | 0xC6 | 0x24 | 0x04 | 0xA4 | 0xA6 => (b as u16, 2),
+            0xB5 | 0x95 | 0x94 | 0x75 | 0xF5 | 0x35 | 0x15 | 0x55 | 0x16 | 0x56 | 0x36 | 0x76
+            | 0xD5 | 0xF6 | 0xD6 | 0x14 | 0xB4 => (b.wrapping_add(self.x) as u16, 2),
+            0x96 | 0xB6 => (b.wrapping_add(self.y) as u16, 2),
+            0xAD | 0x8E | 0x8C | 0x6D | 0xED | 0x2D | 0x0D | 0x4D | 0x0E | 0x4E | 0x2E | 0x6E
+            | 0xCD | 0xEC | 0xCC | 0xEE | 0xCE | 0x2C | 0x0C | 0xAC | 0xAE | 0x8D => (w, 3),
+            0xBD | 0x7D | 0xFD | 0x3D | 0x1D | 0x5D | 0x1E | 0x5E | 0x3E | 0x7E | 0xDD | 0xFE
+            | 0xDE | 0x1C | 0xBC | 0x9D => (w.wrapping_add(self.x as u16), 3),
+            0xB9 | 0x79 | 0xF9 | 0x39 | 0x19 | 0x59 | 0xD9 | 0xBE | 0x99 => {
+                (w.wrapping_add(self.y as u16), 3)
+            }
+            0xA1 | 0x61 | 0xE1 | 0x21 | 0x01 | 0x41 | 0xC1 | 0x81 => {
+                (self.read_zp_word(b.wrapping_add(self.x)), 2)
+            }
+            0xB1 | 0x71 | 0xF1 | 0x31 | 0x11 | 0x51 | 0xD1 | 0x91 => {
+                (self.read_zp_word(b).wrapping_add(self.y as u16), 2)
+            }
+            0xAA | 0x8A | 0xE8 | 0x00 | 0x0A | 0x4A | 0x2A | 0x6A | 0xC8 | 0xCA | 0x88 | 0x18
+            | 0x38 | 0x58 | 0x78 | 0xB8 | 0xD8 | 0xF8 | 0xA8 | 0x98 | 0xEA | 0x1A => (0, 1),
+            0x10 | 0x30 | 0x50 | 0x70 | 0x90 | 0xB0 | 0xD0 | 0xF0 => (0, 2),
+            /* a shrunk case can branch into the middle of an instruction */
+            _ => return false,
+        };
+        match opcode {
+            0x00 => return false,
+            0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => {
+                self.a = self.set_zn(self.read(addr))
+            }
+            0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 | 0xE9 | 0xE5 | 0xF5 | 0xED
+            | 0xFD | 0xF9 | 0xE1 | 0xF1 => {
+                /* SBC is ADC of the inverted operand */
+                let value = if opcode & 0xE0 == 0xE0 {
+                    !self.read(addr)
+                } else {
+                    self.read(addr)
+                };
+                let sum = self.a as u16 + value as u16 + (self.p & 0x01) as u16;
+                let result = sum as u8;
+                let overflow = !(self.a ^ value) & (self.a ^ result) & 0x80 != 0;
+                self.p = (self.p & !0x41) | (sum > 0xFF) as u8 | (overflow as u8) << 6;
+                self.a = self.set_zn(result);
+            }
+            0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => {
+                self.a = self.set_zn(self.a & self.read(addr))
+            }
+            0x09 | 0x05 | 0x15 | 0x0D | 0x1D | 0x19 | 0x01 | 0x11 => {
+                self.a = self.set_zn(self.a | self.read(addr))
+            }
+            0x49 | 0x45 | 0x55 | 0x4D | 0x5D | 0x59 | 0x41 | 0x51 => {
+                self.a = self.set_zn(self.a ^ self.read(addr))
+            }
+            0x0A | 0x06 | 0x16 | 0x0E | 0x1E | 0x4A | 0x46 | 0x56 | 0x4E | 0x5E | 0x2A | 0x26
+            | 0x36 | 0x2E | 0x3E | 0x6A | 0x66 | 0x76 | 0x6E | 0x7E => {
+                let accumulator = opcode & 0x0F == 0x0A;
+                let value = if accumulator { self.a } else { self.read(addr) };
+                /* ROL and ROR shift the old carry in */
+                let carry_in = if opcode & 0x20 != 0 { self.p & 0x01 } else { 0 };
+                let (result, carry) = if opcode < 0x40 {
+                    (value << 1 | carry_in, value >> 7)
+                } else {
+                    (value >> 1 | carry_in << 7, value & 0x01)
+                };
+                self.p = (self.p & !0x01) | carry;
+                let result = self.set_zn(result);
+                if accumulator {
+                    self.a = result;
+                } else {
+                    self.memory[addr as usize] = result;
+                }
+            }
+            0x10 | 0x30 | 0x50 | 0x70 | 0x90 | 0xB0 | 0xD0 | 0xF0 => {
+                /* bits 7-6 pick N, V, C or Z, bit 5 whether it has to be set */
+                let flag = [0x80, 0x40, 0x01, 0x02][opcode as usize >> 6];
+                if (self.p & flag != 0) == (opcode & 0x20 != 0) {
+                    self.pc = self.pc.wrapping_add(2).wrapping_add(b as i8 as u16);
+                    return true;
+                }
+            }
+            0xC9 | 0xC5 | 0xD5 | 0xCD | 0xDD | 0xD9 | 0xC1 | 0xD1 | 0xE0 | 0xE4 | 0xEC | 0xC0
+            | 0xC4 | 0xCC => {
+                let register = match opcode {
+                    0xE0 | 0xE4 | 0xEC => self.x,
+                    0xC0 | 0xC4 | 0xCC => self.y,
+                    _ => self.a,
+                };
+                let value = self.read(addr);
+                self.p = (self.p & !0x01) | (register >= value) as u8;
+                self.set_zn(register.wrapping_sub(value));
+            }
+            0x24 | 0x2C => {
+                let value = self.read(addr);
+                self.p = (self.p & !0xC2) | (value & 0xC0) | ((self.a & value == 0) as u8) << 1;
+            }
+            0xEA | 0x1A | 0x80 | 0x04 | 0x14 | 0x0C | 0x1C => {}
+            0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => self.x = self.set_zn(self.read(addr)),
+            0xA0 | 0xA4 | 0xB4 | 0xAC | 0xBC => self.y = self.set_zn(self.read(addr)),
+            0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 => self.memory[addr as usize] = self.a,
+            0x86 | 0x96 | 0x8E => self.memory[addr as usize] = self.x,
+            0x84 | 0x94 | 0x8C => self.memory[addr as usize] = self.y,
+            0xAA => self.x = self.set_zn(self.a),
+            0x8A => self.a = self.set_zn(self.x),
+            0xA8 => self.y = self.set_zn(self.a),
+            0x98 => self.a = self.set_zn(self.y),
+            /* bits 7-6 pick C, I, V or D, bit 5 set or clear (CLV is B8, there's no SEV) */
+            0x18 | 0x38 | 0x58 | 0x78 | 0xB8 | 0xD8 | 0xF8 => {
+                let flag = [0x01, 0x04, 0x40, 0x08][opcode as usize >> 6];
+                if opcode & 0x20 != 0 && opcode != 0xB8 {
+                    self.p |= flag;
+                } else {
+                    self.p &= !flag;
+                }
+            }
+            0xE8 => self.x = self.set_zn(self.x.wrapping_add(1)),
+            0xC8 => self.y = self.set_zn(self.y.wrapping_add(1)),
+            0xCA => self.x = self.set_zn(self.x.wrapping_sub(1)),
+            0x88 => self.y = self.set_zn(self.y.wrapping_sub(1)),
+            0xE6 | 0xF6 | 0xEE | 0xFE => {
+                let result = self.set_zn(self.read(addr).wrapping_add(1));
+                self.memory[addr as usize] = result;
+            }
+            0xC6 | 0xD6 | 0xCE | 0xDE => {
+                let result = self.set_zn(self.read(addr).wrapping_sub(1));
+                self.memory[addr as usize] = result;
+            }
+            _ => unreachable!(),
+        }
+        self.pc = self.pc.wrapping_add(len);
+        true
+    }
+}
+
+/* (opcode, operand length) for everything the model covers */
+const INSTRUCTIONS: [(u8, usize); 145] = [
+    (0xA9, 1),
+    (0xA5, 1),
+    (0xB5, 1),
+    (0xAD, 2),
+    (0xBD, 2),
+    (0xB9, 2),
+    (0xA1, 1),
+    (0xB1, 1),
+    (0xA0, 1),
+    (0xA2, 1),
+    (0x85, 1),
+    (0x95, 1),
+    (0x8E, 2),
+    (0x86, 1),
+    (0x96, 1),
+    (0x8C, 2),
+    (0x84, 1),
+    (0x94, 1),
+    (0xAA, 0),
+    (0x8A, 0),
+    (0xE8, 0),
+    (0x69, 1),
+    (0x65, 1),
+    (0x75, 1),
+    (0x6D, 2),
+    (0x7D, 2),
+    (0x79, 2),
+    (0x61, 1),
+    (0x71, 1),
+    (0xE9, 1),
+    (0xE5, 1),
+    (0xF5, 1),
+    (0xED, 2),
+    (0xFD, 2),
+    (0xF9, 2),
+    (0xE1, 1),
+    (0xF1, 1),
+    (0x29, 1),
+    (0x25, 1),
+    (0x35, 1),
+    (0x2D, 2),
+    (0x3D, 2),
+    (0x39, 2),
+    (0x21, 1),
+    (0x31, 1),
+    (0x09, 1),
+    (0x05, 1),
+    (0x15, 1),
+    (0x0D, 2),
+    (0x1D, 2),
+    (0x19, 2),
+    (0x01, 1),
+    (0x11, 1),
+    (0x49, 1),
+    (0x45, 1),
+    (0x55, 1),
+    (0x4D, 2),
+    (0x5D, 2),
+    (0x59, 2),
+    (0x41, 1),
+    (0x51, 1),
+    (0x0A, 0),
+    (0x06, 1),
+    (0x16, 1),
+    (0x0E, 2),
+    (0x1E, 2),
+    (0x4A, 0),
+    (0x46, 1),
+    (0x56, 1),
+    (0x4E, 2),
+    (0x5E, 2),
+    (0x2A, 0),
+    (0x26, 1),
+    (0x36, 1),
+    (0x2E, 2),
+    (0x3E, 2),
+    (0x6A, 0),
+    (0x66, 1),
+    (0x76, 1),
+    (0x6E, 2),
+    (0x7E, 2),
+    (0x10, 1),
+    (0x30, 1),
+    (0x50, 1),
+    (0x70, 1),
+    (0x90, 1),
+    (0xB0, 1),
+    (0xD0, 1),
+    (0xF0, 1),
+    (0xC9, 1),
+    (0xC5, 1),
+    (0xD5, 1),
+    (0xCD, 2),
+    (0xDD, 2),
+    (0xD9, 2),
+    (0xC1, 1),
+    (0xD1, 1),
+    (0xE0, 1),
+    (0xE4, 1),
+    (0xEC, 2),
+    (0xC0, 1),
+    (0xC4, 1),
+    (0xCC, 2),
+    (0xE6, 1),
+    (0xF6, 1),
+    (0xEE, 2),
+    (0xFE, 2),
+    (0xC6, 1),
+    (0xD6, 1),
+    (0xCE, 2),
+    (0xDE, 2),
+    (0xC8, 0),
+    (0xCA, 0),
+    (0x88, 0),
+    (0x18, 0),
+    (0x38, 0),
+    (0x58, 0),
+    (0x78, 0),
+    (0xB8, 0),
+    (0xD8, 0),
+    (0xF8, 0),
+    (0xA8, 0),
+    (0x98, 0),
+    (0x24, 1),
+    (0x2C, 2),
+    (0xEA, 0),
+    (0x1A, 0),
+    (0x80, 1),
+    (0x04, 1),
+    (0x14, 1),
+    (0x0C, 2),
+    (0x1C, 2),
+    (0xA4, 1),
+    (0xB4, 1),
+    (0xAC, 2),
+    (0xBC, 2),
+    (0xA6, 1),
+    (0xB6, 1),
+    (0xAE, 2),
+    (0xBE, 2),
+    (0x8D, 2),
+    (0x9D, 2),
+    (0x99, 2),
+    (0x81, 1),
+    (0x91, 1),
+];
+
+#[derive(Debug, Clone)]
+struct Case {
+    registers: [u8; 4],
+    zero_page: Vec<u8>,
+    program: Vec<Vec<u8>>,
+}
+
+fn random_case(rng: &mut Rng) -> Case {
+    let len = 1 + rng.next() as usize % MAX_LEN;
+    let mut program = (0..len)
+        .map(|_| {
+            let (opcode, operands) = INSTRUCTIONS[rng.next() as usize % INSTRUCTIONS.len()];
+            let mut instruction = vec![opcode, rng.byte()];
+            if operands == 2 {
+                /* absolute addresses stay in RAM, clear of the program */
+                instruction.push(rng.byte() & 0x07);
+            }
+            instruction.truncate(1 + operands);
+            instruction
+        })
+        .collect::<Vec<Vec<u8>>>();
+    /* branches go forwards to the start of a later instruction, or the BRK */
+    for i in 0..program.len() {
+        if program[i][0] & 0x1F == 0x10 {
+            let skip = rng.next() as usize % (program.len() - i);
+            program[i][1] = program[i + 1..=i + skip]
+                .iter()
+                .map(Vec::len)
+                .sum::<usize>() as u8;
+        }
+    }
+    Case {
+        registers: [rng.byte(), rng.byte(), rng.byte(), rng.byte()],
+        zero_page: (0..0x100).map(|_| rng.byte()).collect(),
+        program,
+    }
+}
+
+/* Some(description) of the first divergence */
+fn diverges(case: &Case) -> Option<String> {
+    let mut bytes: Vec<u8> = case.program.concat();
+    bytes.push(0x00);
+
+    /* the model has no memory map, every address is RAM to it */
+    let mut cpu = CPU::new();
+    cpu.bus = Bus::flat();
+    cpu.init(bytes).unwrap();
+    let [a, x, y, p] = case.registers;
+    cpu.register_a = Wrapping(a);
+    cpu.register_x = Wrapping(x);
+    cpu.register_y = Wrapping(y);
+    cpu.status = StatusFlags::from_bits(p);
+    for (addr, &value) in case.zero_page.iter().enumerate() {
+        cpu.poke(addr as u16, value);
+    }
+
+    let mut model = Model {
+        a,
+        x,
+        y,
+        p,
+        pc: cpu.program_counter,
+        memory: cpu.bus.flat.clone().unwrap(),
+    };
+    for i in 0..case.program.len() {
+        /* BRK just ends the program, the model doesn't do interrupts */
+        if !model.step() {
+            return None;
+        }
+        cpu.step().unwrap();
+        let actual = Model {
+            a: cpu.register_a.0,
+            x: cpu.register_x.0,
+            y: cpu.register_y.0,
+            p: cpu.status.bits(),
+            pc: cpu.program_counter,
+            memory: cpu.bus.flat.clone().unwrap(),
+        };
+        if actual != model {
+            let memory = (0..actual.memory.len())
+                .find(|&addr| actual.memory[addr] != model.memory[addr])
+                .map(|addr| {
+                    format!(
+                        ", ${:04X} is {:02X} not {:02X}",
+                        addr, actual.memory[addr], model.memory[addr]
+                    )
+                })
+                .unwrap_or_default();
+            return Some(format!(
+                "after instruction {}: cpu A:{:02X} X:{:02X} Y:{:02X} P:{:02X} PC:{:04X}, \
+                 model A:{:02X} X:{:02X} Y:{:02X} P:{:02X} PC:{:04X}{}",
+                i,
+                actual.a,
+                actual.x,
+                actual.y,
+                actual.p,
+                actual.pc,
+                model.a,
+                model.x,
+                model.y,
+                model.p,
+                model.pc,
+                memory
+            ));
+        }
+    }
+    None
+}
+
+/* drop instructions one at a time while the case still diverges */
+fn shrink(mut case: Case) -> Case {
+    let mut i = 0;
+    while i < case.program.len() {
+        let mut smaller = case.clone();
+        smaller.program.remove(i);
+        if !smaller.program.is_empty() && diverges(&smaller).is_some() {
+            case = smaller;
+        } else {
+            i += 1;
+        }
+    }
+    case
+}
+
+#[test]
+fn test_matches_reference_model() {
+    let mut rng = Rng::new(0x2545_F491_4F6C_DD1D);
+    for _ in 0..CASES {
+        let case = random_case(&mut rng);
+        if diverges(&case).is_some() {
+            let case = shrink(case);
+            panic!(
+                "cpu and model diverge {}\nregisters (A, X, Y, P): {:02X?}\nprogram: {:02X?}\n\
+                 zero page: {:02X?}",
+                diverges(&case).unwrap(),
+                case.registers,
+                case.program.concat(),
+                case.zero_page
+            );
+        }
+    }
+}
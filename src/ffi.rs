@@ -0,0 +1,138 @@
+/*
+ * C ABI for embedding the emulator as a shared library, declared in
+ * include/nes.h. A handle from nes_create is a boxed CPU; every other call
+ * takes it and must not be given a null or freed one. Frames, the
+ * framebuffer and controller input need the PPU and controller ports, so
+ * for now a host steps instructions instead.
+ *
+ * The safety rules are the same for every function, so they're here rather
+ * than on each one: pointers are valid for the lengths given, and handles
+ * aren't shared between threads without a lock.
+ */
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::os::raw::c_int;
+use std::slice;
+
+use crate::rom::Rom;
+use crate::{CpuError, CPU};
+
+#[no_mangle]
+pub extern "C" fn nes_create() -> *mut CPU {
+    Box::into_raw(Box::new(CPU::new()))
+}
+
+/* the handle can't be used afterwards */
+#[no_mangle]
+pub unsafe extern "C" fn nes_destroy(nes: *mut CPU) {
+    if !nes.is_null() {
+        drop(Box::from_raw(nes));
+    }
+}
+
+/* an iNES image, 0 once it's loaded and the CPU reset, -1 if it can't be */
+#[no_mangle]
+pub unsafe extern "C" fn nes_load_rom(nes: *mut CPU, data: *const u8, len: usize) -> c_int {
+    let cpu = &mut *nes;
+    let loaded = Rom::parse(slice::from_raw_parts(data, len)).and_then(|rom| cpu.load_rom(&rom));
+    match loaded {
+        Ok(()) => {
+            cpu.reset();
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn nes_reset(nes: *mut CPU) {
+    (*nes).reset();
+}
+
+/*
+ * Runs one instruction: 1 if the CPU can keep going, 0 if it stopped at
+ * BRK (stepping again runs the IRQ handler), -1 if it hit an opcode that
+ * isn't implemented yet.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn nes_step(nes: *mut CPU) -> c_int {
+    match (*nes).step() {
+        Ok(_) => 1,
+        Err(CpuError::Break { .. } | CpuError::Stopped { .. }) => 0,
+        Err(CpuError::UnimplementedOpcode { .. }) => -1,
+    }
+}
+
+/*
+ * Writes the save state to `out` if it's at least `len` bytes, returning
+ * the state's size either way, so a null `out` asks how much to allocate.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn nes_save_state(nes: *const CPU, out: *mut u8, len: usize) -> usize {
+    let state = (*nes).save_state();
+    if !out.is_null() && len >= state.len() {
+        slice::from_raw_parts_mut(out, state.len()).copy_from_slice(&state);
+    }
+    state.len()
+}
+
+/* 0 on success, -1 if the state is bad, in which case nothing changes */
+#[no_mangle]
+pub unsafe extern "C" fn nes_load_state(nes: *mut CPU, data: *const u8, len: usize) -> c_int {
+    match (*nes).load_state(slice::from_raw_parts(data, len)) {
+        Ok(()) => 0,
+        Err(_) => -1,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn nes_peek(nes: *const CPU, addr: u16) -> u8 {
+    (*nes).peek(addr)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /* NROM with LDX #$07, INX, BRK at the reset vector */
+    fn rom() -> Vec<u8> {
+        let mut prg = vec![0; 0x4000];
+        prg[..4].copy_from_slice(&[0xA2, 0x07, 0xE8, 0x00]);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let mut rom = b"NES\x1A\x01\x01\x00\x00".to_vec();
+        rom.resize(16, 0);
+        rom.extend(prg);
+        rom.extend(vec![0; 0x2000]);
+        rom
+    }
+
+    #[test]
+    fn test_ffi_round_trip() {
+        unsafe {
+            let nes = nes_create();
+            assert_eq!(nes_load_rom(nes, b"junk".as_ptr(), 4), -1);
+            let rom = rom();
+            assert_eq!(nes_load_rom(nes, rom.as_ptr(), rom.len()), 0);
+            assert_eq!(nes_step(nes), 1);
+
+            let len = nes_save_state(nes, std::ptr::null_mut(), 0);
+            let mut state = vec![0; len];
+            assert_eq!(nes_save_state(nes, state.as_mut_ptr(), len), len);
+            assert_eq!(nes_step(nes), 1);
+            assert_eq!((*nes).register_x.0, 0x08);
+            assert_eq!(nes_step(nes), 0);
+
+            assert_eq!(nes_load_state(nes, state.as_ptr(), len), 0);
+            assert_eq!((*nes).register_x.0, 0x07);
+            assert_eq!(nes_load_state(nes, state.as_ptr(), 3), -1);
+            assert_eq!(nes_peek(nes, 0x8000), 0xA2);
+
+            /* an opcode that isn't implemented yet */
+            (*nes).program_counter = 0x8010;
+            (*nes).poke(0x8010, 0x02);
+            assert_eq!(nes_step(nes), -1);
+            nes_destroy(nes);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+
+use crate::movie::Frame;
+use crate::savestate::StateError;
+use crate::CPU;
+
+/*
+ * Save states kept every `interval` frames while a movie is recorded or
+ * played, so seeking only replays from the nearest one instead of from
+ * power-on. The state for frame N is taken before frame N's input is
+ * applied, so editing the input of frame N leaves it valid and only
+ * everything after it has to go.
+ */
+pub struct Greenzone {
+    interval: usize,
+    states: BTreeMap<usize, Vec<u8>>,
+}
+
+impl Greenzone {
+    /* `start` is the machine as the movie begins, frame 0 */
+    pub fn new(interval: usize, start: &CPU) -> Self {
+        let mut states = BTreeMap::new();
+        states.insert(0, start.save_state());
+        Greenzone {
+            interval: interval.max(1),
+            states,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    /* there's always the starting state */
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /* call with the frame about to be run, the state is kept if it falls on the interval */
+    pub fn record(&mut self, frame: usize, cpu: &CPU) {
+        if frame.is_multiple_of(self.interval) {
+            self.states.insert(frame, cpu.save_state());
+        }
+    }
+
+    /* the latest checkpoint at or before `frame` */
+    pub fn nearest(&self, frame: usize) -> (usize, &[u8]) {
+        let (&at, state) = self
+            .states
+            .range(..=frame)
+            .next_back()
+            .expect("frame 0 is always kept");
+        (at, state)
+    }
+
+    /* the input for `frame` changed, states after it no longer follow from it */
+    pub fn invalidate_after(&mut self, frame: usize) {
+        self.states.split_off(&(frame + 1));
+    }
+
+    /*
+     * Put the CPU at the start of `target`, replaying `frames` from the
+     * nearest checkpoint with `run_frame` and keeping checkpoints on the way.
+     */
+    pub fn seek(
+        &mut self,
+        cpu: &mut CPU,
+        frames: &[Frame],
+        target: usize,
+        mut run_frame: impl FnMut(&mut CPU, Frame),
+    ) -> Result<(), StateError> {
+        let target = target.min(frames.len());
+        let (at, state) = self.nearest(target);
+        cpu.load_state(state)?;
+        for (frame, input) in frames.iter().enumerate().take(target).skip(at) {
+            if frame > at {
+                self.record(frame, cpu);
+            }
+            run_frame(cpu, *input);
+        }
+        self.record(target, cpu);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /* stand-in for a frame: add the port 0 buttons to A */
+    fn run_frame(cpu: &mut CPU, input: Frame) {
+        cpu.register_a += input.ports[0];
+    }
+
+    fn frames(len: usize) -> Vec<Frame> {
+        (0..len)
+            .map(|i| Frame {
+                commands: 0,
+                ports: [i as u8, 0],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_seek_replays_from_nearest() {
+        let frames = frames(20);
+        let mut cpu = CPU::new();
+        let mut greenzone = Greenzone::new(4, &cpu);
+
+        let mut replayed = 0;
+        greenzone
+            .seek(&mut cpu, &frames, 10, |cpu, input| {
+                replayed += 1;
+                run_frame(cpu, input)
+            })
+            .unwrap();
+        assert_eq!(replayed, 10);
+        assert_eq!(cpu.register_a.0, (0..10).sum::<u8>());
+        assert_eq!(greenzone.len(), 3);
+        assert_eq!(greenzone.nearest(10).0, 8);
+
+        /* back to 9 only replays frame 8 */
+        let mut replayed = 0;
+        greenzone
+            .seek(&mut cpu, &frames, 9, |cpu, input| {
+                replayed += 1;
+                run_frame(cpu, input)
+            })
+            .unwrap();
+        assert_eq!(replayed, 1);
+        assert_eq!(cpu.register_a.0, (0..9).sum::<u8>());
+    }
+
+    #[test]
+    fn test_editing_input_invalidates_later_states() {
+        let mut frames = frames(20);
+        let mut cpu = CPU::new();
+        let mut greenzone = Greenzone::new(4, &cpu);
+        greenzone.seek(&mut cpu, &frames, 16, run_frame).unwrap();
+        assert_eq!(greenzone.nearest(16).0, 16);
+
+        frames[5].ports[0] = 100;
+        greenzone.invalidate_after(5);
+        assert_eq!(greenzone.nearest(16).0, 4);
+
+        greenzone.seek(&mut cpu, &frames, 16, run_frame).unwrap();
+        assert_eq!(cpu.register_a.0, (0..16).sum::<u8>() - 5 + 100);
+    }
+
+    #[test]
+    fn test_seek_past_the_end() {
+        let frames = frames(3);
+        let mut cpu = CPU::new();
+        let mut greenzone = Greenzone::new(1, &cpu);
+        greenzone.seek(&mut cpu, &frames, 50, run_frame).unwrap();
+        assert_eq!(cpu.register_a.0, 3);
+        assert_eq!(greenzone.len(), 4);
+    }
+}
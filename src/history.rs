@@ -0,0 +1,205 @@
+use std::collections::VecDeque;
+use std::num::Wrapping;
+
+use crate::status::StatusFlags;
+use crate::CPU;
+
+/*
+ * Undo log for reverse stepping. Rather than copying all of memory for every
+ * instruction, each entry keeps the registers as they were before the
+ * instruction ran plus the previous value of every byte it wrote.
+ */
+pub struct History {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+    recording: bool,
+}
+
+struct Entry {
+    register_a: u8,
+    register_x: u8,
+    register_y: u8,
+    status: StatusFlags,
+    program_counter: u16,
+    stack_pointer: u8,
+    cycles: u64,
+    writes: Vec<(u16, u8)>,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        History {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            recording: false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub(crate) fn begin(&mut self, cpu: &CPU) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Entry {
+            register_a: cpu.register_a.0,
+            register_x: cpu.register_x.0,
+            register_y: cpu.register_y.0,
+            status: cpu.status,
+            program_counter: cpu.program_counter,
+            stack_pointer: cpu.stack_pointer,
+            cycles: cpu.cycles,
+            writes: Vec::new(),
+        });
+        self.recording = true;
+    }
+
+    pub(crate) fn end(&mut self) {
+        self.recording = false;
+    }
+
+    pub(crate) fn record_write(&mut self, addr: u16, old: u8) {
+        if !self.recording {
+            return;
+        }
+        if let Some(entry) = self.entries.back_mut() {
+            entry.writes.push((addr, old));
+        }
+    }
+
+    fn pop(&mut self) -> Option<Entry> {
+        self.entries.pop_back()
+    }
+}
+
+impl Entry {
+    fn undo(self, cpu: &mut CPU) {
+        for (addr, old) in self.writes.into_iter().rev() {
+            cpu.poke(addr, old);
+        }
+        cpu.register_a = Wrapping(self.register_a);
+        cpu.register_x = Wrapping(self.register_x);
+        cpu.register_y = Wrapping(self.register_y);
+        cpu.status = self.status;
+        cpu.program_counter = self.program_counter;
+        cpu.stack_pointer = self.stack_pointer;
+        cpu.cycles = self.cycles;
+    }
+}
+
+impl CPU {
+    pub fn enable_history(&mut self, capacity: usize) {
+        self.history = Some(History::new(capacity));
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    /* Undo up to `count` instructions, returns how many were actually undone */
+    pub fn step_back(&mut self, count: usize) -> usize {
+        let mut undone = 0;
+        while undone < count {
+            let Some(entry) = self.history.as_mut().and_then(|h| h.pop()) else {
+                break;
+            };
+            entry.undo(self);
+            undone += 1;
+        }
+        undone
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Mem;
+
+    #[test]
+    fn test_step_back_restores_registers() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xa9, 0x05, 0xaa, 0xe8, 0x00]).unwrap();
+        cpu.enable_history(16);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.register_x.0, 0x06);
+
+        assert_eq!(cpu.step_back(2), 2);
+        assert_eq!(cpu.register_a.0, 0x05);
+        assert_eq!(cpu.register_x.0, 0x00);
+        assert_eq!(cpu.program_counter, 0x8002);
+    }
+
+    #[test]
+    fn test_step_back_restores_memory() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x42);
+        cpu.init(vec![0xa9, 0xff, 0x85, 0x10, 0xa9, 0x01, 0x85, 0x10, 0x00])
+            .unwrap();
+        cpu.enable_history(16);
+        cpu.run();
+        assert_eq!(cpu.mem_read(0x10), 0x01);
+
+        cpu.step_back(2);
+        assert_eq!(cpu.mem_read(0x10), 0xff);
+        cpu.step_back(3);
+        assert_eq!(cpu.mem_read(0x10), 0x42);
+        assert_eq!(cpu.program_counter, 0x8000);
+    }
+
+    #[test]
+    fn test_step_back_then_forward_again() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xa9, 0x05, 0xaa, 0xe8, 0x00]).unwrap();
+        cpu.enable_history(16);
+        cpu.run();
+        cpu.step_back(3);
+        cpu.run();
+        assert_eq!(cpu.register_x.0, 0x06);
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xe8, 0xe8, 0xe8, 0xe8, 0x00]).unwrap();
+        cpu.enable_history(2);
+        cpu.run();
+        assert_eq!(cpu.history().unwrap().len(), 2);
+
+        assert_eq!(cpu.step_back(5), 2);
+        assert_eq!(cpu.register_x.0, 0x03);
+    }
+
+    #[test]
+    fn test_writes_outside_step_are_not_undone() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xe8, 0x00]).unwrap();
+        cpu.enable_history(4);
+        cpu.step().unwrap();
+        cpu.mem_write(0x20, 0x99);
+        cpu.step_back(1);
+        assert_eq!(cpu.mem_read(0x20), 0x99);
+    }
+}
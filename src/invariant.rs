@@ -0,0 +1,145 @@
+use std::fmt;
+
+use crate::CPU;
+
+pub struct Invariant {
+    name: String,
+    check: Box<dyn Fn(&CPU) -> bool + Send>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub name: String,
+    /* address of the instruction after which the check failed */
+    pub pc: u16,
+    pub register_a: u8,
+    pub register_x: u8,
+    pub register_y: u8,
+    pub status: u8,
+    pub stack_pointer: u8,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invariant '{}' violated after ${:04X} (A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X})",
+            self.name,
+            self.pc,
+            self.register_a,
+            self.register_x,
+            self.register_y,
+            self.status,
+            self.stack_pointer
+        )
+    }
+}
+
+impl CPU {
+    /*
+     * Register a check that must hold after every instruction. The first one
+     * to fail stops the run and is kept around for inspection.
+     */
+    pub fn add_invariant(&mut self, name: &str, check: impl Fn(&CPU) -> bool + Send + 'static) {
+        self.invariants.push(Invariant {
+            name: name.to_string(),
+            check: Box::new(check),
+        });
+    }
+
+    pub fn clear_invariants(&mut self) {
+        self.invariants.clear();
+        self.violation = None;
+    }
+
+    pub fn invariant_violation(&self) -> Option<&Violation> {
+        self.violation.as_ref()
+    }
+
+    /* like run(), but reports a violated invariant as an error */
+    pub fn run_checked(&mut self) -> Result<(), Violation> {
+        self.run();
+        match self.violation.take() {
+            Some(violation) => Err(violation),
+            None => Ok(()),
+        }
+    }
+
+    /* returns false if any invariant failed for the instruction at `pc` */
+    pub(crate) fn check_invariants(&mut self, pc: u16) -> bool {
+        if self.invariants.is_empty() {
+            return true;
+        }
+        let invariants = std::mem::take(&mut self.invariants);
+        let failed = invariants.iter().find(|invariant| !(invariant.check)(self));
+        if let Some(invariant) = failed {
+            self.violation = Some(Violation {
+                name: invariant.name.clone(),
+                pc,
+                register_a: self.register_a.0,
+                register_x: self.register_x.0,
+                register_y: self.register_y.0,
+                status: self.status.bits(),
+                stack_pointer: self.stack_pointer,
+            });
+        }
+        let ok = failed.is_none();
+        self.invariants = invariants;
+        ok
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_invariant_holds() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xa9, 0x05, 0xaa, 0xe8, 0x00]).unwrap();
+        cpu.add_invariant("x below 0x10", |cpu| cpu.register_x.0 < 0x10);
+        assert_eq!(cpu.run_checked(), Ok(()));
+        assert_eq!(cpu.register_x.0, 0x06);
+    }
+
+    #[test]
+    fn test_invariant_violation_stops_run() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xa9, 0x05, 0xaa, 0xe8, 0xe8, 0x00]).unwrap();
+        cpu.add_invariant("x is odd or zero", |cpu| {
+            cpu.register_x.0 == 0 || cpu.register_x.0 % 2 == 1
+        });
+        cpu.run();
+
+        let violation = cpu.invariant_violation().unwrap();
+        assert_eq!(violation.name, "x is odd or zero");
+        assert_eq!(violation.pc, 0x8003);
+        assert_eq!(violation.register_x, 0x06);
+        assert_eq!(cpu.program_counter, 0x8004);
+    }
+
+    #[test]
+    fn test_invariant_over_memory() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xa9, 0x07, 0x85, 0x10, 0xa9, 0x08, 0x85, 0x10, 0x00])
+            .unwrap();
+        cpu.add_invariant("$10 stays below 8", |cpu| cpu.peek(0x10) < 8);
+
+        let violation = cpu.run_checked().unwrap_err();
+        assert_eq!(violation.pc, 0x8006);
+        assert_eq!(
+            violation.to_string(),
+            "invariant '$10 stays below 8' violated after $8006 (A:08 X:00 Y:00 P:00 SP:FF)"
+        );
+    }
+
+    #[test]
+    fn test_first_failing_invariant_is_reported() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xe8, 0x00]).unwrap();
+        cpu.add_invariant("first", |cpu| cpu.register_x.0 == 0);
+        cpu.add_invariant("second", |cpu| cpu.register_x.0 == 0);
+        let violation = cpu.run_checked().unwrap_err();
+        assert_eq!(violation.name, "first");
+    }
+}
@@ -0,0 +1,2573 @@
+use std::fmt;
+use std::num::Wrapping;
+
+pub mod assembler;
+pub mod bench;
+pub mod blargg;
+pub mod bus;
+pub mod checksum;
+pub mod coverage;
+pub mod diagnostics;
+#[cfg(test)]
+mod differential;
+pub mod ffi;
+pub mod greenzone;
+pub mod history;
+pub mod invariant;
+pub(crate) mod json;
+pub mod movie;
+#[cfg(feature = "opcode-coverage")]
+pub mod opcode_coverage;
+pub mod opcodes;
+#[cfg(test)]
+mod properties;
+pub mod ramwatch;
+pub mod replay;
+pub mod rewind;
+#[cfg(test)]
+mod rng;
+pub mod rom;
+pub mod savestate;
+pub mod singlestep;
+pub mod status;
+pub mod testrom;
+pub mod trace;
+pub mod tracediff;
+pub mod uninit;
+pub mod watch;
+
+use assembler::ORIGIN;
+use bus::{Bus, Mem};
+use coverage::Coverage;
+use diagnostics::Diagnostics;
+use history::History;
+use invariant::{Invariant, Violation};
+use rewind::Rewind;
+use status::StatusFlags;
+use uninit::UninitTracker;
+use watch::Watch;
+
+type Wu8 = Wrapping<u8>;
+
+const RESET_VECTOR: u16 = 0xFFFC;
+
+/*
+Done: every official opcode, and the unofficial NOPs, LAX, SAX, DCP, ISB,
+SLO, RLA, SRE, RRA, and SBC $EB
+TODO: the other unofficial opcodes (ANC, ALR, ARR, XAA, LXA, AXS, AHX,
+SHY, SHX, TAS, LAS, JAM)
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum AddressingMode {
+    Immediate,
+    ZeroPage,
+    ZeroPage_X,
+    ZeroPage_Y,
+    Absolute,
+    Absolute_X,
+    Absolute_Y,
+    Indirect,
+    Indirect_X,
+    Indirect_Y,
+    Accumulator,
+    Relative,
+    NoneAddressing,
+}
+
+/* why step() stopped, with the address of the instruction it stopped at */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError {
+    /* BRK ran, and the CPU is at its handler */
+    Break { pc: u16 },
+    /* the instruction ran, but a read of uninitialized memory or an
+     * invariant asked to stop after it */
+    Stopped { pc: u16 },
+    /* the CPU is left as it was, at the opcode */
+    UnimplementedOpcode { pc: u16, opcode: u8 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::Break { pc } => write!(f, "BRK at ${:04X}", pc),
+            CpuError::Stopped { pc } => write!(f, "stopped after ${:04X}", pc),
+            CpuError::UnimplementedOpcode { pc, opcode } => {
+                write!(f, "unimplemented opcode ${:02X} at ${:04X}", opcode, pc)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+pub struct CPU {
+    pub register_a: Wu8,
+    pub register_x: Wu8,
+    pub register_y: Wu8,
+    pub status: StatusFlags,
+    pub program_counter: u16,
+    pub stack_pointer: u8,
+    /* elapsed CPU cycles, the clock the PPU and APU will run against */
+    pub cycles: u64,
+    pub stack_location: u16,
+    pub stack_size: u8,
+    /* BCD ADC and SBC when D is set, for 6502s other than the NES's 2A03 */
+    pub decimal_mode: bool,
+    /* JMP ($xxFF) reads its high byte from the next page, without the 6502's bug */
+    pub strict_jmp_indirect: bool,
+    /* whether this instruction's indexed address crossed a page */
+    page_crossed: bool,
+    pub bus: Bus,
+    history: Option<History>,
+    diagnostics: Option<Diagnostics>,
+    uninit: Option<UninitTracker>,
+    invariants: Vec<Invariant>,
+    violation: Option<Violation>,
+    coverage: Option<Coverage>,
+    watches: Vec<Watch>,
+    rewind: Option<Rewind>,
+}
+
+/* the debugging hooks see every access the CPU makes */
+impl Mem for CPU {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        if let Some(uninit) = &mut self.uninit {
+            uninit.record_read(addr);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record_read(addr);
+        }
+        self.bus.mem_read(addr)
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        if let Some(history) = &mut self.history {
+            history.record_write(addr, self.bus.peek(addr));
+        }
+        if let Some(uninit) = &mut self.uninit {
+            uninit.record_write(addr);
+        }
+        self.bus.mem_write(addr, data);
+    }
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CPU {
+    pub fn new() -> Self {
+        CPU {
+            register_a: Wrapping(0),
+            register_x: Wrapping(0),
+            register_y: Wrapping(0),
+            status: StatusFlags::empty(),
+            program_counter: 0,
+            bus: Bus::new(),
+            stack_pointer: 0xFF,
+            cycles: 0,
+            stack_location: 0x100,
+            stack_size: 0xFF,
+            decimal_mode: false,
+            strict_jmp_indirect: false,
+            page_crossed: false,
+            history: None,
+            diagnostics: None,
+            uninit: None,
+            invariants: Vec::new(),
+            violation: None,
+            coverage: None,
+            watches: Vec::new(),
+            rewind: None,
+        }
+    }
+
+    /* read memory without any of the side effects of a CPU access */
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
+    }
+
+    /* write memory the same way, for a host feeding in input */
+    pub fn poke(&mut self, addr: u16, data: u8) {
+        self.bus.poke(addr, data);
+    }
+
+    /* the console's 2KB of work RAM, what TAS tools search and watch */
+    pub fn ram(&self) -> &[u8] {
+        self.bus.ram()
+    }
+
+    /*
+     * The stack is page 1, growing down from $01FF. The pointer is the next
+     * free slot and wraps around within the page.
+     */
+    fn stack_addr(&self) -> u16 {
+        self.stack_location + self.stack_pointer as u16
+    }
+
+    fn push8(&mut self, byte: u8) {
+        self.diagnose_push();
+        self.mem_write(self.stack_addr(), byte);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+    }
+
+    fn pop8(&mut self) -> u8 {
+        self.diagnose_pop();
+        self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        self.mem_read(self.stack_addr())
+    }
+
+    /* high byte first, so it pops back off low byte first */
+    fn push16(&mut self, data: u16) {
+        self.push8((data >> 8) as u8);
+        self.push8(data as u8);
+    }
+
+    fn pop16(&mut self) -> u16 {
+        let lo = self.pop8() as u16;
+        let hi = self.pop8() as u16;
+        hi << 8 | lo
+    }
+
+    pub fn reset(&mut self) {
+        self.register_a = Wrapping(0);
+        self.register_x = Wrapping(0);
+        self.register_y = Wrapping(0);
+        self.status = StatusFlags::empty();
+
+        self.program_counter = self.mem_read_u16(RESET_VECTOR);
+    }
+
+    /*
+     * Puts the program at the start of cartridge space and points the reset
+     * vector at it. Without a cartridge loaded the program gets a blank 32KB
+     * PRG ROM to sit in, and one too big for the PRG ROM is refused.
+     */
+    pub fn load(&mut self, program: Vec<u8>) -> Result<(), String> {
+        if self.bus.cartridge().is_none() {
+            self.bus.load_prg_rom(vec![0; 0x8000]);
+        }
+        let space = self.bus.prg_rom().len();
+        if program.len() > space {
+            return Err(format!(
+                "a {} byte program doesn't fit in {} bytes of PRG ROM",
+                program.len(),
+                space
+            ));
+        }
+        for (addr, &byte) in (ORIGIN..=u16::MAX).zip(&program) {
+            self.poke(addr, byte);
+        }
+        let [lo, hi] = ORIGIN.to_le_bytes();
+        self.poke(RESET_VECTOR, lo);
+        self.poke(RESET_VECTOR + 1, hi);
+        Ok(())
+    }
+
+    pub fn init(&mut self, program: Vec<u8>) -> Result<(), String> {
+        self.load(program)?;
+        self.reset();
+        Ok(())
+    }
+
+    pub fn load_and_run(&mut self, program: Vec<u8>) -> Result<(), String> {
+        self.init(program)?;
+        self.run();
+        Ok(())
+    }
+
+    /* steps until step() returns an error, usually the program's BRK */
+    pub fn run(&mut self) {
+        // note: we move  intialization of program_counter from here to load function
+        self.run_with_callback(|_| {});
+    }
+
+    /* run(), calling `callback` before every instruction to trace it or poke in input */
+    pub fn run_with_callback<F>(&mut self, mut callback: F)
+    where
+        F: FnMut(&mut CPU),
+    {
+        loop {
+            callback(self);
+            if self.step().is_err() {
+                break;
+            }
+        }
+    }
+
+    /*
+     * Execute a single instruction, returning the cycles it took. BRK is
+     * an error so programs can end on it until there's something else to
+     * stop them, but it still goes through the IRQ vector like it does on
+     * hardware, so stepping again runs its handler.
+     */
+    pub fn step(&mut self) -> Result<u8, CpuError> {
+        let pc = self.program_counter;
+        let cycles = self.cycles;
+        self.capture_rewind();
+        if let Some(mut history) = self.history.take() {
+            history.begin(self);
+            self.history = Some(history);
+        }
+        if let Some(diagnostics) = &mut self.diagnostics {
+            diagnostics.begin(pc);
+        }
+        if let Some(uninit) = &mut self.uninit {
+            uninit.begin(pc);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.begin(pc, opcodes::lookup(self.bus.peek(pc)).len);
+        }
+        #[cfg(feature = "opcode-coverage")]
+        let opcode = self.peek(pc);
+        let result = self.execute();
+        #[cfg(feature = "opcode-coverage")]
+        opcode_coverage::record(opcode);
+        if let Some(history) = &mut self.history {
+            history.end();
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.end();
+        }
+        let mut stop = false;
+        if let Some(uninit) = &mut self.uninit {
+            uninit.end();
+            stop |= uninit.should_break();
+        }
+        stop |= !self.check_invariants(pc);
+        result?;
+        if stop {
+            return Err(CpuError::Stopped { pc });
+        }
+        Ok((self.cycles - cycles) as u8)
+    }
+
+    fn execute(&mut self) -> Result<(), CpuError> {
+        use opcodes::Mnemonic::*;
+
+        let pc = self.program_counter;
+        let code = self.mem_read(pc);
+        self.program_counter = pc.wrapping_add(1);
+        let op = opcodes::lookup(code);
+        let mode = &op.mode;
+        self.cycles += op.cycles as u64;
+        self.page_crossed = false;
+
+        match op.mnemonic {
+            LDA => self.lda(mode),
+            LDX => self.ldx(mode),
+            LDY => self.ldy(mode),
+            STA => self.sta(mode),
+            STX => self.stx(mode),
+            STY => self.sty(mode),
+
+            ADC => self.adc(mode),
+            SBC => self.sbc(mode),
+            AND => self.and(mode),
+            ORA => self.ora(mode),
+            EOR => self.eor(mode),
+            CMP => self.compare(mode, self.register_a.0),
+            CPX => self.compare(mode, self.register_x.0),
+            CPY => self.compare(mode, self.register_y.0),
+            BIT => self.bit(mode),
+
+            ASL => self.asl(mode),
+            LSR => self.lsr(mode),
+            ROL => self.rol(mode),
+            ROR => self.ror(mode),
+            INC => self.inc(mode),
+            DEC => self.dec(mode),
+            INX => self.inx(),
+            INY => self.iny(),
+            DEX => self.dex(),
+            DEY => self.dey(),
+
+            TAX => self.tax(),
+            TXA => self.txa(),
+            TAY => self.tay(),
+            TYA => self.tya(),
+            TSX => self.tsx(),
+            TXS => self.txs(),
+            PHA => self.pha(),
+            PLA => self.pla(),
+            PHP => self.php(),
+            PLP => self.plp(),
+
+            CLC => self.status.remove(StatusFlags::CARRY),
+            SEC => self.status.insert(StatusFlags::CARRY),
+            CLI => self.status.remove(StatusFlags::INTERRUPT_DISABLE),
+            SEI => self.status.insert(StatusFlags::INTERRUPT_DISABLE),
+            CLV => self.status.remove(StatusFlags::OVERFLOW),
+            CLD => self.status.remove(StatusFlags::DECIMAL),
+            SED => self.status.insert(StatusFlags::DECIMAL),
+
+            /* the unofficial NOPs only use their mode to skip an operand */
+            NOP => self.nop(mode),
+
+            /* unofficial */
+            LAX => self.lax(mode),
+            SAX => self.sax(mode),
+            SLO => self.slo(mode),
+            RLA => self.rla(mode),
+            SRE => self.sre(mode),
+            RRA => self.rra(mode),
+            DCP => self.dcp(mode),
+            ISB => self.isb(mode),
+
+            /* these leave the program counter where they want it */
+            BPL | BMI | BVC | BVS | BCC | BCS | BNE | BEQ => {
+                self.branch(mode, self.branch_condition(code));
+                return Ok(());
+            }
+            JMP => {
+                self.jmp(mode);
+                return Ok(());
+            }
+            JSR => {
+                self.jsr(mode);
+                return Ok(());
+            }
+            RTS => {
+                self.rts();
+                return Ok(());
+            }
+            RTI => {
+                self.rti();
+                return Ok(());
+            }
+            BRK => {
+                self.diagnose_brk();
+                self.brk();
+                return Err(CpuError::Break { pc });
+            }
+            _ => {
+                self.program_counter = pc;
+                self.cycles -= op.cycles as u64;
+                return Err(CpuError::UnimplementedOpcode { pc, opcode: code });
+            }
+        }
+        if self.page_crossed && op.page_cross_penalty() {
+            self.cycles += 1;
+        }
+        self.program_counter = self.program_counter.wrapping_add(op.len as u16 - 1);
+        Ok(())
+    }
+
+    fn update_zero_and_negative_flags(&mut self, result: Wu8) {
+        self.status.set(StatusFlags::ZERO, result == Wrapping(0));
+        self.status.set(
+            StatusFlags::NEGATIVE,
+            result & Wrapping(0b1000_0000) != Wrapping(0),
+        );
+    }
+
+    /* an indexed address, noting whether the index carried into the high byte */
+    fn index(&mut self, base: u16, index: Wu8) -> u16 {
+        let addr = base.wrapping_add(index.0 as u16);
+        self.page_crossed = addr & 0xFF00 != base & 0xFF00;
+        addr
+    }
+
+    fn get_operand_address(&mut self, mode: &AddressingMode) -> u16 {
+        match mode {
+            AddressingMode::Immediate => self.program_counter,
+            AddressingMode::ZeroPage => self.mem_read(self.program_counter) as u16,
+            AddressingMode::Absolute => self.mem_read_u16(self.program_counter),
+            AddressingMode::ZeroPage_X => {
+                let pos = Wrapping(self.mem_read(self.program_counter));
+                (self.register_x + pos).0 as u16
+            }
+            AddressingMode::ZeroPage_Y => {
+                let pos = Wrapping(self.mem_read(self.program_counter));
+                (pos + self.register_y).0 as u16
+            }
+
+            AddressingMode::Absolute_X => {
+                let base = self.mem_read_u16(self.program_counter);
+                self.index(base, self.register_x)
+            }
+            AddressingMode::Absolute_Y => {
+                let base = self.mem_read_u16(self.program_counter);
+                self.index(base, self.register_y)
+            }
+            /*
+             * JMP's 16 bit pointer. The 6502 doesn't carry into the high byte
+             * of the pointer, so one at $xxFF gets its high byte from $xx00.
+             */
+            AddressingMode::Indirect => {
+                let ptr = self.mem_read_u16(self.program_counter);
+                let hi_addr = if ptr & 0x00FF == 0x00FF && !self.strict_jmp_indirect {
+                    ptr & 0xFF00
+                } else {
+                    ptr.wrapping_add(1)
+                };
+                let lo = self.mem_read(ptr);
+                let hi = self.mem_read(hi_addr);
+                (hi as u16) << 8 | (lo as u16)
+            }
+            AddressingMode::Indirect_X => {
+                let base = Wrapping(self.mem_read(self.program_counter));
+
+                let ptr = base + self.register_x;
+                let lo = self.mem_read(ptr.0 as u16);
+                let hi = self.mem_read((ptr + Wrapping(1)).0 as u16);
+                (hi as u16) << 8 | (lo as u16)
+            }
+            AddressingMode::Indirect_Y => {
+                let base = self.mem_read(self.program_counter);
+
+                let lo = self.mem_read(base as u16);
+                let hi = self.mem_read((Wrapping(base) + Wrapping(1)).0 as u16);
+                let deref_base = (hi as u16) << 8 | (lo as u16);
+                self.index(deref_base, self.register_y)
+            }
+            /* the branch target, from a signed offset past the operand */
+            AddressingMode::Relative => {
+                let offset = self.mem_read(self.program_counter) as i8;
+                self.program_counter
+                    .wrapping_add(1)
+                    .wrapping_add(offset as u16)
+            }
+            AddressingMode::Accumulator | AddressingMode::NoneAddressing => {
+                panic!("mode {:?} is not supported", mode);
+            }
+        }
+    }
+
+    fn lda(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        self.register_a = Wrapping(value);
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+    /* A + value + carry, setting carry on unsigned and overflow on signed overflow */
+    fn add_to_a(&mut self, value: u8) {
+        let a = self.register_a.0;
+        let sum = a as u16 + value as u16 + (self.status.get(StatusFlags::CARRY) as u8) as u16;
+        let result = sum as u8;
+        self.status.set(StatusFlags::CARRY, sum > 0xFF);
+        /* both inputs have the same sign and the result doesn't */
+        self.status.set(
+            StatusFlags::OVERFLOW,
+            (a ^ result) & (value ^ result) & 0x80 != 0,
+        );
+        self.register_a = Wrapping(result);
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+    fn decimal(&self) -> bool {
+        self.decimal_mode && self.status.get(StatusFlags::DECIMAL)
+    }
+    fn add_with_carry(&mut self, value: u8) {
+        if self.decimal() {
+            self.add_decimal(value);
+        } else {
+            self.add_to_a(value);
+        }
+    }
+    /* A - value - borrow, where carry clear means borrow: A + !value + carry */
+    fn subtract_with_borrow(&mut self, value: u8) {
+        /* decimal mode only changes the result, the flags are the binary ones */
+        let result = self.decimal().then(|| self.subtract_decimal(value));
+        self.add_to_a(!value);
+        if let Some(result) = result {
+            self.register_a = Wrapping(result);
+        }
+    }
+    /*
+     * BCD addition the way an NMOS 6502 does it: Z comes from the binary
+     * sum, N and V from the high digit before it's adjusted.
+     */
+    fn add_decimal(&mut self, value: u8) {
+        let a = self.register_a.0;
+        let carry = self.status.get(StatusFlags::CARRY) as u8;
+        let mut lo = (a & 0x0F) + (value & 0x0F) + carry;
+        if lo > 0x09 {
+            lo += 0x06;
+        }
+        let mut hi = (a >> 4) + (value >> 4) + (lo > 0x0F) as u8;
+        let unadjusted = hi << 4 | (lo & 0x0F);
+        self.status.set(
+            StatusFlags::ZERO,
+            a.wrapping_add(value).wrapping_add(carry) == 0,
+        );
+        self.status
+            .set(StatusFlags::NEGATIVE, unadjusted & 0x80 != 0);
+        self.status.set(
+            StatusFlags::OVERFLOW,
+            (a ^ unadjusted) & (value ^ unadjusted) & 0x80 != 0,
+        );
+        if hi > 0x09 {
+            hi += 0x06;
+        }
+        self.status.set(StatusFlags::CARRY, hi > 0x0F);
+        self.register_a = Wrapping(hi << 4 | (lo & 0x0F));
+    }
+    fn subtract_decimal(&self, value: u8) -> u8 {
+        let a = self.register_a.0;
+        let borrow = !self.status.get(StatusFlags::CARRY) as i16;
+        let mut lo = (a & 0x0F) as i16 - (value & 0x0F) as i16 - borrow;
+        let mut hi = (a >> 4) as i16 - (value >> 4) as i16;
+        if lo < 0 {
+            lo -= 0x06;
+            hi -= 1;
+        }
+        if hi < 0 {
+            hi -= 0x06;
+        }
+        ((hi << 4) | (lo & 0x0F)) as u8
+    }
+    fn adc(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        self.add_with_carry(value);
+    }
+    fn sbc(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        self.subtract_with_borrow(value);
+    }
+    fn and(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        self.register_a &= Wrapping(value);
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+    fn ora(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        self.register_a |= Wrapping(value);
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+    fn eor(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        self.register_a ^= Wrapping(value);
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+    fn inc(&mut self, mode: &AddressingMode) {
+        self.read_modify_write(mode, Self::increment);
+    }
+    fn dec(&mut self, mode: &AddressingMode) {
+        self.read_modify_write(mode, Self::decrement);
+    }
+    /*
+     * Unofficial NOPs with an operand don't use its value, but need its
+     * address for the cycle abs,X takes crossing a page.
+     */
+    fn nop(&mut self, mode: &AddressingMode) {
+        if *mode != AddressingMode::NoneAddressing {
+            self.get_operand_address(mode);
+        }
+    }
+    /* N and V are copied from the operand, Z is whether A & operand is 0 */
+    fn bit(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        self.status
+            .set(StatusFlags::ZERO, self.register_a.0 & value == 0);
+        self.status.set(StatusFlags::NEGATIVE, value & 0x80 != 0);
+        self.status.set(StatusFlags::OVERFLOW, value & 0x40 != 0);
+    }
+    fn compare(&mut self, mode: &AddressingMode, register: u8) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        self.compare_with(register, value);
+    }
+    /* register - value for the flags alone: C when register >= value */
+    fn compare_with(&mut self, register: u8, value: u8) {
+        self.status.set(StatusFlags::CARRY, register >= value);
+        self.update_zero_and_negative_flags(Wrapping(register) - Wrapping(value));
+    }
+    /* applies `op` to A or to the operand in memory, writing the result back */
+    fn read_modify_write(&mut self, mode: &AddressingMode, op: fn(&mut Self, u8) -> u8) {
+        match mode {
+            AddressingMode::Accumulator => {
+                let result = op(self, self.register_a.0);
+                self.register_a = Wrapping(result);
+            }
+            _ => {
+                let addr = self.get_operand_address(mode);
+                let value = self.mem_read(addr);
+                let result = op(self, value);
+                self.mem_write(addr, result);
+            }
+        }
+    }
+    fn asl(&mut self, mode: &AddressingMode) {
+        self.read_modify_write(mode, Self::shift_left);
+    }
+    fn lsr(&mut self, mode: &AddressingMode) {
+        self.read_modify_write(mode, Self::shift_right);
+    }
+    fn rol(&mut self, mode: &AddressingMode) {
+        self.read_modify_write(mode, Self::rotate_left);
+    }
+    fn ror(&mut self, mode: &AddressingMode) {
+        self.read_modify_write(mode, Self::rotate_right);
+    }
+    /* the operations read_modify_write applies, each setting its own flags */
+    fn increment(&mut self, value: u8) -> u8 {
+        let result = value.wrapping_add(1);
+        self.update_zero_and_negative_flags(Wrapping(result));
+        result
+    }
+    fn decrement(&mut self, value: u8) -> u8 {
+        let result = value.wrapping_sub(1);
+        self.update_zero_and_negative_flags(Wrapping(result));
+        result
+    }
+    fn shift_left(&mut self, value: u8) -> u8 {
+        self.status.set(StatusFlags::CARRY, value & 0x80 != 0);
+        let result = value << 1;
+        self.update_zero_and_negative_flags(Wrapping(result));
+        result
+    }
+    fn shift_right(&mut self, value: u8) -> u8 {
+        self.status.set(StatusFlags::CARRY, value & 0x01 != 0);
+        let result = value >> 1;
+        self.update_zero_and_negative_flags(Wrapping(result));
+        result
+    }
+    /* the carry goes in at bit 0 and bit 7 comes out into it */
+    fn rotate_left(&mut self, value: u8) -> u8 {
+        let result = value << 1 | (self.status.get(StatusFlags::CARRY) as u8);
+        self.status.set(StatusFlags::CARRY, value & 0x80 != 0);
+        self.update_zero_and_negative_flags(Wrapping(result));
+        result
+    }
+    /* the carry goes in at bit 7 and bit 0 comes out into it */
+    fn rotate_right(&mut self, value: u8) -> u8 {
+        let result = value >> 1 | (self.status.get(StatusFlags::CARRY) as u8) << 7;
+        self.status.set(StatusFlags::CARRY, value & 0x01 != 0);
+        self.update_zero_and_negative_flags(Wrapping(result));
+        result
+    }
+    /*
+     * Unofficial opcodes: most are a read-modify-write followed by the ALU
+     * operation from the same column, on the value just written.
+     */
+    fn lax(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        self.register_a = Wrapping(value);
+        self.register_x = Wrapping(value);
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+    /* stores A & X without touching the flags */
+    fn sax(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        self.mem_write(addr, (self.register_a & self.register_x).0);
+    }
+    /* DEC then CMP */
+    fn dcp(&mut self, mode: &AddressingMode) {
+        self.read_modify_write(mode, |cpu, value| {
+            let result = value.wrapping_sub(1);
+            cpu.compare_with(cpu.register_a.0, result);
+            result
+        });
+    }
+    /* INC then SBC */
+    fn isb(&mut self, mode: &AddressingMode) {
+        self.read_modify_write(mode, |cpu, value| {
+            let result = value.wrapping_add(1);
+            cpu.subtract_with_borrow(result);
+            result
+        });
+    }
+    /* ASL then ORA */
+    fn slo(&mut self, mode: &AddressingMode) {
+        self.read_modify_write(mode, |cpu, value| {
+            let result = cpu.shift_left(value);
+            cpu.register_a |= Wrapping(result);
+            cpu.update_zero_and_negative_flags(cpu.register_a);
+            result
+        });
+    }
+    /* ROL then AND */
+    fn rla(&mut self, mode: &AddressingMode) {
+        self.read_modify_write(mode, |cpu, value| {
+            let result = cpu.rotate_left(value);
+            cpu.register_a &= Wrapping(result);
+            cpu.update_zero_and_negative_flags(cpu.register_a);
+            result
+        });
+    }
+    /* LSR then EOR */
+    fn sre(&mut self, mode: &AddressingMode) {
+        self.read_modify_write(mode, |cpu, value| {
+            let result = cpu.shift_right(value);
+            cpu.register_a ^= Wrapping(result);
+            cpu.update_zero_and_negative_flags(cpu.register_a);
+            result
+        });
+    }
+    /* ROR then ADC, with the carry ROR shifted out */
+    fn rra(&mut self, mode: &AddressingMode) {
+        self.read_modify_write(mode, |cpu, value| {
+            let result = cpu.rotate_right(value);
+            cpu.add_with_carry(result);
+            result
+        });
+    }
+    fn ldy(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        self.register_y = Wrapping(value);
+        self.update_zero_and_negative_flags(self.register_y);
+    }
+    fn ldx(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let value = self.mem_read(addr);
+        self.register_x = Wrapping(value);
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+    fn tax(&mut self) {
+        self.register_x = self.register_a;
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+    fn txa(&mut self) {
+        self.register_a = self.register_x;
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+    fn tay(&mut self) {
+        self.register_y = self.register_a;
+        self.update_zero_and_negative_flags(self.register_y);
+    }
+    fn tya(&mut self) {
+        self.register_a = self.register_y;
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+    fn tsx(&mut self) {
+        self.register_x = Wrapping(self.stack_pointer);
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+    /* the only transfer that leaves the flags alone */
+    fn txs(&mut self) {
+        self.stack_pointer = self.register_x.0;
+    }
+    fn inx(&mut self) {
+        self.register_x += Wrapping(1);
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+    fn iny(&mut self) {
+        self.register_y += Wrapping(1);
+        self.update_zero_and_negative_flags(self.register_y);
+    }
+    fn dex(&mut self) {
+        self.register_x -= Wrapping(1);
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+    fn dey(&mut self) {
+        self.register_y -= Wrapping(1);
+        self.update_zero_and_negative_flags(self.register_y);
+    }
+    fn pha(&mut self) {
+        self.push8(self.register_a.0);
+    }
+    fn pla(&mut self) {
+        self.register_a = Wrapping(self.pop8());
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+    /* PHP always pushes B and bit 5 set */
+    fn php(&mut self) {
+        self.push8((self.status | StatusFlags::BREAK | StatusFlags::UNUSED).bits());
+    }
+    /* and PLP ignores them, B reads as clear and bit 5 as set */
+    fn plp(&mut self) {
+        self.status =
+            StatusFlags::from_bits(self.pop8()) & !StatusFlags::BREAK | StatusFlags::UNUSED;
+    }
+    fn sta(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        self.mem_write(addr, self.register_a.0);
+    }
+    fn stx(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        self.mem_write(addr, self.register_x.0);
+    }
+    fn sty(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        self.mem_write(addr, self.register_y.0);
+    }
+    /* bits 7-6 of a branch opcode pick the flag, bit 5 says whether it has to be set */
+    fn branch_condition(&self, code: u8) -> bool {
+        let flag = [
+            StatusFlags::NEGATIVE,
+            StatusFlags::OVERFLOW,
+            StatusFlags::CARRY,
+            StatusFlags::ZERO,
+        ][(code >> 6) as usize];
+        self.status.get(flag) == (code & 0x20 != 0)
+    }
+    /* a taken branch costs a cycle, and another if it lands on a different page */
+    fn branch(&mut self, mode: &AddressingMode, taken: bool) {
+        if taken {
+            let next = self.program_counter.wrapping_add(1);
+            self.program_counter = self.get_operand_address(mode);
+            self.cycles += 1 + (next & 0xFF00 != self.program_counter & 0xFF00) as u64;
+        } else {
+            self.program_counter = self.program_counter.wrapping_add(1);
+        }
+    }
+    fn jmp(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        self.program_counter = addr;
+    }
+    fn jsr(&mut self, mode: &AddressingMode) {
+        /* push the address - 1 onto the stack before transferring control
+         * to the following address
+         */
+        self.diagnose_jsr();
+        let addr = self.get_operand_address(mode);
+        /* the last byte of the JSR itself */
+        self.push16(self.program_counter.wrapping_add(1));
+        self.program_counter = addr;
+    }
+    /* pushes the address after BRK's padding byte and the status with B set */
+    fn brk(&mut self) {
+        let return_addr = self.program_counter.wrapping_add(1);
+        self.push16(return_addr);
+        self.push8((self.status | StatusFlags::BREAK | StatusFlags::UNUSED).bits());
+        self.status.insert(StatusFlags::INTERRUPT_DISABLE);
+        self.program_counter = self.mem_read_u16(0xFFFE);
+    }
+    /* the status as PLP pulls it, then the address as pushed, without RTS's + 1 */
+    fn rti(&mut self) {
+        self.status =
+            StatusFlags::from_bits(self.pop8()) & !StatusFlags::BREAK | StatusFlags::UNUSED;
+        self.program_counter = self.pop16();
+    }
+    fn rts(&mut self) {
+        self.diagnose_rts();
+        self.program_counter = self.pop16().wrapping_add(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_load() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xe8; 0x8000]).unwrap();
+        assert_eq!(cpu.program_counter, ORIGIN);
+        assert_eq!(cpu.peek(0xFFFB), 0xe8);
+        assert!(cpu.load(vec![0xe8; 0x8001]).is_err());
+
+        /* a 16KB cartridge holds half as much */
+        let mut cpu = CPU::new();
+        cpu.load_rom(&rom::Rom::from_prg(vec![0; 0x4000])).unwrap();
+        assert_eq!(
+            cpu.load(vec![0xe8; 0x4001]),
+            Err("a 16385 byte program doesn't fit in 16384 bytes of PRG ROM".to_string())
+        );
+    }
+
+    #[test]
+    fn test_0xa9_lda_immidiate_load_data() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0x05, 0x00]).unwrap();
+        assert_eq!(cpu.register_a.0, 0x05);
+        assert!(!cpu.status.get(StatusFlags::ZERO));
+        assert!(!cpu.status.get(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_ldx_immidiate_load_data() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa0, 0x05, 0x00]).unwrap();
+        assert_eq!(cpu.register_y.0, 0x05);
+        assert!(!cpu.status.get(StatusFlags::ZERO));
+        assert!(!cpu.status.get(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_ldy_immidiate_load_data() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa2, 0x05, 0x00]).unwrap();
+        assert_eq!(cpu.register_x.0, 0x05);
+        assert!(!cpu.status.get(StatusFlags::ZERO));
+        assert!(!cpu.status.get(StatusFlags::NEGATIVE));
+    }
+
+    #[test]
+    fn test_0xa9_lda_zero_flag() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(vec![0xa9, 0x00, 0x00]).unwrap();
+        assert!(cpu.status.get(StatusFlags::ZERO));
+    }
+
+    #[test]
+    fn test_5_ops_working_together() {
+        let mut cpu = CPU::new();
+        cpu.load_and_run(asm! { LDA #$c0  TAX  INX  BRK }).unwrap();
+        assert_eq!(cpu.register_x.0, 0xc1)
+    }
+
+    #[test]
+    fn test_combined_ld_st() {
+        let mut cpu = CPU::new();
+
+        cpu.load_and_run(asm! {
+            LDY #$01
+            LDA #$03
+            STA $01
+            LDA #$07
+            STA $02
+            LDX #$0a
+            STX $0704
+            LDA ($01),Y
+            BRK
+        })
+        .unwrap();
+
+        assert_eq!(cpu.register_a.0, 0x0a);
+        assert_eq!(cpu.register_y.0, 0x01);
+        assert_eq!(cpu.register_x.0, 0x0a);
+    }
+
+    #[test]
+    fn test_0xaa_tax_move_a_to_x() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xaa, 0x00]).unwrap();
+        cpu.register_a = Wrapping(10);
+        cpu.run();
+
+        assert_eq!(cpu.register_x, Wrapping(10))
+    }
+
+    #[test]
+    fn test_txa() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0x8a, 0x00]).unwrap();
+        cpu.register_x = Wrapping(10);
+        cpu.run();
+
+        assert_eq!(cpu.register_a, Wrapping(10))
+    }
+
+    #[test]
+    fn test_inx_overflow() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xe8, 0xe8, 0x00]).unwrap();
+        cpu.register_x = Wrapping(0xff);
+        cpu.run();
+
+        assert_eq!(cpu.register_x, Wrapping(1))
+    }
+
+    #[test]
+    fn test_lda_from_memory() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x55);
+
+        cpu.load_and_run(vec![0xa5, 0x10, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a.0, 0x55);
+    }
+
+    #[test]
+    fn test_lda_open_bus() {
+        let mut cpu = CPU::new();
+        /* nothing answers at $5000, the operand's high byte is still on the bus */
+        cpu.load_and_run(vec![0xad, 0x00, 0x50, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a.0, 0x50);
+    }
+
+    #[test]
+    fn test_lda_from_memory_x0() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x55);
+
+        cpu.load_and_run(vec![0xa5, 0x10, 0x00]).unwrap();
+
+        assert_eq!(cpu.register_a.0, 0x55);
+    }
+
+    #[test]
+    fn test_lda_from_memory_x() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x19, 0x55);
+
+        cpu.init(vec![0xb5, 0x10, 0x00]).unwrap();
+        cpu.register_x = Wrapping(9);
+        cpu.run();
+
+        assert_eq!(cpu.register_a.0, 0x55);
+    }
+
+    #[test]
+    fn test_lda_abs() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x55);
+
+        cpu.init(vec![0xad, 0x10, 0x00, 0x00]).unwrap();
+        cpu.register_x = Wrapping(9);
+        cpu.run();
+
+        assert_eq!(cpu.register_a.0, 0x55);
+    }
+
+    #[test]
+    fn test_lda_abs_x() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x19, 0x55);
+
+        cpu.init(vec![0xbd, 0x10, 0x00, 0x00]).unwrap();
+        cpu.register_x = Wrapping(9);
+        cpu.run();
+
+        assert_eq!(cpu.register_a.0, 0x55);
+    }
+
+    #[test]
+    fn test_lda_abs_y() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x19, 0x55);
+
+        cpu.init(vec![0xb9, 0x10, 0x00, 0x00]).unwrap();
+        cpu.register_y = Wrapping(9);
+        cpu.run();
+
+        assert_eq!(cpu.register_a.0, 0x55);
+    }
+
+    #[test]
+    fn test_lda_ind_x() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x0A, 0x32);
+        cpu.mem_write(0x32, 0xFF);
+
+        cpu.init(vec![0xa1, 0x01, 0x00]).unwrap();
+        cpu.register_x = Wrapping(9);
+        cpu.run();
+
+        assert_eq!(cpu.register_a.0, 0xFF);
+    }
+
+    #[test]
+    fn test_lda_ind_y0() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x00, 0x32);
+        cpu.mem_write(0x32, 0xFE);
+
+        cpu.init(vec![0xb1, 0x00, 0x00]).unwrap();
+        cpu.run();
+
+        assert_eq!(cpu.register_a.0, 0xFE);
+    }
+
+    #[test]
+    fn test_lda_ind_y() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x01, 0x03);
+        cpu.mem_write(0x02, 0x07);
+        cpu.mem_write(0x0704, 0x0a);
+
+        cpu.init(vec![0xb1, 0x01, 0x00]).unwrap();
+        cpu.register_y = Wrapping(0x01);
+        cpu.run();
+        assert_eq!(cpu.register_a.0, 0x0a);
+    }
+
+    #[test]
+    fn test_sta_zp() {
+        let mut cpu = CPU::new();
+
+        cpu.init(vec![0x85, 0x01, 0x00]).unwrap();
+        cpu.register_a = Wrapping(0xff);
+        cpu.run();
+        assert_eq!(cpu.mem_read(0x01), 0xff);
+    }
+
+    #[test]
+    fn test_sta_zp_x() {
+        let mut cpu = CPU::new();
+
+        cpu.init(vec![0x95, 0x01, 0x00]).unwrap();
+        cpu.register_a = Wrapping(0xff);
+        cpu.register_x = Wrapping(0x01);
+        cpu.run();
+        assert_eq!(cpu.mem_read(0x02), 0xff);
+    }
+
+    #[test]
+    fn test_stx_abs() {
+        // TODO: this tests technically tests absolute, but we should try with
+        // two bytes
+        let mut cpu = CPU::new();
+
+        cpu.init(vec![0x8e, 0x01, 0x00]).unwrap();
+        cpu.register_x = Wrapping(0xff);
+        cpu.run();
+        assert_eq!(cpu.mem_read(0x01), 0xff);
+    }
+
+    #[test]
+    fn test_stx_zp() {
+        let mut cpu = CPU::new();
+
+        cpu.init(vec![0x86, 0x01, 0x00]).unwrap();
+        cpu.register_x = Wrapping(0xff);
+        cpu.run();
+        assert_eq!(cpu.mem_read(0x01), 0xff);
+    }
+
+    #[test]
+    fn test_stx_zp_y() {
+        let mut cpu = CPU::new();
+
+        cpu.init(vec![0x96, 0x01, 0x00]).unwrap();
+        cpu.register_x = Wrapping(0xff);
+        cpu.register_y = Wrapping(0x01);
+        cpu.run();
+        assert_eq!(cpu.mem_read(0x02), 0xff);
+    }
+    /* STY */
+    #[test]
+    fn test_sty_abs() {
+        // TODO: this tests technically tests absolute, but we should try with
+        // two bytes
+        let mut cpu = CPU::new();
+
+        cpu.init(vec![0x8c, 0x01, 0x00]).unwrap();
+        cpu.register_y = Wrapping(0xff);
+        cpu.run();
+        assert_eq!(cpu.mem_read(0x01), 0xff);
+    }
+
+    #[test]
+    fn test_sty_zp() {
+        let mut cpu = CPU::new();
+
+        cpu.init(vec![0x84, 0x01, 0x00]).unwrap();
+        cpu.register_y = Wrapping(0xff);
+        cpu.run();
+        assert_eq!(cpu.mem_read(0x01), 0xff);
+    }
+
+    #[test]
+    fn test_sty_zp_x() {
+        let mut cpu = CPU::new();
+
+        cpu.init(vec![0x94, 0x01, 0x00]).unwrap();
+        cpu.register_y = Wrapping(0xff);
+        cpu.register_x = Wrapping(0x01);
+        cpu.run();
+        assert_eq!(cpu.mem_read(0x02), 0xff);
+    }
+
+    #[test]
+    fn test_rts_return_address() {
+        let mut cpu = CPU::new();
+        /* what a JSR at $1232 leaves on the stack, low byte on top */
+        cpu.stack_pointer = 0xef;
+        cpu.mem_write(0x01f0, 0x34);
+        cpu.mem_write(0x01f1, 0x12);
+        cpu.rts();
+        assert_eq!(cpu.program_counter, 0x1235);
+    }
+
+    #[test]
+    fn test_jmp_abs() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0x4c, 0x01, 0x00, 0x00]).unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x01);
+    }
+
+    #[test]
+    fn test_jmp_indirect() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0x6c, 0x01, 0x00, 0x00]).unwrap();
+        cpu.mem_write(0x01, 0x32);
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x32);
+    }
+
+    #[test]
+    fn test_jmp_indirect_page_bug() {
+        let mut cpu = CPU::new();
+        cpu.init(asm! { JMP ($02FF) }).unwrap();
+        cpu.poke(0x02FF, 0x34);
+        cpu.poke(0x0200, 0x12);
+        cpu.poke(0x0300, 0x56);
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x1234);
+
+        cpu.strict_jmp_indirect = true;
+        cpu.init(asm! { JMP ($02FF) }).unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x5634);
+
+        /* a pointer anywhere else isn't affected, and is a full 16 bits */
+        cpu.strict_jmp_indirect = false;
+        cpu.init(asm! { JMP ($0310) }).unwrap();
+        cpu.poke(0x0310, 0xcd);
+        cpu.poke(0x0311, 0xab);
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0xabcd);
+    }
+
+    #[test]
+    fn test_adc_carry_and_overflow() {
+        /* (A, operand, carry in) -> (result, carry out, overflow) */
+        for (a, operand, carry, result, carry_out, overflow) in [
+            (0x01, 0x01, false, 0x02, false, false),
+            (0x01, 0x01, true, 0x03, false, false),
+            (0x7f, 0x01, false, 0x80, false, true),
+            (0x80, 0xff, false, 0x7f, true, true),
+            (0xff, 0x01, false, 0x00, true, false),
+            (0xff, 0x00, true, 0x00, true, false),
+            (0x80, 0x80, false, 0x00, true, true),
+            (0x50, 0xd0, false, 0x20, true, false),
+        ] {
+            let mut cpu = CPU::new();
+            cpu.init(asm! { ADC $10  BRK }).unwrap();
+            cpu.poke(0x10, operand);
+            cpu.register_a = Wrapping(a);
+            cpu.status = StatusFlags::from_bits(carry as u8);
+            cpu.run();
+            let case = format!("{:02X} + {:02X} + {}", a, operand, carry as u8);
+            assert_eq!(cpu.register_a.0, result, "{}", case);
+            assert_eq!(
+                cpu.status.get(StatusFlags::CARRY),
+                carry_out,
+                "C for {}",
+                case
+            );
+            assert_eq!(
+                cpu.status.get(StatusFlags::OVERFLOW),
+                overflow,
+                "V for {}",
+                case
+            );
+            assert_eq!(
+                cpu.status.get(StatusFlags::ZERO),
+                result == 0,
+                "Z for {}",
+                case
+            );
+            assert_eq!(
+                cpu.status.get(StatusFlags::NEGATIVE),
+                result >= 0x80,
+                "N for {}",
+                case
+            );
+        }
+    }
+
+    #[test]
+    fn test_adc_addressing_modes() {
+        let mut cpu = CPU::new();
+        cpu.poke(0x10, 0x01);
+        cpu.poke(0x11, 0x02);
+        cpu.poke(0x0210, 0x04);
+        cpu.poke(0x0212, 0x08);
+        cpu.poke(0x20, 0x10);
+        cpu.poke(0x21, 0x02);
+        cpu.poke(0x0214, 0x10);
+        cpu.init(asm! {
+            ADC #$20
+            ADC $10
+            ADC $10,X
+            ADC $0210
+            ADC $0210,X
+            ADC ($1f,X)
+            ADC ($20),Y
+            ADC $0210,Y
+            BRK
+        })
+        .unwrap();
+        cpu.register_x = Wrapping(0x01);
+        cpu.register_y = Wrapping(0x04);
+        cpu.run();
+        /* 20 + 01 + 02 + 04 + 00 + 04 + 10 + 10 */
+        assert_eq!(cpu.register_a.0, 0x4b);
+    }
+
+    #[test]
+    fn test_sbc_borrow_and_overflow() {
+        /* (A, operand, carry in) -> (result, carry out, overflow) */
+        for (a, operand, carry, result, carry_out, overflow) in [
+            (0x05, 0x03, true, 0x02, true, false),
+            (0x05, 0x03, false, 0x01, true, false),
+            (0x03, 0x05, true, 0xfe, false, false),
+            (0x00, 0x00, false, 0xff, false, false),
+            (0x80, 0x01, true, 0x7f, true, true),
+            (0x7f, 0xff, true, 0x80, false, true),
+            (0x50, 0x50, true, 0x00, true, false),
+        ] {
+            let mut cpu = CPU::new();
+            cpu.init(asm! { SBC #$00  BRK }).unwrap();
+            cpu.poke(0x8001, operand);
+            cpu.register_a = Wrapping(a);
+            cpu.status = StatusFlags::from_bits(carry as u8);
+            cpu.run();
+            let case = format!("{:02X} - {:02X} with C={}", a, operand, carry as u8);
+            assert_eq!(cpu.register_a.0, result, "{}", case);
+            assert_eq!(
+                cpu.status.get(StatusFlags::CARRY),
+                carry_out,
+                "C for {}",
+                case
+            );
+            assert_eq!(
+                cpu.status.get(StatusFlags::OVERFLOW),
+                overflow,
+                "V for {}",
+                case
+            );
+            assert_eq!(
+                cpu.status.get(StatusFlags::ZERO),
+                result == 0,
+                "Z for {}",
+                case
+            );
+            assert_eq!(
+                cpu.status.get(StatusFlags::NEGATIVE),
+                result >= 0x80,
+                "N for {}",
+                case
+            );
+        }
+    }
+
+    #[test]
+    fn test_sbc_addressing_modes() {
+        let mut cpu = CPU::new();
+        cpu.poke(0x10, 0x01);
+        cpu.poke(0x11, 0x02);
+        cpu.poke(0x0210, 0x04);
+        cpu.poke(0x0211, 0x08);
+        cpu.poke(0x20, 0x10);
+        cpu.poke(0x21, 0x02);
+        cpu.poke(0x0214, 0x10);
+        cpu.init(asm! {
+            SBC #$20
+            SBC $10
+            SBC $10,X
+            SBC $0210
+            SBC $0210,X
+            SBC ($1f,X)
+            SBC ($20),Y
+            SBC $0210,Y
+            BRK
+        })
+        .unwrap();
+        cpu.register_a = Wrapping(0xff);
+        cpu.register_x = Wrapping(0x01);
+        cpu.register_y = Wrapping(0x04);
+        cpu.status = StatusFlags::CARRY;
+        cpu.run();
+        /* ff - 20 - 01 - 02 - 04 - 08 - 04 - 10 - 10, never borrowing */
+        assert_eq!(cpu.register_a.0, 0xac);
+        assert!(cpu.status.get(StatusFlags::CARRY));
+    }
+
+    #[test]
+    fn test_logical_ops() {
+        let mut cpu = CPU::new();
+        cpu.init(asm! { LDA #$F0  AND #$3C  BRK }).unwrap();
+        cpu.run();
+        assert_eq!(cpu.register_a.0, 0x30);
+        assert_eq!(
+            cpu.status & (StatusFlags::NEGATIVE | StatusFlags::ZERO),
+            StatusFlags::empty()
+        );
+
+        cpu.init(asm! { LDA #$F0  AND #$0F  BRK }).unwrap();
+        cpu.run();
+        assert_eq!(cpu.register_a.0, 0x00);
+        assert_eq!(cpu.status & StatusFlags::ZERO, StatusFlags::ZERO);
+
+        cpu.init(asm! { LDA #$01  ORA #$80  BRK }).unwrap();
+        cpu.run();
+        assert_eq!(cpu.register_a.0, 0x81);
+        assert_eq!(cpu.status & StatusFlags::NEGATIVE, StatusFlags::NEGATIVE);
+
+        cpu.init(asm! { LDA #$FF  EOR #$FF  BRK }).unwrap();
+        cpu.run();
+        assert_eq!(cpu.register_a.0, 0x00);
+        assert_eq!(cpu.status & StatusFlags::ZERO, StatusFlags::ZERO);
+    }
+
+    #[test]
+    fn test_logical_ops_addressing_modes() {
+        let mut cpu = CPU::new();
+        cpu.poke(0x10, 0x01);
+        cpu.poke(0x11, 0x02);
+        cpu.poke(0x0210, 0x04);
+        cpu.poke(0x0211, 0x08);
+        cpu.poke(0x20, 0x10);
+        cpu.poke(0x21, 0x02);
+        cpu.poke(0x0214, 0x10);
+        cpu.init(asm! {
+            ORA #$20
+            ORA $10
+            ORA $10,X
+            ORA $0210
+            ORA $0210,X
+            ORA ($1f,X)
+            ORA ($20),Y
+            ORA $0210,Y
+            BRK
+        })
+        .unwrap();
+        cpu.register_x = Wrapping(0x01);
+        cpu.register_y = Wrapping(0x04);
+        cpu.run();
+        assert_eq!(cpu.register_a.0, 0x3f);
+
+        cpu.init(asm!(
+            "AND #$3E
+             AND $11,X
+             EOR $10
+             EOR $0210
+             AND $0211
+             EOR ($1f,X)
+             EOR ($20),Y
+             AND $0210,Y
+             EOR $0210,X
+             BRK"
+        ))
+        .unwrap();
+        cpu.register_a = Wrapping(0xff);
+        cpu.register_x = Wrapping(0x01);
+        cpu.register_y = Wrapping(0x04);
+        cpu.run();
+        /* 3e & 00 = 00, ^ 01 ^ 04 = 05, & 08 = 00, ^ 04 ^ 10 = 14, & 10 = 10, ^ 08 = 18 */
+        assert_eq!(cpu.register_a.0, 0x18);
+    }
+
+    #[test]
+    fn test_shifts() {
+        let mut cpu = CPU::new();
+        cpu.init(asm! { LDA #$81  ASL A  BRK }).unwrap();
+        cpu.run();
+        assert_eq!(cpu.register_a.0, 0x02);
+        assert_eq!(
+            cpu.status & (StatusFlags::NEGATIVE | StatusFlags::ZERO | StatusFlags::CARRY),
+            StatusFlags::CARRY
+        );
+
+        cpu.init(asm! { LDA #$40  ASL  BRK }).unwrap();
+        cpu.run();
+        assert_eq!(cpu.register_a.0, 0x80);
+        assert_eq!(
+            cpu.status & (StatusFlags::NEGATIVE | StatusFlags::ZERO | StatusFlags::CARRY),
+            StatusFlags::NEGATIVE
+        );
+
+        cpu.init(asm! { LDA #$01  LSR A  BRK }).unwrap();
+        cpu.run();
+        assert_eq!(cpu.register_a.0, 0x00);
+        assert_eq!(
+            cpu.status & (StatusFlags::NEGATIVE | StatusFlags::ZERO | StatusFlags::CARRY),
+            (StatusFlags::ZERO | StatusFlags::CARRY)
+        );
+
+        cpu.init(asm! { LDA #$FE  LSR A  BRK }).unwrap();
+        cpu.run();
+        assert_eq!(cpu.register_a.0, 0x7f);
+        assert_eq!(
+            cpu.status & (StatusFlags::NEGATIVE | StatusFlags::ZERO | StatusFlags::CARRY),
+            StatusFlags::empty()
+        );
+    }
+
+    #[test]
+    fn test_shifts_in_memory() {
+        let mut cpu = CPU::new();
+        cpu.poke(0x10, 0x01);
+        cpu.poke(0x11, 0x02);
+        cpu.poke(0x0210, 0x04);
+        cpu.poke(0x0211, 0x08);
+        cpu.init(asm!(
+            "ASL $10
+             ASL $10,X
+             LSR $0210
+             LSR $0210,X
+             ASL $1E,X
+             BRK"
+        ))
+        .unwrap();
+        cpu.register_a = Wrapping(0x55);
+        cpu.register_x = Wrapping(0x01);
+        cpu.poke(0x1f, 0x80);
+        cpu.run();
+        assert_eq!(cpu.peek(0x10), 0x02);
+        assert_eq!(cpu.peek(0x11), 0x04);
+        assert_eq!(cpu.peek(0x0210), 0x02);
+        assert_eq!(cpu.peek(0x0211), 0x04);
+        assert_eq!(cpu.peek(0x1f), 0x00);
+        assert_eq!(
+            cpu.status & (StatusFlags::ZERO | StatusFlags::CARRY),
+            (StatusFlags::ZERO | StatusFlags::CARRY)
+        );
+        assert_eq!(cpu.register_a.0, 0x55);
+    }
+
+    #[test]
+    fn test_rotates_carry() {
+        /* (opcode, A, carry in) -> (result, carry out) */
+        for (opcode, a, carry, result, carry_out) in [
+            (0x2A, 0x80, false, 0x00, true),
+            (0x2A, 0x80, true, 0x01, true),
+            (0x2A, 0x41, true, 0x83, false),
+            (0x6A, 0x01, false, 0x00, true),
+            (0x6A, 0x01, true, 0x80, true),
+            (0x6A, 0x82, true, 0xc1, false),
+        ] {
+            let mut cpu = CPU::new();
+            cpu.init(vec![opcode, 0x00]).unwrap();
+            cpu.register_a = Wrapping(a);
+            cpu.status = StatusFlags::from_bits(carry as u8);
+            cpu.run();
+            let case = format!("{:02X} on {:02X} with C={}", opcode, a, carry as u8);
+            assert_eq!(cpu.register_a.0, result, "{}", case);
+            assert_eq!(
+                cpu.status.get(StatusFlags::CARRY),
+                carry_out,
+                "C for {}",
+                case
+            );
+            assert_eq!(
+                cpu.status.get(StatusFlags::ZERO),
+                result == 0,
+                "Z for {}",
+                case
+            );
+            assert_eq!(
+                cpu.status.get(StatusFlags::NEGATIVE),
+                result >= 0x80,
+                "N for {}",
+                case
+            );
+        }
+    }
+
+    #[test]
+    fn test_rotates_in_memory() {
+        let mut cpu = CPU::new();
+        cpu.poke(0x10, 0x81);
+        cpu.poke(0x11, 0x40);
+        cpu.poke(0x0210, 0x01);
+        cpu.poke(0x0211, 0x02);
+        cpu.init(asm! {
+            ROL $10
+            ROL $10,X
+            ROR $0210
+            ROR $0210,X
+            BRK
+        })
+        .unwrap();
+        cpu.register_x = Wrapping(0x01);
+        cpu.run();
+        /* each rotate takes the carry the one before it left */
+        assert_eq!(cpu.peek(0x10), 0x02);
+        assert_eq!(cpu.peek(0x11), 0x81);
+        assert_eq!(cpu.peek(0x0210), 0x00);
+        assert_eq!(cpu.peek(0x0211), 0x81);
+        assert!(!cpu.status.get(StatusFlags::CARRY));
+    }
+
+    #[test]
+    fn test_branches() {
+        /* (opcode, flag that takes it, whether the flag is set or clear) */
+        for (opcode, flag, set) in [
+            (0x10, StatusFlags::NEGATIVE, false),
+            (0x30, StatusFlags::NEGATIVE, true),
+            (0x50, StatusFlags::OVERFLOW, false),
+            (0x70, StatusFlags::OVERFLOW, true),
+            (0x90, StatusFlags::CARRY, false),
+            (0xB0, StatusFlags::CARRY, true),
+            (0xD0, StatusFlags::ZERO, false),
+            (0xF0, StatusFlags::ZERO, true),
+        ] {
+            for taken in [true, false] {
+                let mut cpu = CPU::new();
+                /* branch over the INX when taken */
+                cpu.init(vec![opcode, 0x01, 0xE8, 0x00]).unwrap();
+                cpu.status = if set == taken { flag } else { !flag };
+                cpu.run();
+                let expected = if taken { 0 } else { 1 };
+                assert_eq!(
+                    cpu.register_x.0, expected,
+                    "{:02X} taken: {}",
+                    opcode, taken
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_branch_backwards() {
+        let mut cpu = CPU::new();
+        cpu.init(asm! {
+            LDX #$FB
+        loop:
+            INX
+            BNE loop
+            BRK
+        })
+        .unwrap();
+        cpu.run();
+        assert_eq!(cpu.register_x.0, 0);
+        assert!(cpu.status.get(StatusFlags::ZERO));
+    }
+
+    #[test]
+    fn test_compare_flags() {
+        /* (register, operand) -> (C, Z, N) */
+        for (register, operand, carry, zero, negative) in [
+            (0x10, 0x10, true, true, false),
+            (0x20, 0x10, true, false, false),
+            (0x10, 0x20, false, false, true),
+            (0x00, 0x01, false, false, true),
+            (0xff, 0x00, true, false, true),
+            (0x80, 0x01, true, false, false),
+        ] {
+            for opcode in [0xC9, 0xE0, 0xC0] {
+                let mut cpu = CPU::new();
+                cpu.init(vec![opcode, operand, 0x00]).unwrap();
+                cpu.register_a = Wrapping(register);
+                cpu.register_x = Wrapping(register);
+                cpu.register_y = Wrapping(register);
+                cpu.run();
+                let case = format!("{:02X} with {:02X} and {:02X}", opcode, register, operand);
+                assert_eq!(cpu.status.get(StatusFlags::CARRY), carry, "C for {}", case);
+                assert_eq!(cpu.status.get(StatusFlags::ZERO), zero, "Z for {}", case);
+                assert_eq!(
+                    cpu.status.get(StatusFlags::NEGATIVE),
+                    negative,
+                    "N for {}",
+                    case
+                );
+                assert_eq!(cpu.register_a.0, register);
+                assert_eq!(cpu.register_x.0, register);
+                assert_eq!(cpu.register_y.0, register);
+            }
+        }
+    }
+
+    #[test]
+    fn test_compare_addressing_modes() {
+        let mut cpu = CPU::new();
+        cpu.poke(0x10, 0x05);
+        cpu.poke(0x11, 0x01);
+        cpu.poke(0x12, 0x04);
+        cpu.poke(0x20, 0x10);
+        cpu.poke(0x21, 0x02);
+        cpu.poke(0x0210, 0x05);
+        cpu.poke(0x0214, 0x05);
+        cpu.poke(0x0300, 0x05);
+        cpu.poke(0x0301, 0x01);
+        cpu.poke(0x0302, 0x04);
+        /* each compare is equal, so every BNE falls through to the next */
+        cpu.init(asm!(
+            "CMP $10
+             BNE fail
+             CMP $0F,X
+             BNE fail
+             CMP $0300
+             BNE fail
+             CMP $02FF,X
+             BNE fail
+             CMP $02FC,Y
+             BNE fail
+             CMP ($1F,X)
+             BNE fail
+             CMP ($20),Y
+             BNE fail
+             CPX #$01
+             BNE fail
+             CPX $11
+             BNE fail
+             CPX $0301
+             BNE fail
+             CPY #$04
+             BNE fail
+             CPY $12
+             BNE fail
+             CPY $0302
+             BNE fail
+             LDA #$01
+             BRK
+         fail:
+             BRK"
+        ))
+        .unwrap();
+        cpu.register_a = Wrapping(0x05);
+        cpu.register_x = Wrapping(0x01);
+        cpu.register_y = Wrapping(0x04);
+        cpu.run();
+        assert_eq!(cpu.register_a.0, 0x01);
+    }
+
+    #[test]
+    fn test_register_increments_wrap() {
+        let mut cpu = CPU::new();
+        cpu.init(asm! { INY  DEX  BRK }).unwrap();
+        cpu.register_x = Wrapping(0x00);
+        cpu.register_y = Wrapping(0xff);
+        cpu.run();
+        assert_eq!(cpu.register_y.0, 0x00);
+        assert_eq!(cpu.register_x.0, 0xff);
+        /* flags are from the DEX */
+        assert_eq!(
+            cpu.status & (StatusFlags::NEGATIVE | StatusFlags::ZERO),
+            StatusFlags::NEGATIVE
+        );
+
+        cpu.init(asm! { DEY  BRK }).unwrap();
+        cpu.register_y = Wrapping(0x01);
+        cpu.run();
+        assert_eq!(cpu.register_y.0, 0x00);
+        assert_eq!(
+            cpu.status & (StatusFlags::NEGATIVE | StatusFlags::ZERO),
+            StatusFlags::ZERO
+        );
+
+        cpu.init(asm! { DEY  BRK }).unwrap();
+        cpu.register_y = Wrapping(0x00);
+        cpu.run();
+        assert_eq!(cpu.register_y.0, 0xff);
+    }
+
+    #[test]
+    fn test_memory_increments_wrap() {
+        let mut cpu = CPU::new();
+        cpu.poke(0x10, 0xff);
+        cpu.poke(0x11, 0x00);
+        cpu.poke(0x0210, 0x7f);
+        cpu.poke(0x0211, 0x01);
+        cpu.init(asm! {
+            INC $10
+            DEC $10,X
+            INC $0210
+            DEC $0210,X
+            BRK
+        })
+        .unwrap();
+        cpu.register_x = Wrapping(0x01);
+        cpu.run();
+        assert_eq!(cpu.peek(0x10), 0x00);
+        assert_eq!(cpu.peek(0x11), 0xff);
+        assert_eq!(cpu.peek(0x0210), 0x80);
+        assert_eq!(cpu.peek(0x0211), 0x00);
+        assert_eq!(
+            cpu.status & (StatusFlags::NEGATIVE | StatusFlags::ZERO),
+            StatusFlags::ZERO
+        );
+
+        cpu.poke(0x0300, 0xff);
+        cpu.poke(0x0301, 0x00);
+        cpu.init(asm! { INC $0300  BRK }).unwrap();
+        cpu.run();
+        assert_eq!(cpu.peek(0x0300), 0x00);
+        assert_eq!(
+            cpu.status & (StatusFlags::NEGATIVE | StatusFlags::ZERO),
+            StatusFlags::ZERO
+        );
+        cpu.init(asm! { DEC $02FF,X  BRK }).unwrap();
+        cpu.register_x = Wrapping(0x02);
+        cpu.run();
+        assert_eq!(cpu.peek(0x0301), 0xff);
+        assert_eq!(
+            cpu.status & (StatusFlags::NEGATIVE | StatusFlags::ZERO),
+            StatusFlags::NEGATIVE
+        );
+    }
+
+    #[test]
+    fn test_pha_pla() {
+        let mut cpu = CPU::new();
+        cpu.init(asm! {
+            LDA #$80
+            PHA
+            LDA #$01
+            PHA
+            LDA #$00
+            PLA
+            TAX
+            PLA
+            BRK
+        })
+        .unwrap();
+        cpu.run();
+        assert_eq!(cpu.register_x.0, 0x01);
+        assert_eq!(cpu.register_a.0, 0x80);
+        assert_eq!(
+            cpu.status & (StatusFlags::NEGATIVE | StatusFlags::ZERO),
+            StatusFlags::NEGATIVE
+        );
+        /* all that's left on the stack is what BRK pushed */
+        assert_eq!(cpu.stack_pointer, 0xFC);
+    }
+
+    #[test]
+    fn test_php_plp() {
+        let mut cpu = CPU::new();
+        /* what PHP pushes has B and bit 5 set */
+        cpu.init(asm! { PHP  PLA  BRK }).unwrap();
+        cpu.status = StatusFlags::CARRY | StatusFlags::NEGATIVE;
+        cpu.run();
+        assert_eq!(cpu.register_a.0, 0b1011_0001);
+
+        /* PLP drops B and sets bit 5 whatever was on the stack */
+        cpu.init(asm! { LDA #$DF  PHA  PLP  BRK }).unwrap();
+        cpu.run();
+        assert_eq!(cpu.status, !StatusFlags::BREAK);
+
+        cpu.init(asm! { PHP  LDA #$00  PLP }).unwrap();
+        cpu.status = StatusFlags::CARRY | StatusFlags::OVERFLOW;
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(
+            cpu.status,
+            StatusFlags::CARRY | StatusFlags::OVERFLOW | StatusFlags::UNUSED
+        );
+    }
+
+    #[test]
+    fn test_flag_instructions() {
+        for (source, before, after) in [
+            ("CLC", 0xff, !StatusFlags::CARRY),
+            ("SEC", 0x00, StatusFlags::CARRY),
+            ("CLI", 0xff, !StatusFlags::INTERRUPT_DISABLE),
+            ("SEI", 0x00, StatusFlags::INTERRUPT_DISABLE),
+            ("CLV", 0xff, !StatusFlags::OVERFLOW),
+            ("CLD", 0xff, !StatusFlags::DECIMAL),
+            ("SED", 0x00, StatusFlags::DECIMAL),
+        ] {
+            let mut cpu = CPU::new();
+            cpu.init(assembler::assemble(source).unwrap()).unwrap();
+            cpu.status = StatusFlags::from_bits(before);
+            cpu.step().unwrap();
+            assert_eq!(cpu.status, after, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_transfers() {
+        let mut cpu = CPU::new();
+        cpu.init(asm! { LDA #$80  TAY  LDA #$00  TYA  BRK })
+            .unwrap();
+        cpu.run();
+        assert_eq!(cpu.register_y.0, 0x80);
+        assert_eq!(cpu.register_a.0, 0x80);
+        assert_eq!(
+            cpu.status & (StatusFlags::NEGATIVE | StatusFlags::ZERO),
+            StatusFlags::NEGATIVE
+        );
+
+        cpu.init(asm! { TSX  BRK }).unwrap();
+        cpu.stack_pointer = 0x00;
+        cpu.run();
+        assert_eq!(cpu.register_x.0, 0x00);
+        assert_eq!(
+            cpu.status & (StatusFlags::NEGATIVE | StatusFlags::ZERO),
+            StatusFlags::ZERO
+        );
+    }
+
+    #[test]
+    fn test_txs_leaves_flags() {
+        let mut cpu = CPU::new();
+        cpu.init(asm! { TXS }).unwrap();
+        cpu.register_x = Wrapping(0x80);
+        cpu.status = StatusFlags::ZERO;
+        cpu.step().unwrap();
+        assert_eq!(cpu.stack_pointer, 0x80);
+        assert_eq!(cpu.status, StatusFlags::ZERO);
+
+        /* the pointer TXS sets is where PHA pushes */
+        cpu.init(asm! { LDX #$40  TXS  PHA  TSX  BRK }).unwrap();
+        cpu.run();
+        assert_eq!(cpu.register_x.0, 0x3f);
+    }
+
+    #[test]
+    fn test_bit() {
+        /* (A, operand) -> flags, A unchanged */
+        for (a, operand, flags) in [
+            (0x01, 0x01, StatusFlags::empty()),
+            (0x01, 0x02, StatusFlags::ZERO),
+            (0x01, 0xc1, StatusFlags::NEGATIVE | StatusFlags::OVERFLOW),
+            (0x00, 0x80, StatusFlags::NEGATIVE | StatusFlags::ZERO),
+            (0xff, 0x40, StatusFlags::OVERFLOW),
+        ] {
+            let mut cpu = CPU::new();
+            cpu.poke(0x10, operand);
+            cpu.poke(0x0210, operand);
+            for program in [asm! { BIT $10 }, asm! { BIT $0210 }] {
+                cpu.init(program).unwrap();
+                cpu.register_a = Wrapping(a);
+                cpu.status = StatusFlags::CARRY;
+                cpu.step().unwrap();
+                let case = format!("A={:02X} operand={:02X}", a, operand);
+                assert_eq!(cpu.status, StatusFlags::CARRY | flags, "{}", case);
+                assert_eq!(cpu.register_a.0, a, "{}", case);
+            }
+        }
+    }
+
+    #[test]
+    fn test_nops() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![
+            0xEA, 0x1A, 0x80, 0xff, 0x04, 0xff, 0x14, 0xff, 0x0C, 0xff, 0xff, 0x1C, 0xff, 0xff,
+            0xE8, 0x00,
+        ])
+        .unwrap();
+        cpu.register_a = Wrapping(0x12);
+        cpu.status = StatusFlags::CARRY;
+        cpu.run();
+        /* every operand was skipped, so the INX ran before the BRK */
+        assert_eq!(cpu.register_x.0, 0x01);
+        assert_eq!(cpu.register_a.0, 0x12);
+        assert_eq!(
+            cpu.status,
+            StatusFlags::CARRY | StatusFlags::INTERRUPT_DISABLE
+        );
+    }
+
+    #[test]
+    fn test_run_with_callback() {
+        let mut cpu = CPU::new();
+        cpu.init(asm! { LDA $ff  STA $10  LDA $ff  STA $11  BRK })
+            .unwrap();
+        let mut pcs = Vec::new();
+        cpu.run_with_callback(|cpu| {
+            pcs.push(cpu.program_counter);
+            /* a new key each instruction, the way the snake game reads them */
+            cpu.poke(0xff, pcs.len() as u8);
+        });
+        assert_eq!(pcs, [0x8000, 0x8002, 0x8004, 0x8006, 0x8008]);
+        assert_eq!((cpu.peek(0x10), cpu.peek(0x11)), (1, 3));
+    }
+
+    #[test]
+    fn test_step_errors() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xe8, 0x02]).unwrap();
+        assert_eq!(cpu.step(), Ok(2));
+        assert_eq!(
+            cpu.step(),
+            Err(CpuError::UnimplementedOpcode {
+                pc: 0x8001,
+                opcode: 0x02
+            })
+        );
+        /* left at the opcode, so stepping again fails the same way */
+        assert_eq!((cpu.program_counter, cpu.cycles), (0x8001, 2));
+        assert_eq!(
+            cpu.step().unwrap_err().to_string(),
+            "unimplemented opcode $02 at $8001"
+        );
+    }
+
+    #[test]
+    fn test_pc_wraps_at_ffff() {
+        let mut cpu = CPU::new();
+        cpu.init(asm! { JMP $FFFF }).unwrap();
+        cpu.poke(0xFFFF, 0xe8);
+        cpu.step().unwrap();
+        assert_eq!(cpu.step(), Ok(2));
+        assert_eq!(cpu.program_counter, 0x0000);
+
+        /* a BNE at $FFFE that isn't taken */
+        cpu.program_counter = 0xFFFE;
+        cpu.poke(0xFFFE, 0xd0);
+        cpu.status = StatusFlags::ZERO;
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0000);
+
+        /* a JSR at $FFFE takes its high byte from $0000 and returns to $0001 */
+        cpu.program_counter = 0xFFFE;
+        cpu.poke(0xFFFE, 0x20);
+        cpu.poke(0xFFFF, 0x00);
+        cpu.poke(0x0000, 0x90);
+        cpu.poke(0x9000, 0x60);
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x9000);
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0001);
+    }
+
+    /* inputs the run_program fuzz target found panics with, run the same way */
+    #[test]
+    fn test_fuzz_regressions() {
+        /* JMP $FFFF, onto the BRK in the blank vector */
+        let inputs: [&[u8]; 1] = [&[0x4c, 0xff, 0xff]];
+        for input in inputs {
+            let mut cpu = CPU::new();
+            cpu.init(input.to_vec()).unwrap();
+            for _ in 0..10_000 {
+                if cpu.step().is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_cycles() {
+        let mut cpu = CPU::new();
+        cpu.init(asm! { LDA #$01  STA $10  INX  JMP next  next: BRK })
+            .unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.cycles, 2);
+        cpu.run();
+        /* 2 + 3 + 2 + 3, then 7 for the BRK */
+        assert_eq!(cpu.cycles, 17);
+    }
+
+    #[test]
+    fn test_page_crossing_cycles() {
+        /* (program, X and Y) -> cycles */
+        for (source, index, cycles) in [
+            ("LDA $10F0,X", 0x0f, 4),
+            ("LDA $10F0,X", 0x10, 5),
+            ("LDX $10FF,Y", 0x01, 5),
+            ("STA $10FF,X", 0x01, 5),
+            ("STA $1000,X", 0x01, 5),
+            ("LDA ($20),Y", 0x01, 5),
+            ("LDA ($20),Y", 0x10, 6),
+            ("ASL $10FF,X", 0x01, 7),
+            ("NOP $10FF,X", 0x01, 5),
+            ("LDA $10,X", 0xff, 4),
+        ] {
+            let mut cpu = CPU::new();
+            cpu.poke(0x20, 0xf0);
+            cpu.poke(0x21, 0x10);
+            cpu.init(assembler::assemble(source).unwrap()).unwrap();
+            cpu.register_x = Wrapping(index);
+            cpu.register_y = Wrapping(index);
+            cpu.step().unwrap();
+            assert_eq!(cpu.cycles, cycles, "{} with index {:02X}", source, index);
+        }
+    }
+
+    #[test]
+    fn test_branch_cycles() {
+        /* (BNE offset, Z) -> cycles */
+        for (offset, zero, cycles) in [(0x02, true, 2), (0x02, false, 3), (0x80, false, 4)] {
+            let mut cpu = CPU::new();
+            cpu.init(vec![0xD0, offset]).unwrap();
+            cpu.status.set(StatusFlags::ZERO, zero);
+            cpu.step().unwrap();
+            assert_eq!(cpu.cycles, cycles, "offset {:02X}, Z {}", offset, zero);
+        }
+    }
+
+    #[test]
+    fn test_pc_advances_by_table_length() {
+        use opcodes::Mnemonic::*;
+
+        /* everything else moves the program counter itself, or isn't implemented */
+        let sequential = |op: &&opcodes::Opcode| {
+            !matches!(op.mnemonic, JMP | JSR | RTS | RTI | BRK)
+                && op.mode != AddressingMode::Relative
+                && (op.official
+                    || matches!(
+                        op.mnemonic,
+                        NOP | SBC | LAX | SAX | SLO | RLA | SRE | RRA | DCP | ISB
+                    ))
+        };
+        for op in opcodes::OPCODES.iter().filter(sequential) {
+            let mut cpu = CPU::new();
+            cpu.init(vec![op.code, 0x10, 0x02]).unwrap();
+            cpu.step().unwrap();
+            assert_eq!(
+                cpu.program_counter,
+                0x8000 + op.len as u16,
+                "{} ${:02X}",
+                op.mnemonic,
+                op.code
+            );
+        }
+    }
+
+    #[test]
+    fn test_brk_and_rti() {
+        let mut cpu = CPU::new();
+        cpu.init(asm! {
+            LDA #$01
+            BRK
+            .byte $ff
+            LDX #$02
+            BRK
+        handler:
+            INY
+            RTI
+        })
+        .unwrap();
+        /* the handler starts at $8007 */
+        cpu.poke(0xFFFE, 0x07);
+        cpu.poke(0xFFFF, 0x80);
+        cpu.status = StatusFlags::CARRY;
+        cpu.stack_pointer = 0x80;
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.step(), Err(CpuError::Break { pc: 0x8002 }));
+        assert_eq!(cpu.program_counter, 0x8007);
+        assert_eq!(cpu.stack_pointer, 0x7d);
+        assert_eq!(
+            cpu.status,
+            StatusFlags::CARRY | StatusFlags::INTERRUPT_DISABLE,
+            "BRK sets I"
+        );
+
+        /* the handler returns past the padding byte with the flags from before */
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x8004);
+        assert_eq!(cpu.status, StatusFlags::CARRY | StatusFlags::UNUSED);
+        assert_eq!(cpu.stack_pointer, 0x80);
+        assert_eq!(cpu.register_y.0, 0x01);
+
+        cpu.run();
+        assert_eq!(cpu.register_x.0, 0x02);
+        assert_eq!(cpu.register_y.0, 0x01);
+    }
+
+    #[test]
+    fn test_brk_pushes_b() {
+        let mut cpu = CPU::new();
+        cpu.init(asm! { BRK }).unwrap();
+        cpu.poke(0xFFFE, 0x00);
+        cpu.poke(0xFFFF, 0x90);
+        cpu.poke(0x9000, 0x68); // PLA, the pushed status
+        cpu.status = StatusFlags::NEGATIVE;
+        cpu.run();
+        cpu.step().unwrap();
+        assert_eq!(
+            cpu.register_a.0,
+            (StatusFlags::NEGATIVE | StatusFlags::BREAK | StatusFlags::UNUSED).bits()
+        );
+    }
+
+    #[test]
+    fn test_jsr_rts() {
+        let mut cpu = CPU::new();
+        cpu.init(asm! {
+            JSR sub
+            INX
+            BRK
+        sub:
+            INY
+            RTS
+        })
+        .unwrap();
+        cpu.run();
+        assert_eq!(cpu.register_x.0, 0x01);
+        assert_eq!(cpu.register_y.0, 0x01);
+    }
+
+    #[test]
+    fn test_ldx_ldy_addressing_modes() {
+        let mut cpu = CPU::new();
+        cpu.poke(0x10, 0x01);
+        cpu.poke(0x11, 0x02);
+        cpu.poke(0x12, 0x03);
+        cpu.poke(0x0210, 0x04);
+        cpu.poke(0x0211, 0x05);
+        cpu.poke(0x0212, 0x06);
+        for (program, expected) in [
+            (asm! { LDX $10 }, 0x01),
+            (asm! { LDX $10,Y }, 0x03),
+            (asm! { LDX $0210 }, 0x04),
+            (asm! { LDX $0210,Y }, 0x06),
+            (asm! { LDY $10 }, 0x01),
+            (asm! { LDY $10,X }, 0x02),
+            (asm! { LDY $0210 }, 0x04),
+            (asm! { LDY $0210,X }, 0x05),
+        ] {
+            cpu.init(program).unwrap();
+            cpu.register_x = Wrapping(0x01);
+            cpu.register_y = Wrapping(0x02);
+            let is_ldx = cpu.peek(0x8000) & 0x02 != 0;
+            cpu.step().unwrap();
+            let loaded = if is_ldx {
+                cpu.register_x
+            } else {
+                cpu.register_y
+            };
+            assert_eq!(loaded.0, expected, "{:02X}", cpu.peek(0x8000));
+        }
+
+        cpu.poke(0x10, 0x80);
+        cpu.init(asm! { LDX $10 }).unwrap();
+        cpu.step().unwrap();
+        assert_eq!(
+            cpu.status & (StatusFlags::NEGATIVE | StatusFlags::ZERO),
+            StatusFlags::NEGATIVE
+        );
+        cpu.poke(0x10, 0x00);
+        cpu.init(asm! { LDY $10 }).unwrap();
+        cpu.step().unwrap();
+        assert_eq!(
+            cpu.status & (StatusFlags::NEGATIVE | StatusFlags::ZERO),
+            StatusFlags::ZERO
+        );
+    }
+
+    #[test]
+    fn test_sta_addressing_modes() {
+        let mut cpu = CPU::new();
+        cpu.poke(0x20, 0x00);
+        cpu.poke(0x21, 0x03);
+        cpu.poke(0x22, 0x10);
+        cpu.poke(0x23, 0x03);
+        cpu.init(asm! {
+            STA $0300
+            STA $0300,X
+            STA $0300,Y
+            STA ($21,X)
+            STA ($22),Y
+            BRK
+        })
+        .unwrap();
+        cpu.register_a = Wrapping(0x55);
+        cpu.register_x = Wrapping(0x01);
+        cpu.register_y = Wrapping(0x02);
+        cpu.run();
+        for addr in [0x0300, 0x0301, 0x0302, 0x0310, 0x0312] {
+            assert_eq!(cpu.peek(addr), 0x55, "${:04X}", addr);
+        }
+        assert_eq!(cpu.peek(0x0303), 0x00);
+    }
+
+    #[test]
+    fn test_decimal_mode() {
+        /* (program, A, carry in) -> (A, carry out) */
+        for (program, a, carry, result, carry_out) in [
+            (asm! { ADC #$01 }, 0x09, false, 0x10, false),
+            (asm! { ADC #$25 }, 0x38, true, 0x64, false),
+            (asm! { ADC #$01 }, 0x99, false, 0x00, true),
+            (asm! { ADC #$50 }, 0x60, false, 0x10, true),
+            (asm! { SBC #$01 }, 0x10, true, 0x09, true),
+            (asm! { SBC #$19 }, 0x42, false, 0x22, true),
+            (asm! { SBC #$01 }, 0x00, true, 0x99, false),
+        ] {
+            let mut cpu = CPU::new();
+            cpu.decimal_mode = true;
+            let case = format!("{:02X?} on {:02X} with C={}", program, a, carry as u8);
+            cpu.init(program).unwrap();
+            cpu.register_a = Wrapping(a);
+            cpu.status = StatusFlags::DECIMAL | StatusFlags::from_bits(carry as u8);
+            cpu.step().unwrap();
+            assert_eq!(cpu.register_a.0, result, "{}", case);
+            assert_eq!(
+                cpu.status.get(StatusFlags::CARRY),
+                carry_out,
+                "C for {}",
+                case
+            );
+        }
+    }
+
+    #[test]
+    fn test_decimal_flag_ignored_by_default() {
+        let mut cpu = CPU::new();
+        cpu.init(asm! { SED  LDA #$09  ADC #$01  BRK }).unwrap();
+        cpu.run();
+        assert_eq!(cpu.register_a.0, 0x0a);
+
+        /* and in decimal mode with D clear */
+        cpu.decimal_mode = true;
+        cpu.init(asm! { CLD  LDA #$09  ADC #$01  BRK }).unwrap();
+        cpu.run();
+        assert_eq!(cpu.register_a.0, 0x0a);
+    }
+
+    #[test]
+    fn test_lax_sax() {
+        let mut cpu = CPU::new();
+        cpu.poke(0x10, 0x8f);
+        cpu.init(asm!("LAX $10  SAX $11  SAX $0300  BRK")).unwrap();
+        cpu.run();
+        assert_eq!(cpu.register_a.0, 0x8f);
+        assert_eq!(cpu.register_x.0, 0x8f);
+        assert_eq!(cpu.peek(0x11), 0x8f);
+
+        cpu.init(asm!("SAX $0300")).unwrap();
+        cpu.register_a = Wrapping(0xf0);
+        cpu.register_x = Wrapping(0x3c);
+        cpu.status = StatusFlags::empty();
+        cpu.step().unwrap();
+        assert_eq!(cpu.peek(0x0300), 0x30);
+        assert_eq!(cpu.status, StatusFlags::empty());
+    }
+
+    #[test]
+    fn test_unofficial_read_modify_write() {
+        /* (source, memory before, A before, carry before) -> (memory, A, status) */
+        for (source, before, a, carry, after, result_a, status) in [
+            /* $40 - 1 = $3F, compared with A = $3F */
+            (
+                "DCP $10",
+                0x40,
+                0x3f,
+                false,
+                0x3f,
+                0x3f,
+                StatusFlags::CARRY | StatusFlags::ZERO,
+            ),
+            /* $0F + 1 = $10, A - $10 with no borrow */
+            ("ISB $10", 0x0f, 0x30, true, 0x10, 0x20, StatusFlags::CARRY),
+            /* $81 << 1 = $02 with carry, ORed into A */
+            ("SLO $10", 0x81, 0x10, false, 0x02, 0x12, StatusFlags::CARRY),
+            /* $40 rotated left takes the carry in: $81, ANDed with A */
+            (
+                "RLA $10",
+                0x40,
+                0xf0,
+                true,
+                0x81,
+                0x80,
+                StatusFlags::NEGATIVE,
+            ),
+            /* $03 >> 1 = $01 with carry, EORed into A */
+            (
+                "SRE $10",
+                0x03,
+                0x01,
+                false,
+                0x01,
+                0x00,
+                StatusFlags::CARRY | StatusFlags::ZERO,
+            ),
+            /* $03 rotated right: $01 with carry, added with that carry */
+            (
+                "RRA $10",
+                0x03,
+                0x10,
+                false,
+                0x01,
+                0x12,
+                StatusFlags::empty(),
+            ),
+        ] {
+            let mut cpu = CPU::new();
+            cpu.poke(0x10, before);
+            cpu.init(assembler::assemble(source).unwrap()).unwrap();
+            cpu.register_a = Wrapping(a);
+            cpu.status = StatusFlags::from_bits(carry as u8);
+            cpu.step().unwrap();
+            assert_eq!(cpu.peek(0x10), after, "{}", source);
+            assert_eq!(cpu.register_a.0, result_a, "{}", source);
+            assert_eq!(cpu.status, status, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_unofficial_addressing_modes() {
+        let mut cpu = CPU::new();
+        cpu.poke(0x20, 0x00);
+        cpu.poke(0x21, 0x03);
+        cpu.init(asm!(
+            "DCP $0300,Y
+             ISB ($20),Y
+             SLO ($1F,X)
+             LAX ($1F,X)
+             BRK"
+        ))
+        .unwrap();
+        cpu.register_x = Wrapping(0x01);
+        cpu.register_y = Wrapping(0x01);
+        cpu.poke(0x0300, 0x40);
+        cpu.poke(0x0301, 0x10);
+        cpu.run();
+        assert_eq!(cpu.peek(0x0301), 0x10);
+        assert_eq!(cpu.peek(0x0300), 0x80);
+        assert_eq!(cpu.register_x.0, 0x80);
+    }
+
+    #[test]
+    fn test_stack_is_page_one() {
+        let mut cpu = CPU::new();
+        cpu.init(asm! { LDA #$11  PHA  LDA #$22  PHA  JSR sub  BRK  sub: BRK })
+            .unwrap();
+        cpu.run();
+        assert_eq!(cpu.peek(0x01FF), 0x11);
+        assert_eq!(cpu.peek(0x01FE), 0x22);
+        /* JSR's return address - 1, high byte first */
+        assert_eq!(cpu.peek(0x01FD), 0x80);
+        assert_eq!(cpu.peek(0x01FC), 0x08);
+        assert_eq!(cpu.peek(0xFF), 0x00);
+        assert_eq!(cpu.peek(0xFE), 0x00);
+    }
+
+    #[test]
+    fn test_stack_pointer_wraps() {
+        let mut cpu = CPU::new();
+        cpu.stack_pointer = 0x00;
+        cpu.push16(0x1234);
+        assert_eq!(cpu.stack_pointer, 0xFE);
+        assert_eq!(cpu.peek(0x0100), 0x12);
+        assert_eq!(cpu.peek(0x01FF), 0x34);
+        assert_eq!(cpu.pop16(), 0x1234);
+        assert_eq!(cpu.stack_pointer, 0x00);
+    }
+
+    #[test]
+    fn test_cpus_run_on_threads() {
+        /* nothing is shared between CPUs, so each can run on its own thread */
+        let runs: Vec<_> = (0..4u8)
+            .map(|i| {
+                let mut cpu = CPU::new();
+                cpu.init(vec![0xa2, i, 0xe8, 0xe8, 0x00]).unwrap();
+                cpu.add_invariant("x stays small", |cpu| cpu.register_x.0 < 0x10);
+                std::thread::spawn(move || {
+                    cpu.run();
+                    cpu.register_x.0
+                })
+            })
+            .collect();
+        let xs: Vec<u8> = runs.into_iter().map(|run| run.join().unwrap()).collect();
+        assert_eq!(xs, vec![2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_game() {
+        let mut cpu = CPU::new();
+        let game_code = vec![
+            0x20, 0x06, 0x06, 0x20, 0x38, 0x06, 0x20, 0x0d, 0x06, 0x20, 0x2a, 0x06, 0x60, 0xa9,
+            0x02, 0x85, 0x02, 0xa9, 0x04, 0x85, 0x03, 0xa9, 0x11, 0x85, 0x10, 0xa9, 0x10, 0x85,
+            0x12, 0xa9, 0x0f, 0x85, 0x14, 0xa9, 0x04, 0x85, 0x11, 0x85, 0x13, 0x85, 0x15, 0x60,
+            0xa5, 0xfe, 0x85, 0x00, 0xa5, 0xfe, 0x29, 0x03, 0x18, 0x69, 0x02, 0x85, 0x01, 0x60,
+            0x20, 0x4d, 0x06, 0x20, 0x8d, 0x06, 0x20, 0xc3, 0x06, 0x20, 0x19, 0x07, 0x20, 0x20,
+            0x07, 0x20, 0x2d, 0x07, 0x4c, 0x38, 0x06, 0xa5, 0xff, 0xc9, 0x77, 0xf0, 0x0d, 0xc9,
+            0x64, 0xf0, 0x14, 0xc9, 0x73, 0xf0, 0x1b, 0xc9, 0x61, 0xf0, 0x22, 0x60, 0xa9, 0x04,
+            0x24, 0x02, 0xd0, 0x26, 0xa9, 0x01, 0x85, 0x02, 0x60, 0xa9, 0x08, 0x24, 0x02, 0xd0,
+            0x1b, 0xa9, 0x02, 0x85, 0x02, 0x60, 0xa9, 0x01, 0x24, 0x02, 0xd0, 0x10, 0xa9, 0x04,
+            0x85, 0x02, 0x60, 0xa9, 0x02, 0x24, 0x02, 0xd0, 0x05, 0xa9, 0x08, 0x85, 0x02, 0x60,
+            0x60, 0x20, 0x94, 0x06, 0x20, 0xa8, 0x06, 0x60, 0xa5, 0x00, 0xc5, 0x10, 0xd0, 0x0d,
+            0xa5, 0x01, 0xc5, 0x11, 0xd0, 0x07, 0xe6, 0x03, 0xe6, 0x03, 0x20, 0x2a, 0x06, 0x60,
+            0xa2, 0x02, 0xb5, 0x10, 0xc5, 0x10, 0xd0, 0x06, 0xb5, 0x11, 0xc5, 0x11, 0xf0, 0x09,
+            0xe8, 0xe8, 0xe4, 0x03, 0xf0, 0x06, 0x4c, 0xaa, 0x06, 0x4c, 0x35, 0x07, 0x60, 0xa6,
+            0x03, 0xca, 0x8a, 0xb5, 0x10, 0x95, 0x12, 0xca, 0x10, 0xf9, 0xa5, 0x02, 0x4a, 0xb0,
+            0x09, 0x4a, 0xb0, 0x19, 0x4a, 0xb0, 0x1f, 0x4a, 0xb0, 0x2f, 0xa5, 0x10, 0x38, 0xe9,
+            0x20, 0x85, 0x10, 0x90, 0x01, 0x60, 0xc6, 0x11, 0xa9, 0x01, 0xc5, 0x11, 0xf0, 0x28,
+            0x60, 0xe6, 0x10, 0xa9, 0x1f, 0x24, 0x10, 0xf0, 0x1f, 0x60, 0xa5, 0x10, 0x18, 0x69,
+            0x20, 0x85, 0x10, 0xb0, 0x01, 0x60, 0xe6, 0x11, 0xa9, 0x06, 0xc5, 0x11, 0xf0, 0x0c,
+            0x60, 0xc6, 0x10, 0xa5, 0x10, 0x29, 0x1f, 0xc9, 0x1f, 0xf0, 0x01, 0x60, 0x4c, 0x35,
+            0x07, 0xa0, 0x00, 0xa5, 0xfe, 0x91, 0x00, 0x60, 0xa6, 0x03, 0xa9, 0x00, 0x81, 0x10,
+            0xa2, 0x00, 0xa9, 0x01, 0x81, 0x10, 0x60, 0xa2, 0x00, 0xea, 0xea, 0xca, 0xd0, 0xfb,
+            0x60,
+        ];
+
+        cpu.init(game_code).unwrap();
+        cpu.run();
+    }
+}
@@ -0,0 +1,601 @@
+/*
+ * Input movies, one Frame of controller state per emulated frame. The
+ * on-disk format is FCEUX's FM2: "key value" header lines followed by one
+ * "|commands|port0|port1|port2|" line per frame.
+ */
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::checksum;
+
+/* controller bits, in the order the NES shifts them out */
+pub const BUTTON_A: u8 = 0x01;
+pub const BUTTON_B: u8 = 0x02;
+pub const BUTTON_SELECT: u8 = 0x04;
+pub const BUTTON_START: u8 = 0x08;
+pub const BUTTON_UP: u8 = 0x10;
+pub const BUTTON_DOWN: u8 = 0x20;
+pub const BUTTON_LEFT: u8 = 0x40;
+pub const BUTTON_RIGHT: u8 = 0x80;
+
+/* FM2 writes gamepads as RLDUTSBA, leftmost character is the highest bit */
+const FM2_BUTTONS: &[u8; 8] = b"RLDUTSBA";
+
+/* the commands column */
+pub const COMMAND_SOFT_RESET: u8 = 0x01;
+pub const COMMAND_POWER: u8 = 0x02;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Frame {
+    pub commands: u8,
+    pub ports: [u8; 2],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Start {
+    PowerOn,
+    /* FCEUX's own savestate, base64 and all; we can't load it */
+    Savestate(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Movie {
+    /* header lines in file order, comments can repeat */
+    pub header: Vec<(String, String)>,
+    pub frames: Vec<Frame>,
+}
+
+fn format_gamepad(buttons: u8) -> String {
+    FM2_BUTTONS
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            if buttons & (0x80 >> i) != 0 {
+                *c as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+fn parse_gamepad(column: &str, line_no: usize) -> Result<u8, String> {
+    if column.is_empty() {
+        return Ok(0);
+    }
+    if column.len() != FM2_BUTTONS.len() {
+        return Err(format!(
+            "line {}: gamepad column '{}' isn't 8 characters",
+            line_no, column
+        ));
+    }
+    Ok(column
+        .bytes()
+        .enumerate()
+        .filter(|(_, c)| *c != b'.' && *c != b' ')
+        .fold(0, |buttons, (i, _)| buttons | (0x80 >> i)))
+}
+
+/* one FM2 input line after the leading '|' */
+fn parse_frame(input: &str, line_no: usize) -> Result<Frame, String> {
+    let columns: Vec<&str> = input.split('|').collect();
+    let commands = columns[0]
+        .trim()
+        .parse()
+        .map_err(|_| format!("line {}: bad commands '{}'", line_no, columns[0]))?;
+    let mut ports = [0; 2];
+    for (port, buttons) in ports.iter_mut().enumerate() {
+        *buttons = parse_gamepad(columns.get(port + 1).unwrap_or(&""), line_no)?;
+    }
+    Ok(Frame { commands, ports })
+}
+
+fn format_frame(frame: &Frame) -> String {
+    format!(
+        "|{}|{}|{}|",
+        frame.commands,
+        format_gamepad(frame.ports[0]),
+        format_gamepad(frame.ports[1])
+    )
+}
+
+/*
+ * Bare input lines without the FM2 header, "|0|..R.B...|........|" per
+ * frame, for scripts to generate and diff tools to read. Blank lines and
+ * lines starting with '#' are ignored.
+ */
+pub fn parse_input_log(text: &str) -> Result<Vec<Frame>, String> {
+    let mut frames = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let input = line
+            .strip_prefix('|')
+            .ok_or_else(|| format!("line {}: input lines start with '|'", i + 1))?;
+        frames.push(parse_frame(input, i + 1)?);
+    }
+    Ok(frames)
+}
+
+pub fn format_input_log(frames: &[Frame]) -> String {
+    frames
+        .iter()
+        .map(|frame| format_frame(frame) + "\n")
+        .collect()
+}
+
+impl Movie {
+    pub fn parse_fm2(text: &str) -> Result<Movie, String> {
+        let mut movie = Movie::default();
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(input) = line.strip_prefix('|') {
+                movie.frames.push(parse_frame(input, line_no)?);
+            } else if movie.frames.is_empty() {
+                let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+                movie.header.push((key.to_string(), value.to_string()));
+            } else {
+                return Err(format!("line {}: header line after input", line_no));
+            }
+        }
+        if movie.get("version").is_none() {
+            return Err("missing version header".to_string());
+        }
+        if movie.get("binary").is_some_and(|v| v != "0") {
+            return Err("binary FM2 input isn't supported".to_string());
+        }
+        Ok(movie)
+    }
+
+    pub fn to_fm2(&self) -> String {
+        let mut out = String::new();
+        for (key, value) in &self.header {
+            out.push_str(key);
+            if !value.is_empty() {
+                out.push(' ');
+                out.push_str(value);
+            }
+            out.push('\n');
+        }
+        for frame in &self.frames {
+            out.push_str(&format_frame(frame));
+            out.push_str("|\n");
+        }
+        out
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.header
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self.header.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self.header.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn rerecord_count(&self) -> u32 {
+        self.get("rerecordCount")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0)
+    }
+
+    pub fn start(&self) -> Start {
+        match self.get("savestate") {
+            Some(state) if !state.is_empty() => Start::Savestate(state.to_string()),
+            _ => Start::PowerOn,
+        }
+    }
+
+    /* buttons held on `port` during `frame`, nothing once the movie ends */
+    pub fn input(&self, frame: usize, port: usize) -> u8 {
+        self.frames
+            .get(frame)
+            .and_then(|f| f.ports.get(port))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn playback(&self) -> Playback<'_> {
+        Playback {
+            movie: self,
+            frame: 0,
+        }
+    }
+}
+
+/* FM2 wants an integer emulator version, 0.1.0 becomes 100 */
+fn emu_version() -> u32 {
+    env!("CARGO_PKG_VERSION")
+        .split('.')
+        .take(3)
+        .fold(0, |v, part| v * 100 + part.parse::<u32>().unwrap_or(0))
+}
+
+/* only has to be unique enough to tell recordings apart */
+fn guid() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let seed = now
+        .to_le_bytes()
+        .iter()
+        .chain(&std::process::id().to_le_bytes())
+        .copied()
+        .collect::<Vec<u8>>();
+    let hash = checksum::md5(&seed);
+    let hex: String = hash.iter().map(|b| format!("{:02X}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/*
+ * Records a movie one frame at a time. Re-recording is loading an earlier
+ * save state and carrying on from there, which throws away the input after
+ * that frame and bumps the rerecord count.
+ */
+pub struct Recorder {
+    movie: Movie,
+    branches: Vec<Branch>,
+}
+
+/* a named point to come back to: the machine state and the input leading up to it */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    pub name: String,
+    pub state: Vec<u8>,
+    pub frames: Vec<Frame>,
+}
+
+impl Recorder {
+    /* `rom` is the ROM image without its iNES header, as FCEUX checksums it */
+    pub fn new(rom_filename: &str, rom: &[u8]) -> Self {
+        let mut movie = Movie::default();
+        movie.set("version", "3");
+        movie.set("emuVersion", &emu_version().to_string());
+        movie.set("rerecordCount", "0");
+        movie.set("palFlag", "0");
+        movie.set("romFilename", rom_filename);
+        movie.set(
+            "romChecksum",
+            &format!("base64:{}", checksum::base64(&checksum::md5(rom))),
+        );
+        movie.set("guid", &guid());
+        movie.set("fourscore", "0");
+        movie.set("microphone", "0");
+        movie.set("port0", "1");
+        movie.set("port1", "1");
+        movie.set("port2", "0");
+        movie.set("FDS", "0");
+        movie.set("NewPPU", "0");
+        Recorder {
+            movie,
+            branches: Vec::new(),
+        }
+    }
+
+    pub fn frame(&self) -> usize {
+        self.movie.frames.len()
+    }
+
+    pub fn record(&mut self, frame: Frame) {
+        self.movie.frames.push(frame);
+    }
+
+    /* call after loading a save state taken at `frame` */
+    pub fn rerecord_from(&mut self, frame: usize) {
+        self.movie.frames.truncate(frame);
+        let count = self.movie.rerecord_count() + 1;
+        self.movie.set("rerecordCount", &count.to_string());
+    }
+
+    /* `state` is the save state for the current frame, replaces a branch of the same name */
+    pub fn save_branch(&mut self, name: &str, state: Vec<u8>) {
+        let branch = Branch {
+            name: name.to_string(),
+            state,
+            frames: self.movie.frames.clone(),
+        };
+        match self.branches.iter_mut().find(|b| b.name == name) {
+            Some(existing) => *existing = branch,
+            None => self.branches.push(branch),
+        }
+    }
+
+    /*
+     * Switch the input over to a branch and hand back its state for the
+     * caller to load. Counts as a rerecord, like any other state load.
+     */
+    pub fn load_branch(&mut self, name: &str) -> Option<&[u8]> {
+        let branch = self.branches.iter().find(|b| b.name == name)?;
+        self.movie.frames = branch.frames.clone();
+        let count = self.movie.rerecord_count() + 1;
+        self.movie.set("rerecordCount", &count.to_string());
+        Some(&branch.state)
+    }
+
+    pub fn delete_branch(&mut self, name: &str) -> bool {
+        let len = self.branches.len();
+        self.branches.retain(|b| b.name != name);
+        self.branches.len() != len
+    }
+
+    /* in the order they were first saved */
+    pub fn branches(&self) -> &[Branch] {
+        &self.branches
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+/* walks the movie one frame at a time, for whatever drives the controllers */
+pub struct Playback<'a> {
+    movie: &'a Movie,
+    frame: usize,
+}
+
+impl Playback<'_> {
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames.len()
+    }
+}
+
+impl Iterator for Playback<'_> {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        let frame = self.movie.frames.get(self.frame).copied()?;
+        self.frame += 1;
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const FM2: &str = "\
+version 3
+emuVersion 22020
+rerecordCount 12
+palFlag 0
+romFilename smb
+romChecksum base64:jjYwGG411HcjG/j9UOVM3Q==
+guid 452DE2C3-EF43-2FA9-77AC-0677FC51543B
+fourscore 0
+microphone 0
+port0 1
+port1 1
+port2 0
+comment author someone
+comment second comment
+|2|........|........||
+|0|....T...|........||
+|0|R......A|.L......||
+|1|........|........||
+";
+
+    #[test]
+    fn test_parse_header() {
+        let movie = Movie::parse_fm2(FM2).unwrap();
+        assert_eq!(movie.get("romFilename"), Some("smb"));
+        assert_eq!(movie.get("comment"), Some("author someone"));
+        assert_eq!(movie.rerecord_count(), 12);
+        assert_eq!(movie.start(), Start::PowerOn);
+        assert_eq!(movie.header.len(), 14);
+    }
+
+    #[test]
+    fn test_parse_input() {
+        let movie = Movie::parse_fm2(FM2).unwrap();
+        assert_eq!(movie.frames.len(), 4);
+        assert_eq!(movie.frames[0].commands, COMMAND_POWER);
+        assert_eq!(movie.input(1, 0), BUTTON_START);
+        assert_eq!(movie.input(2, 0), BUTTON_RIGHT | BUTTON_A);
+        assert_eq!(movie.input(2, 1), BUTTON_LEFT);
+        assert_eq!(movie.frames[3].commands, COMMAND_SOFT_RESET);
+        assert_eq!(movie.input(99, 0), 0);
+    }
+
+    #[test]
+    fn test_any_character_means_pressed() {
+        let movie = Movie::parse_fm2("version 3\n|0|RLDUTSBA|    xxxx||\n").unwrap();
+        assert_eq!(movie.input(0, 0), 0xFF);
+        assert_eq!(movie.input(0, 1), 0x0F);
+    }
+
+    #[test]
+    fn test_missing_ports() {
+        let movie = Movie::parse_fm2("version 3\nport1 0\n|0|.......A|||\n").unwrap();
+        assert_eq!(movie.input(0, 0), BUTTON_A);
+        assert_eq!(movie.input(0, 1), 0);
+    }
+
+    #[test]
+    fn test_savestate_start() {
+        let movie = Movie::parse_fm2("version 3\nsavestate base64:AAAA\n").unwrap();
+        assert_eq!(movie.start(), Start::Savestate("base64:AAAA".to_string()));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Movie::parse_fm2("|0|........|........||\n").is_err());
+        assert!(Movie::parse_fm2("version 3\n|x|........|........||\n").is_err());
+        assert!(Movie::parse_fm2("version 3\n|0|...|........||\n").is_err());
+        assert!(Movie::parse_fm2("version 3\nbinary 1\n").is_err());
+    }
+
+    #[test]
+    fn test_fm2_round_trip() {
+        let movie = Movie::parse_fm2(FM2).unwrap();
+        assert_eq!(Movie::parse_fm2(&movie.to_fm2()).unwrap(), movie);
+        assert!(movie
+            .to_fm2()
+            .ends_with("|0|R......A|.L......||\n|1|........|........||\n"));
+    }
+
+    #[test]
+    fn test_input_log() {
+        let frames =
+            parse_input_log("# run right\n|0|R.....B.|........|\n\n|1|........|\n").unwrap();
+        assert_eq!(
+            frames,
+            [
+                Frame {
+                    commands: 0,
+                    ports: [BUTTON_RIGHT | BUTTON_B, 0]
+                },
+                Frame {
+                    commands: COMMAND_SOFT_RESET,
+                    ports: [0, 0]
+                },
+            ]
+        );
+        assert_eq!(
+            format_input_log(&frames),
+            "|0|R.....B.|........|\n|1|........|........|\n"
+        );
+        assert_eq!(parse_input_log(&format_input_log(&frames)).unwrap(), frames);
+
+        let movie = Movie::parse_fm2(FM2).unwrap();
+        assert_eq!(
+            parse_input_log(&format_input_log(&movie.frames)).unwrap(),
+            movie.frames
+        );
+        assert_eq!(
+            parse_input_log("0|........|"),
+            Err("line 1: input lines start with '|'".to_string())
+        );
+    }
+
+    #[test]
+    fn test_recorder_metadata() {
+        let recorder = Recorder::new("game.nes", b"");
+        let movie = recorder.movie();
+        assert_eq!(movie.get("version"), Some("3"));
+        assert_eq!(movie.get("romFilename"), Some("game.nes"));
+        assert_eq!(
+            movie.get("romChecksum"),
+            Some("base64:1B2M2Y8AsgTpgAmY7PhCfg==")
+        );
+        assert_eq!(movie.get("guid").unwrap().len(), 36);
+        assert_eq!(
+            movie.get("emuVersion"),
+            Some(emu_version().to_string().as_str())
+        );
+    }
+
+    #[test]
+    fn test_record_and_rerecord() {
+        let mut recorder = Recorder::new("game.nes", &[0; 16]);
+        recorder.record(Frame {
+            commands: COMMAND_POWER,
+            ports: [0, 0],
+        });
+        recorder.record(Frame {
+            commands: 0,
+            ports: [BUTTON_START, 0],
+        });
+        recorder.record(Frame {
+            commands: 0,
+            ports: [BUTTON_A, 0],
+        });
+        recorder.rerecord_from(2);
+        recorder.record(Frame {
+            commands: 0,
+            ports: [BUTTON_B, 0],
+        });
+        assert_eq!(recorder.frame(), 3);
+
+        let movie = Movie::parse_fm2(&recorder.finish().to_fm2()).unwrap();
+        assert_eq!(movie.rerecord_count(), 1);
+        assert_eq!(movie.input(1, 0), BUTTON_START);
+        assert_eq!(movie.input(2, 0), BUTTON_B);
+    }
+
+    #[test]
+    fn test_branches() {
+        let mut cpu = crate::CPU::new();
+        cpu.init(vec![0xe8, 0xe8, 0xe8, 0x00]).unwrap();
+        let mut recorder = Recorder::new("game.nes", &[]);
+
+        cpu.step().unwrap();
+        recorder.record(Frame {
+            commands: 0,
+            ports: [BUTTON_A, 0],
+        });
+        recorder.save_branch("jump", cpu.save_state());
+        cpu.step().unwrap();
+        recorder.record(Frame {
+            commands: 0,
+            ports: [BUTTON_B, 0],
+        });
+        recorder.save_branch("run", cpu.save_state());
+        cpu.step().unwrap();
+        recorder.record(Frame {
+            commands: 0,
+            ports: [BUTTON_UP, 0],
+        });
+        recorder.save_branch("jump", cpu.save_state());
+        let names: Vec<_> = recorder
+            .branches()
+            .iter()
+            .map(|b| b.name.as_str())
+            .collect();
+        assert_eq!(names, ["jump", "run"]);
+
+        let state = recorder.load_branch("run").unwrap().to_vec();
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.register_x.0, 2);
+        assert_eq!(recorder.frame(), 2);
+        assert_eq!(recorder.movie().input(1, 0), BUTTON_B);
+        assert_eq!(recorder.movie().rerecord_count(), 1);
+
+        let state = recorder.load_branch("jump").unwrap().to_vec();
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.register_x.0, 3);
+        assert_eq!(recorder.frame(), 3);
+
+        assert!(recorder.delete_branch("run"));
+        assert!(!recorder.delete_branch("run"));
+        assert!(recorder.load_branch("run").is_none());
+        assert_eq!(recorder.movie().rerecord_count(), 2);
+    }
+
+    #[test]
+    fn test_playback() {
+        let movie = Movie::parse_fm2(FM2).unwrap();
+        let mut playback = movie.playback();
+        assert_eq!(playback.next().unwrap().commands, COMMAND_POWER);
+        assert_eq!(playback.next().unwrap().ports[0], BUTTON_START);
+        assert_eq!(playback.frame(), 2);
+        assert_eq!(playback.by_ref().count(), 2);
+        assert!(playback.is_finished());
+    }
+}
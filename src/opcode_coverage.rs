@@ -0,0 +1,88 @@
+/*
+ * Opcode coverage for the test suite. With the `opcode-coverage` feature
+ * every opcode the CPU executes, in any test, is recorded, and a report of
+ * the ones that never ran is kept in the file named by NES_OPCODE_COVERAGE
+ * (target/opcode-coverage.txt by default):
+ *
+ *   cargo test --features opcode-coverage && cat target/opcode-coverage.txt
+ *
+ * There's no hook for the end of a test run, so the report is rewritten
+ * each time an opcode runs for the first time instead, which leaves it
+ * complete once the suite is done.
+ */
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use crate::opcodes::{Mnemonic, Opcode, OPCODES};
+
+static EXECUTED: [AtomicBool; 256] = [const { AtomicBool::new(false) }; 256];
+static WRITING: Mutex<()> = Mutex::new(());
+
+pub(crate) fn record(code: u8) {
+    if !EXECUTED[code as usize].swap(true, Ordering::Relaxed) {
+        write_report();
+    }
+}
+
+fn write_report() {
+    let _writing = WRITING.lock().unwrap_or_else(|e| e.into_inner());
+    let path = std::env::var("NES_OPCODE_COVERAGE")
+        .unwrap_or_else(|_| "target/opcode-coverage.txt".to_string());
+    if let Err(e) = std::fs::write(&path, report()) {
+        eprintln!("opcode coverage: {}: {}", path, e);
+    }
+}
+
+/* for everything executed so far in this process */
+pub fn report() -> String {
+    let executed: Vec<bool> = EXECUTED.iter().map(|e| e.load(Ordering::Relaxed)).collect();
+    report_for(&executed)
+}
+
+/* never executed opcodes, a line per mnemonic */
+fn report_for(executed: &[bool]) -> String {
+    let mut out = String::new();
+    for (official, kind) in [(true, "official"), (false, "unofficial")] {
+        let ops: Vec<&Opcode> = OPCODES
+            .iter()
+            .filter(|op| op.official == official)
+            .collect();
+        let run = ops.iter().filter(|op| executed[op.code as usize]).count();
+        writeln!(out, "{} opcodes: {} of {} executed", kind, run, ops.len()).unwrap();
+        let mut missing: Vec<(Mnemonic, Vec<u8>)> = Vec::new();
+        for op in ops.iter().filter(|op| !executed[op.code as usize]) {
+            match missing.last_mut() {
+                Some((mnemonic, codes)) if *mnemonic == op.mnemonic => codes.push(op.code),
+                _ => missing.push((op.mnemonic, vec![op.code])),
+            }
+        }
+        for (mnemonic, codes) in missing {
+            let codes: Vec<String> = codes.iter().map(|c| format!("{:02X}", c)).collect();
+            writeln!(out, "  {}  {}", mnemonic, codes.join(" ")).unwrap();
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut executed = vec![false; 256];
+        for op in OPCODES.iter() {
+            executed[op.code as usize] = !matches!(op.mnemonic, Mnemonic::ADC | Mnemonic::SLO);
+        }
+        executed[0x69] = true;
+        assert_eq!(
+            report_for(&executed),
+            "official opcodes: 144 of 151 executed\n\
+             \x20 ADC  65 75 6D 7D 79 61 71\n\
+             unofficial opcodes: 98 of 105 executed\n\
+             \x20 SLO  07 17 0F 1F 1B 03 13\n"
+        );
+    }
+}
@@ -0,0 +1,398 @@
+/*
+ * Every 6502 opcode with its mnemonic, addressing mode, length in bytes and
+ * base cycle count (page crossings and taken branches add to it). Unofficial
+ * opcodes are included so traces and disassembly can name them. The CPU
+ * decodes from this table too, though it only runs the stable unofficial
+ * opcodes. Implied operands use NoneAddressing.
+ */
+
+use std::fmt;
+
+use crate::AddressingMode;
+use crate::AddressingMode::*;
+use Mnemonic::*;
+
+/* the enum, plus the name each variant prints as */
+macro_rules! mnemonics {
+    ($($name:ident),* $(,)?) => {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Mnemonic {
+            $($name),*
+        }
+
+        impl Mnemonic {
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Mnemonic::$name => stringify!($name)),*
+                }
+            }
+        }
+    };
+}
+
+mnemonics! {
+    ADC, AHX, ALR, ANC, AND, ARR, ASL, AXS, BCC, BCS, BEQ, BIT, BMI, BNE, BPL, BRK,
+    BVC, BVS, CLC, CLD, CLI, CLV, CMP, CPX, CPY, DCP, DEC, DEX, DEY, EOR, INC, INX,
+    INY, ISB, JAM, JMP, JSR, LAS, LAX, LDA, LDX, LDY, LSR, LXA, NOP, ORA, PHA, PHP,
+    PLA, PLP, RLA, ROL, ROR, RRA, RTI, RTS, SAX, SBC, SEC, SED, SEI, SHX, SHY, SLO,
+    SRE, STA, STX, STY, TAS, TAX, TAY, TSX, TXA, TXS, TYA, XAA,
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug)]
+pub struct Opcode {
+    pub code: u8,
+    pub mnemonic: Mnemonic,
+    pub mode: AddressingMode,
+    pub len: u8,
+    pub cycles: u8,
+    pub official: bool,
+}
+
+const fn op(code: u8, mnemonic: Mnemonic, mode: AddressingMode, len: u8, cycles: u8) -> Opcode {
+    Opcode {
+        code,
+        mnemonic,
+        mode,
+        len,
+        cycles,
+        official: true,
+    }
+}
+
+const fn unofficial(
+    code: u8,
+    mnemonic: Mnemonic,
+    mode: AddressingMode,
+    len: u8,
+    cycles: u8,
+) -> Opcode {
+    Opcode {
+        official: false,
+        ..op(code, mnemonic, mode, len, cycles)
+    }
+}
+
+pub static OPCODES: [Opcode; 256] = [
+    op(0x69, ADC, Immediate, 2, 2),
+    op(0x65, ADC, ZeroPage, 2, 3),
+    op(0x75, ADC, ZeroPage_X, 2, 4),
+    op(0x6D, ADC, Absolute, 3, 4),
+    op(0x7D, ADC, Absolute_X, 3, 4),
+    op(0x79, ADC, Absolute_Y, 3, 4),
+    op(0x61, ADC, Indirect_X, 2, 6),
+    op(0x71, ADC, Indirect_Y, 2, 5),
+    op(0x29, AND, Immediate, 2, 2),
+    op(0x25, AND, ZeroPage, 2, 3),
+    op(0x35, AND, ZeroPage_X, 2, 4),
+    op(0x2D, AND, Absolute, 3, 4),
+    op(0x3D, AND, Absolute_X, 3, 4),
+    op(0x39, AND, Absolute_Y, 3, 4),
+    op(0x21, AND, Indirect_X, 2, 6),
+    op(0x31, AND, Indirect_Y, 2, 5),
+    op(0x0A, ASL, Accumulator, 1, 2),
+    op(0x06, ASL, ZeroPage, 2, 5),
+    op(0x16, ASL, ZeroPage_X, 2, 6),
+    op(0x0E, ASL, Absolute, 3, 6),
+    op(0x1E, ASL, Absolute_X, 3, 7),
+    op(0x90, BCC, Relative, 2, 2),
+    op(0xB0, BCS, Relative, 2, 2),
+    op(0xF0, BEQ, Relative, 2, 2),
+    op(0x30, BMI, Relative, 2, 2),
+    op(0xD0, BNE, Relative, 2, 2),
+    op(0x10, BPL, Relative, 2, 2),
+    op(0x50, BVC, Relative, 2, 2),
+    op(0x70, BVS, Relative, 2, 2),
+    op(0x24, BIT, ZeroPage, 2, 3),
+    op(0x2C, BIT, Absolute, 3, 4),
+    op(0x00, BRK, NoneAddressing, 1, 7),
+    op(0x18, CLC, NoneAddressing, 1, 2),
+    op(0xD8, CLD, NoneAddressing, 1, 2),
+    op(0x58, CLI, NoneAddressing, 1, 2),
+    op(0xB8, CLV, NoneAddressing, 1, 2),
+    op(0xC9, CMP, Immediate, 2, 2),
+    op(0xC5, CMP, ZeroPage, 2, 3),
+    op(0xD5, CMP, ZeroPage_X, 2, 4),
+    op(0xCD, CMP, Absolute, 3, 4),
+    op(0xDD, CMP, Absolute_X, 3, 4),
+    op(0xD9, CMP, Absolute_Y, 3, 4),
+    op(0xC1, CMP, Indirect_X, 2, 6),
+    op(0xD1, CMP, Indirect_Y, 2, 5),
+    op(0xE0, CPX, Immediate, 2, 2),
+    op(0xE4, CPX, ZeroPage, 2, 3),
+    op(0xEC, CPX, Absolute, 3, 4),
+    op(0xC0, CPY, Immediate, 2, 2),
+    op(0xC4, CPY, ZeroPage, 2, 3),
+    op(0xCC, CPY, Absolute, 3, 4),
+    op(0xC6, DEC, ZeroPage, 2, 5),
+    op(0xD6, DEC, ZeroPage_X, 2, 6),
+    op(0xCE, DEC, Absolute, 3, 6),
+    op(0xDE, DEC, Absolute_X, 3, 7),
+    op(0xCA, DEX, NoneAddressing, 1, 2),
+    op(0x88, DEY, NoneAddressing, 1, 2),
+    op(0x49, EOR, Immediate, 2, 2),
+    op(0x45, EOR, ZeroPage, 2, 3),
+    op(0x55, EOR, ZeroPage_X, 2, 4),
+    op(0x4D, EOR, Absolute, 3, 4),
+    op(0x5D, EOR, Absolute_X, 3, 4),
+    op(0x59, EOR, Absolute_Y, 3, 4),
+    op(0x41, EOR, Indirect_X, 2, 6),
+    op(0x51, EOR, Indirect_Y, 2, 5),
+    op(0xE6, INC, ZeroPage, 2, 5),
+    op(0xF6, INC, ZeroPage_X, 2, 6),
+    op(0xEE, INC, Absolute, 3, 6),
+    op(0xFE, INC, Absolute_X, 3, 7),
+    op(0xE8, INX, NoneAddressing, 1, 2),
+    op(0xC8, INY, NoneAddressing, 1, 2),
+    op(0x4C, JMP, Absolute, 3, 3),
+    op(0x6C, JMP, Indirect, 3, 5),
+    op(0x20, JSR, Absolute, 3, 6),
+    op(0xA9, LDA, Immediate, 2, 2),
+    op(0xA5, LDA, ZeroPage, 2, 3),
+    op(0xB5, LDA, ZeroPage_X, 2, 4),
+    op(0xAD, LDA, Absolute, 3, 4),
+    op(0xBD, LDA, Absolute_X, 3, 4),
+    op(0xB9, LDA, Absolute_Y, 3, 4),
+    op(0xA1, LDA, Indirect_X, 2, 6),
+    op(0xB1, LDA, Indirect_Y, 2, 5),
+    op(0xA2, LDX, Immediate, 2, 2),
+    op(0xA6, LDX, ZeroPage, 2, 3),
+    op(0xB6, LDX, ZeroPage_Y, 2, 4),
+    op(0xAE, LDX, Absolute, 3, 4),
+    op(0xBE, LDX, Absolute_Y, 3, 4),
+    op(0xA0, LDY, Immediate, 2, 2),
+    op(0xA4, LDY, ZeroPage, 2, 3),
+    op(0xB4, LDY, ZeroPage_X, 2, 4),
+    op(0xAC, LDY, Absolute, 3, 4),
+    op(0xBC, LDY, Absolute_X, 3, 4),
+    op(0x4A, LSR, Accumulator, 1, 2),
+    op(0x46, LSR, ZeroPage, 2, 5),
+    op(0x56, LSR, ZeroPage_X, 2, 6),
+    op(0x4E, LSR, Absolute, 3, 6),
+    op(0x5E, LSR, Absolute_X, 3, 7),
+    op(0xEA, NOP, NoneAddressing, 1, 2),
+    op(0x09, ORA, Immediate, 2, 2),
+    op(0x05, ORA, ZeroPage, 2, 3),
+    op(0x15, ORA, ZeroPage_X, 2, 4),
+    op(0x0D, ORA, Absolute, 3, 4),
+    op(0x1D, ORA, Absolute_X, 3, 4),
+    op(0x19, ORA, Absolute_Y, 3, 4),
+    op(0x01, ORA, Indirect_X, 2, 6),
+    op(0x11, ORA, Indirect_Y, 2, 5),
+    op(0x48, PHA, NoneAddressing, 1, 3),
+    op(0x08, PHP, NoneAddressing, 1, 3),
+    op(0x68, PLA, NoneAddressing, 1, 4),
+    op(0x28, PLP, NoneAddressing, 1, 4),
+    op(0x2A, ROL, Accumulator, 1, 2),
+    op(0x26, ROL, ZeroPage, 2, 5),
+    op(0x36, ROL, ZeroPage_X, 2, 6),
+    op(0x2E, ROL, Absolute, 3, 6),
+    op(0x3E, ROL, Absolute_X, 3, 7),
+    op(0x6A, ROR, Accumulator, 1, 2),
+    op(0x66, ROR, ZeroPage, 2, 5),
+    op(0x76, ROR, ZeroPage_X, 2, 6),
+    op(0x6E, ROR, Absolute, 3, 6),
+    op(0x7E, ROR, Absolute_X, 3, 7),
+    op(0x40, RTI, NoneAddressing, 1, 6),
+    op(0x60, RTS, NoneAddressing, 1, 6),
+    op(0xE9, SBC, Immediate, 2, 2),
+    op(0xE5, SBC, ZeroPage, 2, 3),
+    op(0xF5, SBC, ZeroPage_X, 2, 4),
+    op(0xED, SBC, Absolute, 3, 4),
+    op(0xFD, SBC, Absolute_X, 3, 4),
+    op(0xF9, SBC, Absolute_Y, 3, 4),
+    op(0xE1, SBC, Indirect_X, 2, 6),
+    op(0xF1, SBC, Indirect_Y, 2, 5),
+    op(0x38, SEC, NoneAddressing, 1, 2),
+    op(0xF8, SED, NoneAddressing, 1, 2),
+    op(0x78, SEI, NoneAddressing, 1, 2),
+    op(0x85, STA, ZeroPage, 2, 3),
+    op(0x95, STA, ZeroPage_X, 2, 4),
+    op(0x8D, STA, Absolute, 3, 4),
+    op(0x9D, STA, Absolute_X, 3, 5),
+    op(0x99, STA, Absolute_Y, 3, 5),
+    op(0x81, STA, Indirect_X, 2, 6),
+    op(0x91, STA, Indirect_Y, 2, 6),
+    op(0x86, STX, ZeroPage, 2, 3),
+    op(0x96, STX, ZeroPage_Y, 2, 4),
+    op(0x8E, STX, Absolute, 3, 4),
+    op(0x84, STY, ZeroPage, 2, 3),
+    op(0x94, STY, ZeroPage_X, 2, 4),
+    op(0x8C, STY, Absolute, 3, 4),
+    op(0xAA, TAX, NoneAddressing, 1, 2),
+    op(0xA8, TAY, NoneAddressing, 1, 2),
+    op(0xBA, TSX, NoneAddressing, 1, 2),
+    op(0x8A, TXA, NoneAddressing, 1, 2),
+    op(0x9A, TXS, NoneAddressing, 1, 2),
+    op(0x98, TYA, NoneAddressing, 1, 2),
+    unofficial(0x1A, NOP, NoneAddressing, 1, 2),
+    unofficial(0x3A, NOP, NoneAddressing, 1, 2),
+    unofficial(0x5A, NOP, NoneAddressing, 1, 2),
+    unofficial(0x7A, NOP, NoneAddressing, 1, 2),
+    unofficial(0xDA, NOP, NoneAddressing, 1, 2),
+    unofficial(0xFA, NOP, NoneAddressing, 1, 2),
+    unofficial(0x80, NOP, Immediate, 2, 2),
+    unofficial(0x82, NOP, Immediate, 2, 2),
+    unofficial(0x89, NOP, Immediate, 2, 2),
+    unofficial(0xC2, NOP, Immediate, 2, 2),
+    unofficial(0xE2, NOP, Immediate, 2, 2),
+    unofficial(0x04, NOP, ZeroPage, 2, 3),
+    unofficial(0x44, NOP, ZeroPage, 2, 3),
+    unofficial(0x64, NOP, ZeroPage, 2, 3),
+    unofficial(0x14, NOP, ZeroPage_X, 2, 4),
+    unofficial(0x34, NOP, ZeroPage_X, 2, 4),
+    unofficial(0x54, NOP, ZeroPage_X, 2, 4),
+    unofficial(0x74, NOP, ZeroPage_X, 2, 4),
+    unofficial(0xD4, NOP, ZeroPage_X, 2, 4),
+    unofficial(0xF4, NOP, ZeroPage_X, 2, 4),
+    unofficial(0x0C, NOP, Absolute, 3, 4),
+    unofficial(0x1C, NOP, Absolute_X, 3, 4),
+    unofficial(0x3C, NOP, Absolute_X, 3, 4),
+    unofficial(0x5C, NOP, Absolute_X, 3, 4),
+    unofficial(0x7C, NOP, Absolute_X, 3, 4),
+    unofficial(0xDC, NOP, Absolute_X, 3, 4),
+    unofficial(0xFC, NOP, Absolute_X, 3, 4),
+    unofficial(0xA7, LAX, ZeroPage, 2, 3),
+    unofficial(0xB7, LAX, ZeroPage_Y, 2, 4),
+    unofficial(0xAF, LAX, Absolute, 3, 4),
+    unofficial(0xBF, LAX, Absolute_Y, 3, 4),
+    unofficial(0xA3, LAX, Indirect_X, 2, 6),
+    unofficial(0xB3, LAX, Indirect_Y, 2, 5),
+    unofficial(0x87, SAX, ZeroPage, 2, 3),
+    unofficial(0x97, SAX, ZeroPage_Y, 2, 4),
+    unofficial(0x8F, SAX, Absolute, 3, 4),
+    unofficial(0x83, SAX, Indirect_X, 2, 6),
+    unofficial(0xEB, SBC, Immediate, 2, 2),
+    unofficial(0xC7, DCP, ZeroPage, 2, 5),
+    unofficial(0xD7, DCP, ZeroPage_X, 2, 6),
+    unofficial(0xCF, DCP, Absolute, 3, 6),
+    unofficial(0xDF, DCP, Absolute_X, 3, 7),
+    unofficial(0xDB, DCP, Absolute_Y, 3, 7),
+    unofficial(0xC3, DCP, Indirect_X, 2, 8),
+    unofficial(0xD3, DCP, Indirect_Y, 2, 8),
+    unofficial(0xE7, ISB, ZeroPage, 2, 5),
+    unofficial(0xF7, ISB, ZeroPage_X, 2, 6),
+    unofficial(0xEF, ISB, Absolute, 3, 6),
+    unofficial(0xFF, ISB, Absolute_X, 3, 7),
+    unofficial(0xFB, ISB, Absolute_Y, 3, 7),
+    unofficial(0xE3, ISB, Indirect_X, 2, 8),
+    unofficial(0xF3, ISB, Indirect_Y, 2, 8),
+    unofficial(0x07, SLO, ZeroPage, 2, 5),
+    unofficial(0x17, SLO, ZeroPage_X, 2, 6),
+    unofficial(0x0F, SLO, Absolute, 3, 6),
+    unofficial(0x1F, SLO, Absolute_X, 3, 7),
+    unofficial(0x1B, SLO, Absolute_Y, 3, 7),
+    unofficial(0x03, SLO, Indirect_X, 2, 8),
+    unofficial(0x13, SLO, Indirect_Y, 2, 8),
+    unofficial(0x27, RLA, ZeroPage, 2, 5),
+    unofficial(0x37, RLA, ZeroPage_X, 2, 6),
+    unofficial(0x2F, RLA, Absolute, 3, 6),
+    unofficial(0x3F, RLA, Absolute_X, 3, 7),
+    unofficial(0x3B, RLA, Absolute_Y, 3, 7),
+    unofficial(0x23, RLA, Indirect_X, 2, 8),
+    unofficial(0x33, RLA, Indirect_Y, 2, 8),
+    unofficial(0x47, SRE, ZeroPage, 2, 5),
+    unofficial(0x57, SRE, ZeroPage_X, 2, 6),
+    unofficial(0x4F, SRE, Absolute, 3, 6),
+    unofficial(0x5F, SRE, Absolute_X, 3, 7),
+    unofficial(0x5B, SRE, Absolute_Y, 3, 7),
+    unofficial(0x43, SRE, Indirect_X, 2, 8),
+    unofficial(0x53, SRE, Indirect_Y, 2, 8),
+    unofficial(0x67, RRA, ZeroPage, 2, 5),
+    unofficial(0x77, RRA, ZeroPage_X, 2, 6),
+    unofficial(0x6F, RRA, Absolute, 3, 6),
+    unofficial(0x7F, RRA, Absolute_X, 3, 7),
+    unofficial(0x7B, RRA, Absolute_Y, 3, 7),
+    unofficial(0x63, RRA, Indirect_X, 2, 8),
+    unofficial(0x73, RRA, Indirect_Y, 2, 8),
+    unofficial(0x0B, ANC, Immediate, 2, 2),
+    unofficial(0x2B, ANC, Immediate, 2, 2),
+    unofficial(0x4B, ALR, Immediate, 2, 2),
+    unofficial(0x6B, ARR, Immediate, 2, 2),
+    unofficial(0x8B, XAA, Immediate, 2, 2),
+    unofficial(0xAB, LXA, Immediate, 2, 2),
+    unofficial(0xCB, AXS, Immediate, 2, 2),
+    unofficial(0x93, AHX, Indirect_Y, 2, 6),
+    unofficial(0x9F, AHX, Absolute_Y, 3, 5),
+    unofficial(0x9C, SHY, Absolute_X, 3, 5),
+    unofficial(0x9E, SHX, Absolute_Y, 3, 5),
+    unofficial(0x9B, TAS, Absolute_Y, 3, 5),
+    unofficial(0xBB, LAS, Absolute_Y, 3, 4),
+    unofficial(0x02, JAM, NoneAddressing, 1, 0),
+    unofficial(0x12, JAM, NoneAddressing, 1, 0),
+    unofficial(0x22, JAM, NoneAddressing, 1, 0),
+    unofficial(0x32, JAM, NoneAddressing, 1, 0),
+    unofficial(0x42, JAM, NoneAddressing, 1, 0),
+    unofficial(0x52, JAM, NoneAddressing, 1, 0),
+    unofficial(0x62, JAM, NoneAddressing, 1, 0),
+    unofficial(0x72, JAM, NoneAddressing, 1, 0),
+    unofficial(0x92, JAM, NoneAddressing, 1, 0),
+    unofficial(0xB2, JAM, NoneAddressing, 1, 0),
+    unofficial(0xD2, JAM, NoneAddressing, 1, 0),
+    unofficial(0xF2, JAM, NoneAddressing, 1, 0),
+];
+
+impl Opcode {
+    /*
+     * Whether an indexed address crossing a page costs a cycle. Only reads
+     * pay it; stores and read-modify-writes always spend that cycle, so it's
+     * in their base count.
+     */
+    pub fn page_cross_penalty(&self) -> bool {
+        matches!(self.mode, Absolute_X | Absolute_Y | Indirect_Y)
+            && matches!(
+                self.mnemonic,
+                ADC | AND | CMP | EOR | LDA | LDX | LDY | ORA | SBC | LAX | LAS | NOP
+            )
+    }
+}
+
+/* where each opcode is in OPCODES, so the CPU can decode without a search */
+static INDEX: [u8; 256] = index();
+
+const fn index() -> [u8; 256] {
+    let mut index = [0; 256];
+    let mut i = 0;
+    while i < OPCODES.len() {
+        index[OPCODES[i].code as usize] = i as u8;
+        i += 1;
+    }
+    index
+}
+
+pub fn lookup(code: u8) -> &'static Opcode {
+    &OPCODES[INDEX[code as usize] as usize]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_every_opcode_once() {
+        let mut seen = [false; 256];
+        for op in OPCODES.iter() {
+            assert!(!seen[op.code as usize], "${:02X} listed twice", op.code);
+            seen[op.code as usize] = true;
+        }
+        assert_eq!(OPCODES.iter().filter(|op| op.official).count(), 151);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let op = lookup(0x6C);
+        assert_eq!((op.mnemonic, op.len, op.cycles), (JMP, 3, 5));
+        assert_eq!(op.mnemonic.to_string(), "JMP");
+        assert_eq!(op.mode, Indirect);
+        assert!(!lookup(0xA7).official);
+        assert_eq!(lookup(0xD0).len, 2);
+        assert!(lookup(0xBD).page_cross_penalty());
+        assert!(!lookup(0x9D).page_cross_penalty());
+        assert!(!lookup(0x1E).page_cross_penalty());
+    }
+}
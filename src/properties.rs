@@ -0,0 +1,168 @@
+/*
+ * Property tests: random register and memory states run through short
+ * instruction sequences, checking rules that hold for every input. There's
+ * no proptest here, so a fixed-seed xorshift generator stands in for it and
+ * failures print the case that broke.
+ */
+
+use std::num::Wrapping;
+
+use crate::rng::Rng;
+use crate::status::StatusFlags;
+use crate::CPU;
+
+const CASES: usize = 1000;
+
+const SEED: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/* a CPU with random registers and zero page, ready to run `program` */
+fn random_cpu(rng: &mut Rng, program: Vec<u8>) -> CPU {
+    let mut cpu = CPU::new();
+    cpu.init(program).unwrap();
+    cpu.register_a = Wrapping(rng.byte());
+    cpu.register_x = Wrapping(rng.byte());
+    cpu.register_y = Wrapping(rng.byte());
+    cpu.status = StatusFlags::from_bits(rng.byte());
+    for addr in 0..0x100 {
+        cpu.poke(addr, rng.byte());
+    }
+    cpu
+}
+
+fn check_zero_and_negative(cpu: &CPU, before: StatusFlags, result: u8, what: &str) {
+    assert_eq!(
+        cpu.status.get(StatusFlags::ZERO),
+        result == 0,
+        "{}: Z wrong for result {:02X}",
+        what,
+        result
+    );
+    assert_eq!(
+        cpu.status.get(StatusFlags::NEGATIVE),
+        result & 0x80 != 0,
+        "{}: N wrong for result {:02X}",
+        what,
+        result
+    );
+    assert_eq!(
+        cpu.status & !(StatusFlags::ZERO | StatusFlags::NEGATIVE),
+        before & !(StatusFlags::ZERO | StatusFlags::NEGATIVE),
+        "{}: touched flags other than Z and N",
+        what
+    );
+}
+
+#[test]
+fn test_loads_set_zero_and_negative() {
+    let mut rng = Rng::new(SEED);
+    for _ in 0..CASES {
+        let value = rng.byte();
+        let zp = rng.byte();
+        let (opcode, operand) = match rng.next() % 6 {
+            0 => (0xA9, value),
+            1 => (0xA2, value),
+            2 => (0xA0, value),
+            _ => (0xA5, zp),
+        };
+        let mut cpu = random_cpu(&mut rng, vec![opcode, operand, 0x00]);
+        if opcode == 0xA5 {
+            cpu.poke(zp as u16, value);
+        }
+        let before = cpu.status;
+        cpu.step().unwrap();
+        let loaded = match opcode {
+            0xA2 => cpu.register_x.0,
+            0xA0 => cpu.register_y.0,
+            _ => cpu.register_a.0,
+        };
+        assert_eq!(loaded, value, "{:02X} {:02X}", opcode, operand);
+        check_zero_and_negative(
+            &cpu,
+            before,
+            value,
+            &format!("{:02X} {:02X}", opcode, operand),
+        );
+    }
+}
+
+#[test]
+fn test_register_ops_set_zero_and_negative() {
+    let mut rng = Rng::new(SEED);
+    for _ in 0..CASES {
+        let opcode = [0xAA, 0x8A, 0xE8][(rng.next() % 3) as usize];
+        let mut cpu = random_cpu(&mut rng, vec![opcode, 0x00]);
+        let (a, x, before) = (cpu.register_a.0, cpu.register_x.0, cpu.status);
+        cpu.step().unwrap();
+        let (result, what) = match opcode {
+            0xAA => (a, format!("TAX with A={:02X}", a)),
+            0x8A => (x, format!("TXA with X={:02X}", x)),
+            _ => (x.wrapping_add(1), format!("INX with X={:02X}", x)),
+        };
+        let register = if opcode == 0x8A {
+            cpu.register_a.0
+        } else {
+            cpu.register_x.0
+        };
+        assert_eq!(register, result, "{}", what);
+        check_zero_and_negative(&cpu, before, result, &what);
+    }
+}
+
+#[test]
+fn test_stores_only_write_their_target() {
+    let mut rng = Rng::new(SEED);
+    for _ in 0..CASES {
+        let opcode = [0x85, 0x86, 0x84][(rng.next() % 3) as usize];
+        let zp = rng.byte();
+        let mut cpu = random_cpu(&mut rng, vec![opcode, zp, 0x00]);
+        let what = format!("{:02X} {:02X}", opcode, zp);
+        let value = match opcode {
+            0x85 => cpu.register_a.0,
+            0x86 => cpu.register_x.0,
+            _ => cpu.register_y.0,
+        };
+        let registers = |cpu: &CPU| (cpu.register_a, cpu.register_x, cpu.register_y, cpu.status);
+        let (before, zero_page) = (registers(&cpu), cpu.ram()[..0x100].to_vec());
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.peek(zp as u16), value, "{}", what);
+        assert_eq!(registers(&cpu), before, "{} changed a register", what);
+        for (addr, &old) in zero_page.iter().enumerate() {
+            if addr != zp as usize {
+                assert_eq!(cpu.ram()[addr], old, "{} wrote ${:02X}", what, addr);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_adc_sbc_round_trip() {
+    let mut rng = Rng::new(SEED);
+    for _ in 0..CASES {
+        let operand = rng.byte();
+        /* CLC; ADC #operand; SEC; SBC #operand */
+        let mut cpu = random_cpu(
+            &mut rng,
+            vec![0x18, 0x69, operand, 0x38, 0xE9, operand, 0x00],
+        );
+        let a = cpu.register_a.0;
+        cpu.run();
+        assert_eq!(cpu.register_a.0, a, "A={:02X} operand={:02X}", a, operand);
+    }
+}
+
+#[test]
+fn test_stack_depth_restored_across_jsr_rts() {
+    let mut rng = Rng::new(SEED);
+    for _ in 0..CASES {
+        /* JSR $8005; BRK; (padding); RTS */
+        let mut cpu = random_cpu(&mut rng, vec![0x20, 0x05, 0x80, 0x00, 0x00, 0x60]);
+        cpu.stack_pointer = rng.byte() | 0x02;
+        let sp = cpu.stack_pointer;
+        cpu.step().unwrap();
+        assert_eq!(cpu.stack_pointer, sp.wrapping_sub(2));
+        cpu.step().unwrap();
+        assert_eq!(cpu.stack_pointer, sp, "SP={:02X}", sp);
+        assert_eq!(cpu.program_counter, 0x8003);
+    }
+}
@@ -0,0 +1,156 @@
+/*
+ * FCEUX RAM watch lists (.wch). Each watch is a tab separated line:
+ *
+ *   id  address  size  type  big-endian  description
+ *
+ * with the address in hex, size b/w/d for 1, 2 or 4 bytes, type s(igned),
+ * u(nsigned), h(ex) or b(inary), and big-endian 0 or 1. FCEUX puts the
+ * number of watches on the first line; lines without tabs are skipped.
+ */
+
+use crate::CPU;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RamWatch {
+    pub addr: u16,
+    pub size: u8,
+    pub kind: char,
+    pub big_endian: bool,
+    pub description: String,
+}
+
+impl RamWatch {
+    pub fn parse_wch(text: &str) -> Result<Vec<RamWatch>, String> {
+        let mut watches = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            if !line.contains('\t') {
+                continue;
+            }
+            let bad = || format!("line {}: bad watch '{}'", i + 1, line);
+            let fields: Vec<&str> = line.splitn(6, '\t').collect();
+            if fields.len() < 5 {
+                return Err(bad());
+            }
+            let addr = u16::from_str_radix(fields[1].trim(), 16).map_err(|_| bad())?;
+            let size = match fields[2].trim() {
+                "b" => 1,
+                "w" => 2,
+                "d" => 4,
+                _ => return Err(bad()),
+            };
+            let kind = match fields[3].trim() {
+                "s" => 's',
+                "u" => 'u',
+                "h" => 'h',
+                "b" => 'b',
+                _ => return Err(bad()),
+            };
+            let big_endian = match fields[4].trim() {
+                "0" => false,
+                "1" => true,
+                _ => return Err(bad()),
+            };
+            watches.push(RamWatch {
+                addr,
+                size,
+                kind,
+                big_endian,
+                description: fields.get(5).unwrap_or(&"").to_string(),
+            });
+        }
+        Ok(watches)
+    }
+
+    pub fn to_wch(watches: &[RamWatch]) -> String {
+        let mut out = format!("{}\n", watches.len());
+        for (i, watch) in watches.iter().enumerate() {
+            let size = match watch.size {
+                1 => 'b',
+                2 => 'w',
+                _ => 'd',
+            };
+            out.push_str(&format!(
+                "{:05X}\t{:04X}\t{}\t{}\t{}\t{}\n",
+                i, watch.addr, size, watch.kind, watch.big_endian as u8, watch.description
+            ));
+        }
+        out
+    }
+
+    /* the raw bytes put together, sign extended for signed watches */
+    pub fn value(&self, cpu: &CPU) -> i64 {
+        let bytes = (0..self.size as u16).map(|i| cpu.peek(self.addr.wrapping_add(i)));
+        let raw = if self.big_endian {
+            bytes.fold(0u32, |v, b| v << 8 | b as u32)
+        } else {
+            bytes.rev().fold(0u32, |v, b| v << 8 | b as u32)
+        };
+        if self.kind == 's' {
+            let shift = 32 - 8 * self.size as u32;
+            ((raw << shift) as i32 >> shift) as i64
+        } else {
+            raw as i64
+        }
+    }
+
+    pub fn format(&self, cpu: &CPU) -> String {
+        let value = self.value(cpu);
+        let digits = self.size as usize * 2;
+        let shown = match self.kind {
+            'h' => format!("{:0width$X}", value, width = digits),
+            'b' => format!("{:0width$b}", value, width = digits * 4),
+            _ => value.to_string(),
+        };
+        format!("{} = {}", self.description, shown)
+    }
+}
+
+impl CPU {
+    /* the current value of every watch, for tools polling once a frame */
+    pub fn ram_watch_values(&self, watches: &[RamWatch]) -> Vec<i64> {
+        watches.iter().map(|watch| watch.value(self)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Mem;
+
+    const WCH: &str = "3\n\
+        00000\t0075\tb\tu\t0\tlives\n\
+        00001\t0086\tw\th\t0\tplayer x\n\
+        00002\t00A0\tw\ts\t1\tspeed\n";
+
+    #[test]
+    fn test_parse_wch() {
+        let watches = RamWatch::parse_wch(WCH).unwrap();
+        assert_eq!(watches.len(), 3);
+        assert_eq!(
+            watches[1],
+            RamWatch {
+                addr: 0x0086,
+                size: 2,
+                kind: 'h',
+                big_endian: false,
+                description: "player x".to_string(),
+            }
+        );
+        assert_eq!(RamWatch::to_wch(&watches), WCH);
+        assert!(RamWatch::parse_wch("00000\t0075\tq\tu\t0\tlives").is_err());
+    }
+
+    #[test]
+    fn test_watch_values() {
+        let watches = RamWatch::parse_wch(WCH).unwrap();
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x75, 3);
+        cpu.mem_write(0x86, 0x34);
+        cpu.mem_write(0x87, 0x12);
+        cpu.mem_write(0xA0, 0xFF);
+        cpu.mem_write(0xA1, 0xFE);
+        assert_eq!(cpu.ram_watch_values(&watches), [3, 0x1234, -2]);
+        assert_eq!(watches[1].format(&cpu), "player x = 1234");
+        assert_eq!(watches[2].format(&cpu), "speed = -2");
+    }
+}
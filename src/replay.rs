@@ -0,0 +1,257 @@
+/*
+ * Per-frame hashes for checking that a movie replays the same way it did
+ * last time. The sidecar file is one "frame hash" line per frame, with the
+ * hash as 32 hex digits; anything after a '#' is a comment. The same hashes
+ * back the determinism self-check.
+ */
+
+use std::fmt;
+use std::ops::Range;
+use std::panic;
+use std::thread;
+
+use crate::bus::Bus;
+use crate::checksum;
+use crate::CPU;
+
+pub type Hash = [u8; 16];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HashLog {
+    pub hashes: Vec<Hash>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Desync {
+    pub frame: usize,
+    pub expected: Option<Hash>,
+    pub actual: Option<Hash>,
+}
+
+fn hex(hash: &Hash) -> String {
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn parse_hash(text: &str) -> Option<Hash> {
+    if text.len() != 32 {
+        return None;
+    }
+    let mut hash = [0; 16];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(text.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(hash)
+}
+
+impl HashLog {
+    pub fn parse(text: &str) -> Result<HashLog, String> {
+        let mut log = HashLog::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let frame = fields.next().and_then(|f| f.parse::<usize>().ok());
+            let hash = fields.next().and_then(parse_hash);
+            match (frame, hash, fields.next()) {
+                (Some(frame), Some(hash), None) if frame == log.hashes.len() => {
+                    log.hashes.push(hash)
+                }
+                (Some(frame), Some(_), None) => {
+                    return Err(format!(
+                        "line {}: expected frame {}, found {}",
+                        i + 1,
+                        log.hashes.len(),
+                        frame
+                    ))
+                }
+                _ => return Err(format!("line {}: expected '<frame> <hash>'", i + 1)),
+            }
+        }
+        Ok(log)
+    }
+
+    pub fn to_text(&self) -> String {
+        self.hashes
+            .iter()
+            .enumerate()
+            .map(|(frame, hash)| format!("{} {}\n", frame, hex(hash)))
+            .collect()
+    }
+
+    pub fn push(&mut self, hash: Hash) {
+        self.hashes.push(hash);
+    }
+
+    /* first frame where `actual` differs from this log, including one running out early */
+    pub fn first_desync(&self, actual: &HashLog) -> Option<Desync> {
+        let frames = self.hashes.len().max(actual.hashes.len());
+        (0..frames)
+            .map(|frame| Desync {
+                frame,
+                expected: self.hashes.get(frame).copied(),
+                actual: actual.hashes.get(frame).copied(),
+            })
+            .find(|d| d.expected != d.actual)
+    }
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |hash: &Option<Hash>| hash.as_ref().map_or("nothing".to_string(), hex);
+        write!(
+            f,
+            "desync at frame {}: expected {}, got {}",
+            self.frame,
+            show(&self.expected),
+            show(&self.actual)
+        )
+    }
+}
+
+impl CPU {
+    /* hash of the registers and the given memory ranges, the whole of RAM if empty */
+    pub fn state_hash(&self, ranges: &[Range<u16>]) -> Hash {
+        let mut data = vec![
+            self.register_a.0,
+            self.register_x.0,
+            self.register_y.0,
+            self.status.bits(),
+            self.stack_pointer,
+        ];
+        data.extend_from_slice(&self.program_counter.to_le_bytes());
+        if ranges.is_empty() {
+            data.extend_from_slice(self.ram());
+        }
+        for range in ranges {
+            data.extend(range.clone().map(|addr| self.peek(addr)));
+        }
+        checksum::md5(&data)
+    }
+
+    /*
+     * Runs `steps` instructions twice from the current state, at the same
+     * time on two threads if `threaded`, and compares the state hash after
+     * each one. Netplay, rewind and TAS tools all assume a state always
+     * plays out the same way; this is the check for it. Runs stop early at
+     * BRK. The hashes are per instruction rather than per frame until there
+     * are frames and inputs to feed in.
+     */
+    pub fn verify_determinism(&self, steps: usize, threaded: bool) -> Result<(), Desync> {
+        let state = self.save_state();
+        let (first, second) = if threaded {
+            /* each thread builds its own CPU from the state */
+            let spawn = || {
+                let (bus, state) = (self.bus.clone(), state.clone());
+                thread::spawn(move || hash_run(bus, &state, steps))
+            };
+            let (a, b) = (spawn(), spawn());
+            let join = |run: thread::JoinHandle<HashLog>| {
+                run.join().unwrap_or_else(|e| panic::resume_unwind(e))
+            };
+            (join(a), join(b))
+        } else {
+            (
+                hash_run(self.bus.clone(), &state, steps),
+                hash_run(self.bus.clone(), &state, steps),
+            )
+        };
+        match first.first_desync(&second) {
+            Some(desync) => Err(desync),
+            None => Ok(()),
+        }
+    }
+}
+
+/* the state doesn't hold the ROM, so it starts from a copy of the bus */
+fn hash_run(bus: Bus, state: &[u8], steps: usize) -> HashLog {
+    let mut cpu = CPU::new();
+    cpu.bus = bus;
+    cpu.load_state(state).expect("state was just saved");
+    let mut log = HashLog::default();
+    for _ in 0..steps {
+        let running = cpu.step();
+        log.push(cpu.state_hash(&[]));
+        if running.is_err() {
+            break;
+        }
+    }
+    log
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hash_log_round_trip() {
+        let mut log = HashLog::default();
+        log.push([0; 16]);
+        log.push([0xab; 16]);
+        let text = log.to_text();
+        assert_eq!(
+            text,
+            "0 00000000000000000000000000000000\n1 abababababababababababababababab\n"
+        );
+        assert_eq!(HashLog::parse(&text), Ok(log));
+        assert!(HashLog::parse("1 00000000000000000000000000000000").is_err());
+        assert!(HashLog::parse("0 abc").is_err());
+    }
+
+    fn record(program: Vec<u8>, ranges: &[Range<u16>]) -> HashLog {
+        let mut cpu = CPU::new();
+        cpu.init(program).unwrap();
+        let mut log = HashLog::default();
+        loop {
+            log.push(cpu.state_hash(ranges));
+            if cpu.step().is_err() {
+                return log;
+            }
+        }
+    }
+
+    #[test]
+    fn test_first_desync() {
+        let golden = record(vec![0xa9, 0x05, 0x85, 0x10, 0xe8, 0x00], &[]);
+        let replay = record(vec![0xa9, 0x05, 0x85, 0x11, 0xe8, 0x00], &[]);
+        assert_eq!(golden.first_desync(&golden), None);
+
+        /* the store goes somewhere else, which shows up once it has run */
+        let desync = golden.first_desync(&replay).unwrap();
+        assert_eq!(desync.frame, 2);
+        assert!(desync
+            .to_string()
+            .starts_with("desync at frame 2: expected "));
+
+        let mut short = golden.clone();
+        short.hashes.pop();
+        assert_eq!(golden.first_desync(&short).unwrap().actual, None);
+    }
+
+    #[test]
+    fn test_hash_selected_ranges() {
+        /* the only difference is outside the hashed ranges */
+        let ranges = [0x0000..0x0010, 0x0100..0x0200];
+        let golden = record(vec![0xa9, 0x05, 0x85, 0x10, 0x00], &ranges);
+        let replay = record(vec![0xa9, 0x05, 0x85, 0x11, 0x00], &ranges);
+        assert_eq!(golden.first_desync(&replay), None);
+    }
+
+    #[test]
+    fn test_verify_determinism() {
+        let mut cpu = CPU::new();
+        cpu.init(crate::asm! {
+            LDX #$00
+            loop: TXA
+            STA $10,X
+            INX
+            JMP loop
+        })
+        .unwrap();
+        assert_eq!(cpu.verify_determinism(1000, false), Ok(()));
+        assert_eq!(cpu.verify_determinism(1000, true), Ok(()));
+        /* the CPU checked is left where it was */
+        assert_eq!(cpu.program_counter, 0x8000);
+    }
+}
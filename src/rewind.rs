@@ -0,0 +1,255 @@
+use std::collections::VecDeque;
+
+use crate::CPU;
+
+/*
+ * Bounded ring of save states for rewinding. Only the newest snapshot is
+ * kept whole, every older one is stored as the list of byte ranges where it
+ * differs from the snapshot after it. Most of memory doesn't change between
+ * snapshots so the deltas stay small, and dropping the oldest entry is just
+ * a pop.
+ */
+pub struct Rewind {
+    interval: u64,
+    capacity: usize,
+    steps: u64,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+fn push_varint(out: &mut Vec<u8>, mut v: usize) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut v = 0;
+    let mut shift = 0;
+    while let Some(&b) = data.get(*pos) {
+        *pos += 1;
+        v |= ((b & 0x7f) as usize) << shift;
+        if b & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    v
+}
+
+/* delta turning `newer` back into `older`: (skip, len, bytes) runs, or a raw copy */
+fn encode(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    if older.len() != newer.len() {
+        out.push(0);
+        out.extend_from_slice(older);
+        return out;
+    }
+    out.push(1);
+    let mut pos = 0;
+    while pos < older.len() {
+        let start = pos
+            + older[pos..]
+                .iter()
+                .zip(&newer[pos..])
+                .take_while(|(a, b)| a == b)
+                .count();
+        if start == older.len() {
+            break;
+        }
+        let len = older[start..]
+            .iter()
+            .zip(&newer[start..])
+            .take_while(|(a, b)| a != b)
+            .count();
+        push_varint(&mut out, start - pos);
+        push_varint(&mut out, len);
+        out.extend_from_slice(&older[start..start + len]);
+        pos = start + len;
+    }
+    out
+}
+
+fn decode(delta: &[u8], newer: &[u8]) -> Vec<u8> {
+    if delta.first() == Some(&0) {
+        return delta[1..].to_vec();
+    }
+    let mut state = newer.to_vec();
+    let mut pos = 1;
+    let mut at = 0;
+    while pos < delta.len() {
+        at += read_varint(delta, &mut pos);
+        let len = read_varint(delta, &mut pos);
+        state[at..at + len].copy_from_slice(&delta[pos..pos + len]);
+        pos += len;
+        at += len;
+    }
+    state
+}
+
+impl Rewind {
+    /* a snapshot every `interval` instructions, keeping at most `capacity` of them */
+    pub fn new(interval: u64, capacity: usize) -> Self {
+        Rewind {
+            interval: interval.max(1),
+            capacity,
+            steps: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.latest.is_some() as usize + self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    /* bytes held by the ring, to see what compression is buying */
+    pub fn stored_bytes(&self) -> usize {
+        self.latest.as_ref().map_or(0, Vec::len) + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+
+    fn due(&mut self) -> bool {
+        let due = self.steps.is_multiple_of(self.interval);
+        self.steps += 1;
+        due
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(previous) = self.latest.take() {
+            self.deltas.push_back(encode(&previous, &state));
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    /* newest snapshot first, each call goes one further back */
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let latest = self.latest.take()?;
+        self.latest = self.deltas.pop_back().map(|delta| decode(&delta, &latest));
+        Some(latest)
+    }
+}
+
+impl CPU {
+    pub fn enable_rewind(&mut self, interval: u64, capacity: usize) {
+        self.rewind = Some(Rewind::new(interval, capacity));
+    }
+
+    pub fn rewind_buffer(&self) -> Option<&Rewind> {
+        self.rewind.as_ref()
+    }
+
+    /*
+     * Go back to the most recent snapshot, returns false once the ring is
+     * exhausted. Holding a rewind key maps to calling this repeatedly.
+     */
+    pub fn rewind(&mut self) -> bool {
+        let Some(state) = self.rewind.as_mut().and_then(Rewind::pop) else {
+            return false;
+        };
+        self.load_state(&state).is_ok()
+    }
+
+    pub(crate) fn capture_rewind(&mut self) {
+        if let Some(mut rewind) = self.rewind.take() {
+            if rewind.due() {
+                rewind.push(self.save_state());
+            }
+            self.rewind = Some(rewind);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_delta_round_trip() {
+        let newer = vec![0, 1, 2, 3, 4, 5, 6, 7];
+        let older = vec![0, 9, 2, 3, 8, 8, 6, 7];
+        let delta = encode(&older, &newer);
+        assert_eq!(decode(&delta, &newer), older);
+
+        assert_eq!(decode(&encode(&newer, &newer), &newer), newer);
+        assert_eq!(decode(&encode(&[1, 2], &newer), &newer), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_large_skip() {
+        let newer = vec![0; 70000];
+        let mut older = newer.clone();
+        older[69999] = 1;
+        let delta = encode(&older, &newer);
+        assert!(delta.len() < 8);
+        assert_eq!(decode(&delta, &newer), older);
+    }
+
+    #[test]
+    fn test_ring_pops_newest_first() {
+        let mut rewind = Rewind::new(1, 8);
+        rewind.push(vec![1, 1]);
+        rewind.push(vec![1, 2]);
+        rewind.push(vec![3, 2]);
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.pop(), Some(vec![3, 2]));
+        assert_eq!(rewind.pop(), Some(vec![1, 2]));
+        assert_eq!(rewind.pop(), Some(vec![1, 1]));
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn test_ring_is_bounded() {
+        let mut rewind = Rewind::new(1, 2);
+        for i in 0..5 {
+            rewind.push(vec![i]);
+        }
+        assert_eq!(rewind.len(), 2);
+        assert_eq!(rewind.pop(), Some(vec![4]));
+        assert_eq!(rewind.pop(), Some(vec![3]));
+        assert_eq!(rewind.pop(), None);
+    }
+
+    #[test]
+    fn test_cpu_rewind() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xe8, 0xe8, 0xe8, 0xe8, 0xe8, 0xe8, 0x00])
+            .unwrap();
+        cpu.enable_rewind(2, 16);
+        cpu.run();
+        assert_eq!(cpu.register_x.0, 6);
+
+        /* snapshots were taken before instructions 0, 2, 4 and 6 */
+        assert!(cpu.rewind());
+        assert_eq!(cpu.register_x.0, 6);
+        assert!(cpu.rewind());
+        assert_eq!(cpu.register_x.0, 4);
+        assert!(cpu.rewind());
+        assert!(cpu.rewind());
+        assert_eq!(cpu.register_x.0, 0);
+        assert_eq!(cpu.program_counter, 0x8000);
+        assert!(!cpu.rewind());
+    }
+
+    #[test]
+    fn test_snapshots_are_compressed() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xe8, 0xe8, 0xe8, 0xe8, 0x00]).unwrap();
+        cpu.enable_rewind(1, 16);
+        cpu.run();
+        let rewind = cpu.rewind_buffer().unwrap();
+        assert_eq!(rewind.len(), 5);
+        assert!(rewind.stored_bytes() < cpu.save_state().len() + 100);
+    }
+}
@@ -0,0 +1,286 @@
+/*
+ * iNES ROM images: a 16 byte header, an optional 512 byte trainer, then the
+ * PRG ROM in 16KB banks and the CHR ROM in 8KB banks. The header also has
+ * the mapper number and how the cartridge wires up nametable mirroring.
+ * NES 2.0 headers are only read as far as the region and console type.
+ */
+
+use crate::CPU;
+
+const INES_MAGIC: &[u8; 4] = b"NES\x1A";
+const PRG_BANK: usize = 0x4000;
+const CHR_BANK: usize = 0x2000;
+
+/* which console the game was made for, it decides the timing */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    /* runs on either */
+    Multi,
+    /* famiclones: PAL's scanline count with NTSC's CPU to PPU ratio */
+    Dendy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Console {
+    /* the NES or Famicom */
+    Home,
+    VsSystem,
+    PlayChoice,
+    /* one of NES 2.0's extended console types, famiclones and the like */
+    Extended,
+}
+
+/* how the PPU's two nametables fill its four screens */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    /* $2000 and $2400 are the same, for vertical scrolling */
+    Horizontal,
+    Vertical,
+    /* the cartridge brings its own VRAM for all four */
+    FourScreen,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rom {
+    pub prg: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub mapper: u8,
+    pub mirroring: Mirroring,
+    pub region: Region,
+    pub console: Console,
+}
+
+fn is_nes2(header: &[u8]) -> bool {
+    header[7] & 0x0C == 0x08
+}
+
+fn region(header: &[u8]) -> Region {
+    let nes2 = is_nes2(header);
+    match (nes2, header[12] & 0x03, header[9] & 0x01) {
+        (true, 0, _) => Region::Ntsc,
+        (true, 1, _) => Region::Pal,
+        (true, 2, _) => Region::Multi,
+        (true, _, _) => Region::Dendy,
+        /* plain iNES only has a PAL bit, and few dumps set it */
+        (false, _, 1) => Region::Pal,
+        (false, _, _) => Region::Ntsc,
+    }
+}
+
+fn mirroring(header: &[u8]) -> Mirroring {
+    match (header[6] & 0x08, header[6] & 0x01) {
+        (0x08, _) => Mirroring::FourScreen,
+        (_, 0x01) => Mirroring::Vertical,
+        _ => Mirroring::Horizontal,
+    }
+}
+
+fn console(header: &[u8]) -> Console {
+    match header[7] & 0x03 {
+        0 => Console::Home,
+        1 => Console::VsSystem,
+        /* plain iNES sets bit 1 for PlayChoice, NES 2.0 uses 3 for extended */
+        3 if is_nes2(header) => Console::Extended,
+        _ => Console::PlayChoice,
+    }
+}
+
+/*
+ * The region from GoodNES and No-Intro style tags in a file name, like
+ * "(E)" or "(USA, Europe)", for headers too old to say.
+ */
+pub fn region_from_name(name: &str) -> Option<Region> {
+    let (mut ntsc, mut pal, mut dendy) = (false, false, false);
+    for tag in name.split('(').skip(1).filter_map(|t| t.split(')').next()) {
+        for word in tag.split(',').map(str::trim) {
+            match word {
+                "U" | "J" | "JU" | "USA" | "Japan" | "Canada" | "Korea" | "NTSC" => ntsc = true,
+                "E" | "Europe" | "PAL" | "Australia" | "Germany" | "France" | "Spain" | "Italy"
+                | "Sweden" | "Netherlands" | "UK" => pal = true,
+                "Dendy" => dendy = true,
+                "W" | "World" => (ntsc, pal) = (true, true),
+                _ => {}
+            }
+        }
+    }
+    match (ntsc, pal, dendy) {
+        (_, _, true) => Some(Region::Dendy),
+        (true, true, _) => Some(Region::Multi),
+        (true, _, _) => Some(Region::Ntsc),
+        (_, true, _) => Some(Region::Pal),
+        _ => None,
+    }
+}
+
+impl Rom {
+    /* an NROM cartridge with nothing on it but `prg` */
+    pub fn from_prg(prg: Vec<u8>) -> Rom {
+        Rom {
+            prg,
+            chr: Vec::new(),
+            mapper: 0,
+            mirroring: Mirroring::Horizontal,
+            region: Region::Ntsc,
+            console: Console::Home,
+        }
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Rom, String> {
+        if data.get(..4) != Some(INES_MAGIC) {
+            return Err("not an iNES file".to_string());
+        }
+        let header = data.get(..16).ok_or("iNES header is truncated")?;
+        let prg_len = header[4] as usize * PRG_BANK;
+        let chr_len = header[5] as usize * CHR_BANK;
+        let mapper = (header[7] & 0xF0) | (header[6] >> 4);
+        let prg_start = 16 + if header[6] & 0x04 != 0 { 512 } else { 0 };
+        let chr_start = prg_start + prg_len;
+        let prg = data
+            .get(prg_start..chr_start)
+            .ok_or("PRG ROM is truncated")?
+            .to_vec();
+        let chr = data
+            .get(chr_start..chr_start + chr_len)
+            .ok_or("CHR ROM is truncated")?
+            .to_vec();
+        Ok(Rom {
+            prg,
+            chr,
+            mapper,
+            mirroring: mirroring(header),
+            region: region(header),
+            console: console(header),
+        })
+    }
+
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Rom, String> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut rom = Rom::parse(&data).map_err(|e| format!("{}: {}", path.display(), e))?;
+        /* NES 2.0 headers are trusted, older ones fall back on the name */
+        if !is_nes2(&data) {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if let Some(region) = region_from_name(&name) {
+                rom.region = region;
+            }
+        }
+        Ok(rom)
+    }
+}
+
+impl CPU {
+    pub fn load_rom(&mut self, rom: &Rom) -> Result<(), String> {
+        self.bus.insert_cartridge(rom.clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ines(prg_banks: u8, flags6: u8, prg: &[u8]) -> Vec<u8> {
+        let mut data = INES_MAGIC.to_vec();
+        data.extend_from_slice(&[prg_banks, 1, flags6, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let mut bank = prg.to_vec();
+        bank.resize(prg_banks as usize * PRG_BANK, 0);
+        data.extend(bank);
+        data.extend(vec![0; CHR_BANK]);
+        data
+    }
+
+    #[test]
+    fn test_parse() {
+        let rom = Rom::parse(&ines(1, 0x10, &[0xEA])).unwrap();
+        assert_eq!(rom.prg.len(), PRG_BANK);
+        assert_eq!(rom.chr.len(), CHR_BANK);
+        assert_eq!(rom.prg[0], 0xEA);
+        assert_eq!(rom.mapper, 1);
+
+        assert!(Rom::parse(b"NES").is_err());
+        assert!(Rom::parse(&ines(2, 0, &[])[..0x5000]).is_err());
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let parse = |flags6| Rom::parse(&ines(1, flags6, &[])).unwrap().mirroring;
+        assert_eq!(parse(0x00), Mirroring::Horizontal);
+        assert_eq!(parse(0x01), Mirroring::Vertical);
+        assert_eq!(parse(0x09), Mirroring::FourScreen);
+    }
+
+    #[test]
+    fn test_region() {
+        let mut data = ines(1, 0, &[]);
+        assert_eq!(Rom::parse(&data).unwrap().region, Region::Ntsc);
+        data[9] = 0x01;
+        assert_eq!(Rom::parse(&data).unwrap().region, Region::Pal);
+
+        /* NES 2.0 ignores the old bit, byte 12 has the region */
+        data[7] = 0x08;
+        assert_eq!(Rom::parse(&data).unwrap().region, Region::Ntsc);
+        data[12] = 0x01;
+        assert_eq!(Rom::parse(&data).unwrap().region, Region::Pal);
+        data[12] = 0x02;
+        assert_eq!(Rom::parse(&data).unwrap().region, Region::Multi);
+        data[12] = 0x03;
+        assert_eq!(Rom::parse(&data).unwrap().region, Region::Dendy);
+    }
+
+    #[test]
+    fn test_console() {
+        let mut data = ines(1, 0, &[]);
+        assert_eq!(Rom::parse(&data).unwrap().console, Console::Home);
+        data[7] = 0x01;
+        assert_eq!(Rom::parse(&data).unwrap().console, Console::VsSystem);
+        data[7] = 0x02;
+        assert_eq!(Rom::parse(&data).unwrap().console, Console::PlayChoice);
+        data[7] = 0x0B;
+        assert_eq!(Rom::parse(&data).unwrap().console, Console::Extended);
+    }
+
+    #[test]
+    fn test_region_from_name() {
+        assert_eq!(region_from_name("Elite (E) [!].nes"), Some(Region::Pal));
+        assert_eq!(region_from_name("Zelda (USA).nes"), Some(Region::Ntsc));
+        assert_eq!(
+            region_from_name("Tetris (USA, Europe) (Rev 1).nes"),
+            Some(Region::Multi)
+        );
+        assert_eq!(
+            region_from_name("Homebrew (Dendy).nes"),
+            Some(Region::Dendy)
+        );
+        assert_eq!(region_from_name("nestest.nes"), None);
+    }
+
+    #[test]
+    fn test_load_falls_back_on_name() {
+        let dir = std::env::temp_dir().join(format!("nes-rom-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut data = ines(1, 0, &[]);
+        std::fs::write(dir.join("Game (Europe).nes"), &data).unwrap();
+        /* an NES 2.0 header saying NTSC wins over the name */
+        data[7] = 0x08;
+        std::fs::write(dir.join("Other (Europe).nes"), &data).unwrap();
+
+        let old = Rom::load(dir.join("Game (Europe).nes")).unwrap();
+        let nes2 = Rom::load(dir.join("Other (Europe).nes")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(old.region, Region::Pal);
+        assert_eq!(nes2.region, Region::Ntsc);
+    }
+
+    #[test]
+    fn test_nrom_mirrors_16k() {
+        let rom = Rom::parse(&ines(1, 0, &[0xA9, 0x42])).unwrap();
+        let mut cpu = CPU::new();
+        cpu.load_rom(&rom).unwrap();
+        assert_eq!(cpu.peek(0x8000), 0xA9);
+        assert_eq!(cpu.peek(0xC001), 0x42);
+
+        let mapper1 = Rom::parse(&ines(1, 0x10, &[])).unwrap();
+        assert!(cpu.load_rom(&mapper1).is_err());
+    }
+}
@@ -0,0 +1,613 @@
+use std::fmt;
+use std::num::Wrapping;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::status::StatusFlags;
+use crate::CPU;
+
+const MAGIC: &[u8; 4] = b"NESS";
+const FORMAT_VERSION: u16 = 1;
+
+/*
+ * After the magic and format version a state is a list of sections, each
+ * with a tag, its own version and a length. Readers skip sections they don't
+ * know and default the ones that are missing, so components can be added, or
+ * their layout bumped, without breaking older states.
+ */
+const CPU_SECTION: [u8; 4] = *b"CPU ";
+/* version 1 had no cycle count */
+const CPU_SECTION_VERSION: u16 = 2;
+const RAM_SECTION: [u8; 4] = *b"RAM ";
+/*
+ * Versions 1 and 2 were the whole flat 64KB address space, version 1 one
+ * byte short of it. Loading one takes its RAM and PRG RAM ranges.
+ */
+const RAM_SECTION_VERSION: u16 = 3;
+const PRG_RAM_SECTION: [u8; 4] = *b"PRAM";
+const PRG_RAM_SECTION_VERSION: u16 = 1;
+/* the open bus value */
+const BUS_SECTION: [u8; 4] = *b"BUS ";
+const BUS_SECTION_VERSION: u16 = 1;
+const INFO_SECTION: [u8; 4] = *b"INFO";
+const INFO_SECTION_VERSION: u16 = 1;
+
+/*
+ * What a slot picker shows about a state without loading it. Written first
+ * so it can be read without walking the rest of the file.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotInfo {
+    /* seconds since the Unix epoch */
+    pub timestamp: u64,
+    /* CRC-32 of the ROM the state was made with */
+    pub rom_crc: u32,
+    pub frame: u64,
+}
+
+impl SlotInfo {
+    pub fn now(rom_crc: u32, frame: u64) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        SlotInfo {
+            timestamp,
+            rom_crc,
+            frame,
+        }
+    }
+
+    /* None for states saved without slot info */
+    pub fn read(data: &[u8]) -> Result<Option<SlotInfo>, StateError> {
+        let mut r = read_header(data)?;
+        while !r.is_empty() {
+            let section = r.section()?;
+            if section.tag == INFO_SECTION {
+                section.check_version(INFO_SECTION_VERSION)?;
+                let mut s = section.reader();
+                return Ok(Some(SlotInfo {
+                    timestamp: s.u64()?,
+                    rom_crc: s.u32()?,
+                    frame: s.u64()?,
+                }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[derive(Debug)]
+pub enum StateError {
+    BadMagic,
+    Truncated,
+    WrongMemorySize(usize),
+    UnsupportedVersion(u16),
+    UnsupportedSection { tag: [u8; 4], version: u16 },
+    WrongRom { expected: u32, found: u32 },
+    Io(std::io::Error),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::WrongMemorySize(len) => {
+                write!(f, "save state has {} bytes of memory", len)
+            }
+            StateError::UnsupportedVersion(version) => write!(
+                f,
+                "save state format version {} is newer than the supported version {}",
+                version, FORMAT_VERSION
+            ),
+            StateError::UnsupportedSection { tag, version } => write!(
+                f,
+                "save state section '{}' has unsupported version {}",
+                String::from_utf8_lossy(tag).trim_end(),
+                version
+            ),
+            StateError::WrongRom { expected, found } => write!(
+                f,
+                "save state is for ROM {:08X}, not {:08X}",
+                found, expected
+            ),
+            StateError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl From<std::io::Error> for StateError {
+    fn from(e: std::io::Error) -> Self {
+        StateError::Io(e)
+    }
+}
+
+pub(crate) struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub(crate) fn new() -> Self {
+        StateWriter { buf: Vec::new() }
+    }
+
+    pub(crate) fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    pub(crate) fn u16(&mut self, v: u16) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub(crate) fn u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub(crate) fn u64(&mut self, v: u64) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub(crate) fn bytes(&mut self, v: &[u8]) {
+        self.buf.extend_from_slice(v);
+    }
+
+    pub(crate) fn section(
+        &mut self,
+        tag: [u8; 4],
+        version: u16,
+        payload: impl FnOnce(&mut StateWriter),
+    ) {
+        let mut section = StateWriter::new();
+        payload(&mut section);
+        self.bytes(&tag);
+        self.u16(version);
+        self.u32(section.buf.len() as u32);
+        self.bytes(&section.buf);
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub(crate) struct Section<'a> {
+    pub(crate) tag: [u8; 4],
+    pub(crate) version: u16,
+    pub(crate) data: &'a [u8],
+}
+
+impl Section<'_> {
+    pub(crate) fn reader(&self) -> StateReader<'_> {
+        StateReader::new(self.data)
+    }
+
+    pub(crate) fn check_version(&self, supported: u16) -> Result<(), StateError> {
+        if self.version > supported {
+            return Err(StateError::UnsupportedSection {
+                tag: self.tag,
+                version: self.version,
+            });
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        StateReader { data, pos: 0 }
+    }
+
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let end = self.pos.checked_add(len).ok_or(StateError::Truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or(StateError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, StateError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, StateError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    pub(crate) fn section(&mut self) -> Result<Section<'a>, StateError> {
+        let tag = self.bytes(4)?.try_into().unwrap();
+        let version = self.u16()?;
+        let len = self.u32()? as usize;
+        let data = self.bytes(len)?;
+        Ok(Section { tag, version, data })
+    }
+}
+
+fn read_header(data: &[u8]) -> Result<StateReader<'_>, StateError> {
+    let mut r = StateReader::new(data);
+    if r.bytes(MAGIC.len())? != MAGIC {
+        return Err(StateError::BadMagic);
+    }
+    let version = r.u16()?;
+    if version > FORMAT_VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+    Ok(r)
+}
+
+/*
+ * Save states only cover what's emulated so far, the CPU registers, RAM,
+ * PRG RAM and open bus. ROM comes from the cartridge, so a state loads on top of
+ * whatever is already in the bus. Debugging aids (history, coverage, watches...)
+ * aren't machine state and are left alone by load_state().
+ */
+impl CPU {
+    pub fn save_state(&self) -> Vec<u8> {
+        self.write_state(None)
+    }
+
+    /* a state for a save slot, with the info a frontend shows in its picker */
+    pub fn save_slot(&self, info: &SlotInfo) -> Vec<u8> {
+        self.write_state(Some(info))
+    }
+
+    /* like load_state, but refuses a slot saved with a different ROM */
+    pub fn load_slot(&mut self, data: &[u8], rom_crc: u32) -> Result<(), StateError> {
+        if let Some(info) = SlotInfo::read(data)? {
+            if info.rom_crc != rom_crc {
+                return Err(StateError::WrongRom {
+                    expected: rom_crc,
+                    found: info.rom_crc,
+                });
+            }
+        }
+        self.load_state(data)
+    }
+
+    fn write_state(&self, info: Option<&SlotInfo>) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        w.bytes(MAGIC);
+        w.u16(FORMAT_VERSION);
+        if let Some(info) = info {
+            w.section(INFO_SECTION, INFO_SECTION_VERSION, |w| {
+                w.u64(info.timestamp);
+                w.u32(info.rom_crc);
+                w.u64(info.frame);
+            });
+        }
+        w.section(CPU_SECTION, CPU_SECTION_VERSION, |w| {
+            w.u8(self.register_a.0);
+            w.u8(self.register_x.0);
+            w.u8(self.register_y.0);
+            w.u8(self.status.bits());
+            w.u16(self.program_counter);
+            w.u8(self.stack_pointer);
+            w.u64(self.cycles);
+        });
+        w.section(RAM_SECTION, RAM_SECTION_VERSION, |w| {
+            w.bytes(self.bus.ram())
+        });
+        w.section(PRG_RAM_SECTION, PRG_RAM_SECTION_VERSION, |w| {
+            w.bytes(self.bus.prg_ram())
+        });
+        w.section(BUS_SECTION, BUS_SECTION_VERSION, |w| {
+            w.u8(self.bus.open_bus)
+        });
+        w.finish()
+    }
+
+    /* the state is fully validated before anything is overwritten */
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut r = read_header(data)?;
+
+        /* missing sections fall back to power-on state */
+        let fresh = CPU::new();
+        let mut registers = (
+            fresh.register_a.0,
+            fresh.register_x.0,
+            fresh.register_y.0,
+            fresh.status.bits(),
+            fresh.program_counter,
+            fresh.stack_pointer,
+        );
+        let mut cycles = fresh.cycles;
+        let mut ram = None;
+        let mut prg_ram = None;
+        let mut open_bus = fresh.bus.open_bus;
+        while !r.is_empty() {
+            let section = r.section()?;
+            match section.tag {
+                CPU_SECTION => {
+                    section.check_version(CPU_SECTION_VERSION)?;
+                    let mut s = section.reader();
+                    registers = (s.u8()?, s.u8()?, s.u8()?, s.u8()?, s.u16()?, s.u8()?);
+                    if section.version >= 2 {
+                        cycles = s.u64()?;
+                    }
+                }
+                RAM_SECTION => {
+                    section.check_version(RAM_SECTION_VERSION)?;
+                    let expected = match section.version {
+                        1 => 0xFFFF,
+                        2 => 0x10000,
+                        _ => self.bus.ram().len(),
+                    };
+                    if section.data.len() != expected {
+                        return Err(StateError::WrongMemorySize(section.data.len()));
+                    }
+                    if section.version < 3 {
+                        ram = Some(&section.data[..0x0800]);
+                        prg_ram = prg_ram.or(Some(&section.data[0x6000..0x8000]));
+                    } else {
+                        ram = Some(section.data);
+                    }
+                }
+                PRG_RAM_SECTION => {
+                    section.check_version(PRG_RAM_SECTION_VERSION)?;
+                    if section.data.len() != self.bus.prg_ram().len() {
+                        return Err(StateError::WrongMemorySize(section.data.len()));
+                    }
+                    prg_ram = Some(section.data);
+                }
+                BUS_SECTION => {
+                    section.check_version(BUS_SECTION_VERSION)?;
+                    open_bus = section.reader().u8()?;
+                }
+                _ => {}
+            }
+        }
+
+        let (register_a, register_x, register_y, status, program_counter, stack_pointer) =
+            registers;
+        self.register_a = Wrapping(register_a);
+        self.register_x = Wrapping(register_x);
+        self.register_y = Wrapping(register_y);
+        self.status = StatusFlags::from_bits(status);
+        self.program_counter = program_counter;
+        self.stack_pointer = stack_pointer;
+        self.cycles = cycles;
+        self.bus.ram_mut().fill(0);
+        if let Some(ram) = ram {
+            self.bus.ram_mut().copy_from_slice(ram);
+        }
+        self.bus.open_bus = open_bus;
+        self.bus.prg_ram_mut().fill(0);
+        if let Some(prg_ram) = prg_ram {
+            self.bus.prg_ram_mut().copy_from_slice(prg_ram);
+        }
+        Ok(())
+    }
+
+    pub fn save_state_to(&self, path: impl AsRef<Path>) -> Result<(), StateError> {
+        std::fs::write(path, self.save_state())?;
+        Ok(())
+    }
+
+    pub fn load_state_from(&mut self, path: impl AsRef<Path>) -> Result<(), StateError> {
+        let data = std::fs::read(path)?;
+        self.load_state(&data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Mem;
+
+    #[test]
+    fn test_round_trip() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xa9, 0x05, 0x85, 0x10, 0xaa, 0xe8, 0x00])
+            .unwrap();
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        let state = cpu.save_state();
+        cpu.run();
+        assert_eq!(cpu.register_x.0, 0x06);
+
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.register_a.0, 0x05);
+        assert_eq!(cpu.register_x.0, 0x00);
+        assert_eq!(cpu.program_counter, 0x8004);
+        assert_eq!(cpu.mem_read(0x10), 0x05);
+
+        cpu.run();
+        assert_eq!(cpu.register_x.0, 0x06);
+    }
+
+    #[test]
+    fn test_load_into_fresh_cpu() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xa9, 0x42, 0x85, 0x10, 0x00]).unwrap();
+        cpu.run();
+
+        let mut other = CPU::new();
+        other.load_state(&cpu.save_state()).unwrap();
+        assert_eq!(other.register_a.0, 0x42);
+        assert_eq!(other.peek(0x10), 0x42);
+        assert_eq!(other.cycles, 12);
+        assert_eq!(other.save_state(), cpu.save_state());
+    }
+
+    #[test]
+    fn test_bad_states_are_rejected() {
+        let mut cpu = CPU::new();
+        let state = cpu.save_state();
+        assert!(matches!(cpu.load_state(b"nope"), Err(StateError::BadMagic)));
+        assert!(matches!(
+            cpu.load_state(&state[..state.len() - 1]),
+            Err(StateError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn test_failed_load_leaves_cpu_untouched() {
+        let mut cpu = CPU::new();
+        let state = cpu.save_state();
+        cpu.register_a = Wrapping(0x99);
+        assert!(cpu.load_state(&state[..10]).is_err());
+        assert_eq!(cpu.register_a.0, 0x99);
+    }
+
+    fn header() -> StateWriter {
+        let mut w = StateWriter::new();
+        w.bytes(MAGIC);
+        w.u16(FORMAT_VERSION);
+        w
+    }
+
+    #[test]
+    fn test_unknown_sections_are_skipped() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xa9, 0x42, 0x00]).unwrap();
+        cpu.run();
+        let mut state = cpu.save_state();
+        let mut extra = StateWriter::new();
+        extra.section(*b"PPU ", 7, |w| w.bytes(&[1, 2, 3]));
+        state.extend(extra.finish());
+
+        let mut other = CPU::new();
+        other.load_state(&state).unwrap();
+        assert_eq!(other.register_a.0, 0x42);
+    }
+
+    #[test]
+    fn test_missing_sections_are_defaulted() {
+        let mut w = header();
+        w.section(CPU_SECTION, 1, |w| {
+            w.bytes(&[0x01, 0x02, 0x03, 0x00]);
+            w.u16(0x8000);
+            w.u8(0xFD);
+        });
+
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x55);
+        cpu.load_state(&w.finish()).unwrap();
+        assert_eq!(cpu.register_y.0, 0x03);
+        assert_eq!(cpu.stack_pointer, 0xFD);
+        assert_eq!(cpu.mem_read(0x10), 0x00);
+    }
+
+    #[test]
+    fn test_short_version_1_memory() {
+        let mut w = header();
+        w.section(RAM_SECTION, 1, |w| w.bytes(&[0x42; 0xFFFF]));
+        let mut cpu = CPU::new();
+        cpu.load_state(&w.finish()).unwrap();
+        assert_eq!(cpu.peek(0x07FF), 0x42);
+        assert_eq!(cpu.peek(0x7FFF), 0x42);
+        /* the ROM part of it is dropped */
+        assert_eq!(cpu.peek(0x8000), 0x00);
+    }
+
+    #[test]
+    fn test_flat_version_2_memory() {
+        let mut memory = vec![0; 0x10000];
+        memory[0x0010] = 0x11;
+        memory[0x6000] = 0x22;
+        let mut w = header();
+        w.section(RAM_SECTION, 2, |w| w.bytes(&memory));
+        let mut cpu = CPU::new();
+        cpu.load_state(&w.finish()).unwrap();
+        assert_eq!(cpu.peek(0x0810), 0x11);
+        assert_eq!(cpu.peek(0x6000), 0x22);
+
+        let mut w = header();
+        w.section(RAM_SECTION, 2, |w| w.bytes(&memory[..0x0800]));
+        assert!(matches!(
+            cpu.load_state(&w.finish()),
+            Err(StateError::WrongMemorySize(0x0800))
+        ));
+    }
+
+    #[test]
+    fn test_newer_format_is_rejected() {
+        let mut w = StateWriter::new();
+        w.bytes(MAGIC);
+        w.u16(FORMAT_VERSION + 1);
+        let err = CPU::new().load_state(&w.finish()).unwrap_err();
+        assert!(matches!(err, StateError::UnsupportedVersion(2)));
+    }
+
+    #[test]
+    fn test_newer_section_is_rejected() {
+        let mut w = header();
+        w.section(CPU_SECTION, CPU_SECTION_VERSION + 1, |w| w.bytes(&[0; 16]));
+        let err = CPU::new().load_state(&w.finish()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "save state section 'CPU' has unsupported version 3"
+        );
+    }
+
+    #[test]
+    fn test_slot_info() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xa9, 0x42, 0x00]).unwrap();
+        cpu.run();
+        let info = SlotInfo {
+            timestamp: 0x1_2345_6789,
+            rom_crc: 0xCAFEBABE,
+            frame: 600,
+        };
+        let slot = cpu.save_slot(&info);
+        assert_eq!(SlotInfo::read(&slot).unwrap(), Some(info));
+        assert_eq!(SlotInfo::read(&cpu.save_state()).unwrap(), None);
+
+        let mut other = CPU::new();
+        other.load_slot(&slot, 0xCAFEBABE).unwrap();
+        assert_eq!(other.register_a.0, 0x42);
+        /* plain load_state doesn't care about the info */
+        CPU::new().load_state(&slot).unwrap();
+    }
+
+    #[test]
+    fn test_slot_from_other_rom_is_rejected() {
+        let slot = CPU::new().save_slot(&SlotInfo::now(0x1111_1111, 0));
+        let mut cpu = CPU::new();
+        cpu.register_a = Wrapping(0x99);
+        let err = cpu.load_slot(&slot, 0x2222_2222).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "save state is for ROM 11111111, not 22222222"
+        );
+        assert_eq!(cpu.register_a.0, 0x99);
+
+        /* states without info can't be checked, so they're let through */
+        cpu.load_slot(&CPU::new().save_state(), 0x2222_2222)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_file_round_trip() {
+        let path = std::env::temp_dir().join(format!("nes-state-{}.bin", std::process::id()));
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xa9, 0x42, 0x00]).unwrap();
+        cpu.run();
+        cpu.save_state_to(&path).unwrap();
+
+        let mut other = CPU::new();
+        other.load_state_from(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(other.register_a.0, 0x42);
+    }
+}
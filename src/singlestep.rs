@@ -0,0 +1,350 @@
+/*
+ * Runner for Tom Harte's ProcessorTests (SingleStepTests) 6502 corpus: one
+ * JSON file per opcode, each holding thousands of cases of
+ *
+ *   {"name": ..., "initial": {pc, s, a, x, y, p, ram}, "final": {...},
+ *    "cycles": [[addr, value, "read"|"write"], ...]}
+ *
+ * A case loads the initial registers and RAM, runs one instruction and
+ * compares registers and RAM against the final state. There's no bus
+ * cycle recording yet so the cycle lists are parsed but not checked.
+ *
+ * Point NES_SINGLESTEP_DIR at the nes6502/v1 directory to run the corpus
+ * from `cargo test`, and NES_SINGLESTEP_OPCODES at a comma separated list
+ * of opcodes (e.g. "a9,85") to run only those files.
+ */
+
+use std::fmt;
+use std::num::Wrapping;
+use std::path::Path;
+
+use crate::bus::Bus;
+use crate::json::Value;
+use crate::status::StatusFlags;
+use crate::{CpuError, CPU};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct State {
+    pub pc: u16,
+    pub s: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub ram: Vec<(u16, u8)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    pub name: String,
+    pub initial: State,
+    pub final_state: State,
+    pub cycles: Vec<(u16, u8, bool)>,
+}
+
+/* what one opcode file came to */
+#[derive(Debug, Default)]
+pub struct Report {
+    pub passed: usize,
+    pub failed: usize,
+    /* the first few failures, described */
+    pub failures: Vec<String>,
+}
+
+const MAX_FAILURES: usize = 5;
+
+fn number<T: TryFrom<u64>>(value: Option<&Value>, what: &str) -> Result<T, String> {
+    value
+        .and_then(Value::as_u64)
+        .and_then(|n| T::try_from(n).ok())
+        .ok_or_else(|| format!("bad or missing '{}'", what))
+}
+
+fn parse_state(value: Option<&Value>) -> Result<State, String> {
+    let value = value.ok_or("missing state")?;
+    let ram = value
+        .get("ram")
+        .and_then(Value::as_array)
+        .ok_or("missing 'ram'")?
+        .iter()
+        .map(|pair| {
+            let pair = pair.as_array().ok_or("bad ram entry")?;
+            Ok((number(pair.first(), "ram")?, number(pair.get(1), "ram")?))
+        })
+        .collect::<Result<_, String>>()?;
+    Ok(State {
+        pc: number(value.get("pc"), "pc")?,
+        s: number(value.get("s"), "s")?,
+        a: number(value.get("a"), "a")?,
+        x: number(value.get("x"), "x")?,
+        y: number(value.get("y"), "y")?,
+        p: number(value.get("p"), "p")?,
+        ram,
+    })
+}
+
+fn parse_cycle(value: &Value) -> Result<(u16, u8, bool), String> {
+    let cycle = value.as_array().ok_or("bad cycle")?;
+    let write = cycle.get(2).and_then(Value::as_str) == Some("write");
+    Ok((
+        number(cycle.first(), "cycle")?,
+        number(cycle.get(1), "cycle")?,
+        write,
+    ))
+}
+
+fn parse_case(value: &Value) -> Result<Case, String> {
+    let name = value
+        .get("name")
+        .and_then(Value::as_str)
+        .ok_or("missing 'name'")?
+        .to_string();
+    let cycles = match value.get("cycles").and_then(Value::as_array) {
+        Some(cycles) => cycles.iter().map(parse_cycle).collect::<Result<_, _>>()?,
+        None => Vec::new(),
+    };
+    Ok(Case {
+        initial: parse_state(value.get("initial"))?,
+        final_state: parse_state(value.get("final"))?,
+        name,
+        cycles,
+    })
+}
+
+pub fn parse_cases(text: &str) -> Result<Vec<Case>, String> {
+    Value::parse(text)?
+        .as_array()
+        .ok_or("expected an array of cases")?
+        .iter()
+        .enumerate()
+        .map(|(i, case)| parse_case(case).map_err(|e| format!("case {}: {}", i, e)))
+        .collect()
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+            self.pc, self.a, self.x, self.y, self.p, self.s
+        )
+    }
+}
+
+impl Case {
+    /* Ok, or a description of every register and byte that came out wrong */
+    pub fn run(&self) -> Result<(), String> {
+        let cpu = self
+            .execute()
+            .map_err(|e| format!("{}: {}", self.name, e))?;
+        self.check(&cpu)
+    }
+
+    /* the CPU after the instruction, which can be a BRK */
+    fn execute(&self) -> Result<CPU, CpuError> {
+        /* the corpus treats all 64KB as RAM */
+        let mut cpu = CPU::new();
+        cpu.bus = Bus::flat();
+        cpu.program_counter = self.initial.pc;
+        cpu.stack_pointer = self.initial.s;
+        cpu.register_a = Wrapping(self.initial.a);
+        cpu.register_x = Wrapping(self.initial.x);
+        cpu.register_y = Wrapping(self.initial.y);
+        cpu.status = StatusFlags::from_bits(self.initial.p);
+        for &(addr, value) in &self.initial.ram {
+            cpu.poke(addr, value);
+        }
+        match cpu.step() {
+            Err(e @ CpuError::UnimplementedOpcode { .. }) => Err(e),
+            _ => Ok(cpu),
+        }
+    }
+
+    fn check(&self, cpu: &CPU) -> Result<(), String> {
+        let actual = State {
+            pc: cpu.program_counter,
+            s: cpu.stack_pointer,
+            a: cpu.register_a.0,
+            x: cpu.register_x.0,
+            y: cpu.register_y.0,
+            p: cpu.status.bits(),
+            ram: Vec::new(),
+        };
+        let mut problems = Vec::new();
+        if actual.to_string() != self.final_state.to_string() {
+            problems.push(format!("expected {}, got {}", self.final_state, actual));
+        }
+        for &(addr, value) in &self.final_state.ram {
+            if cpu.peek(addr) != value {
+                problems.push(format!(
+                    "${:04X} is {:02X}, expected {:02X}",
+                    addr,
+                    cpu.peek(addr),
+                    value
+                ));
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("{}: {}", self.name, problems.join(", ")))
+        }
+    }
+}
+
+/*
+ * Runs every case in one opcode file. An opcode the CPU doesn't implement
+ * yet is reported as a single failure rather than thousands, since every
+ * case would fail the same way, and the cases not yet run count as failed.
+ */
+pub fn run_file(path: impl AsRef<Path>) -> Result<Report, String> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let cases = parse_cases(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut report = Report::default();
+    for case in &cases {
+        let failure = match case.execute() {
+            Ok(cpu) => match case.check(&cpu) {
+                Ok(()) => {
+                    report.passed += 1;
+                    continue;
+                }
+                Err(e) => e,
+            },
+            Err(e) => {
+                report.failed += cases.len() - report.passed - report.failed;
+                report.failures.push(format!("{}: {}", case.name, e));
+                break;
+            }
+        };
+        report.failed += 1;
+        if report.failures.len() < MAX_FAILURES {
+            report.failures.push(failure);
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LDA_IMMEDIATE: &str = r#"[
+        {"name": "a9 42 00",
+         "initial": {"pc": 1000, "s": 253, "a": 0, "x": 0, "y": 0, "p": 38,
+                     "ram": [[1000, 169], [1001, 66]]},
+         "final": {"pc": 1002, "s": 253, "a": 66, "x": 0, "y": 0, "p": 36,
+                   "ram": [[1000, 169], [1001, 66]]},
+         "cycles": [[1000, 169, "read"], [1001, 66, "read"]]},
+        {"name": "a9 00 00",
+         "initial": {"pc": 65534, "s": 253, "a": 5, "x": 0, "y": 0, "p": 36,
+                     "ram": [[65534, 169], [65535, 0]]},
+         "final": {"pc": 0, "s": 253, "a": 0, "x": 0, "y": 0, "p": 38,
+                   "ram": [[65534, 169], [65535, 0]]},
+         "cycles": [[65534, 169, "read"], [65535, 0, "read"]]}
+    ]"#;
+
+    #[test]
+    fn test_parse_cases() {
+        let cases = parse_cases(LDA_IMMEDIATE).unwrap();
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[0].initial.pc, 1000);
+        assert_eq!(cases[0].final_state.a, 66);
+        assert_eq!(cases[0].cycles[1], (1001, 66, false));
+        assert!(parse_cases(r#"[{"name": "x"}]"#).is_err());
+    }
+
+    #[test]
+    fn test_case_passes() {
+        let cases = parse_cases(LDA_IMMEDIATE).unwrap();
+        assert_eq!(cases[0].run(), Ok(()));
+    }
+
+    #[test]
+    fn test_case_failure_is_described() {
+        let mut case = parse_cases(LDA_IMMEDIATE).unwrap().remove(0);
+        case.final_state.a = 0x43;
+        case.final_state.ram[1].1 = 0x99;
+        assert_eq!(
+            case.run(),
+            Err("a9 42 00: expected PC:03EA A:43 X:00 Y:00 P:24 SP:FD, \
+                 got PC:03EA A:42 X:00 Y:00 P:24 SP:FD, $03E9 is 42, expected 99"
+                .to_string())
+        );
+    }
+
+    #[test]
+    fn test_run_file() {
+        let path = std::env::temp_dir().join(format!("nes-singlestep-{}.json", std::process::id()));
+        std::fs::write(&path, LDA_IMMEDIATE.replace("[1000, 169]", "[1000, 2]")).unwrap();
+        let report = run_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        /* $02 (JAM) isn't implemented, both cases count as failed off the first */
+        assert_eq!(report.passed, 0);
+        assert_eq!(report.failed, 2);
+        assert_eq!(
+            report.failures,
+            ["a9 42 00: unimplemented opcode $02 at $03E8"]
+        );
+    }
+
+    #[test]
+    fn test_run_file_counts_each_case_once() {
+        let path =
+            std::env::temp_dir().join(format!("nes-singlestep-once-{}.json", std::process::id()));
+        let text = LDA_IMMEDIATE
+            .replace(
+                r#""pc": 1002, "s": 253, "a": 66"#,
+                r#""pc": 1002, "s": 253, "a": 67"#,
+            )
+            .replace("[65534, 169]", "[65534, 2]");
+        std::fs::write(&path, text).unwrap();
+        let report = run_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        /* the first case fails its check, then $02 stops the run on the second */
+        assert_eq!(report.passed, 0);
+        assert_eq!(report.failed, 2);
+        assert_eq!(report.failures.len(), 2);
+        assert_eq!(
+            report.failures[1],
+            "a9 00 00: unimplemented opcode $02 at $FFFE"
+        );
+    }
+
+    #[test]
+    fn test_corpus() {
+        let Ok(dir) = std::env::var("NES_SINGLESTEP_DIR") else {
+            return;
+        };
+        let only = std::env::var("NES_SINGLESTEP_OPCODES").ok();
+        let mut files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|e| e == "json"))
+            .filter(|path| {
+                let opcode = path.file_stem().unwrap().to_string_lossy().to_lowercase();
+                only.as_ref()
+                    .is_none_or(|only| only.split(',').any(|o| o.trim() == opcode))
+            })
+            .collect();
+        files.sort();
+
+        let mut failed = false;
+        for path in files {
+            let report = run_file(&path).unwrap();
+            println!(
+                "{}: {} passed, {} failed",
+                path.file_name().unwrap().to_string_lossy(),
+                report.passed,
+                report.failed
+            );
+            for failure in &report.failures {
+                println!("    {}", failure);
+            }
+            failed |= report.failed > 0;
+        }
+        assert!(!failed, "some SingleStepTests cases failed");
+    }
+}
@@ -0,0 +1,108 @@
+/*
+ * The processor status register, P. B and U aren't really stored in it,
+ * they only exist in the copies PHP and BRK push to the stack, but they're
+ * kept as flags so a pushed or pulled byte round-trips through the type.
+ */
+
+use std::ops::{BitAnd, BitOr, BitOrAssign, Not};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct StatusFlags(u8);
+
+impl StatusFlags {
+    pub const CARRY: StatusFlags = StatusFlags(0b0000_0001);
+    pub const ZERO: StatusFlags = StatusFlags(0b0000_0010);
+    pub const INTERRUPT_DISABLE: StatusFlags = StatusFlags(0b0000_0100);
+    pub const DECIMAL: StatusFlags = StatusFlags(0b0000_1000);
+    pub const BREAK: StatusFlags = StatusFlags(0b0001_0000);
+    pub const UNUSED: StatusFlags = StatusFlags(0b0010_0000);
+    pub const OVERFLOW: StatusFlags = StatusFlags(0b0100_0000);
+    pub const NEGATIVE: StatusFlags = StatusFlags(0b1000_0000);
+
+    pub const fn empty() -> Self {
+        StatusFlags(0)
+    }
+
+    pub const fn from_bits(bits: u8) -> Self {
+        StatusFlags(bits)
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /* whether every flag in `flags` is set */
+    pub const fn get(self, flags: StatusFlags) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+
+    pub fn set(&mut self, flags: StatusFlags, on: bool) {
+        if on {
+            self.insert(flags);
+        } else {
+            self.remove(flags);
+        }
+    }
+
+    pub fn insert(&mut self, flags: StatusFlags) {
+        self.0 |= flags.0;
+    }
+
+    pub fn remove(&mut self, flags: StatusFlags) {
+        self.0 &= !flags.0;
+    }
+}
+
+impl BitOr for StatusFlags {
+    type Output = StatusFlags;
+
+    fn bitor(self, other: StatusFlags) -> StatusFlags {
+        StatusFlags(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for StatusFlags {
+    fn bitor_assign(&mut self, other: StatusFlags) {
+        self.0 |= other.0;
+    }
+}
+
+impl BitAnd for StatusFlags {
+    type Output = StatusFlags;
+
+    fn bitand(self, other: StatusFlags) -> StatusFlags {
+        StatusFlags(self.0 & other.0)
+    }
+}
+
+impl Not for StatusFlags {
+    type Output = StatusFlags;
+
+    fn not(self) -> StatusFlags {
+        StatusFlags(!self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_flags() {
+        let mut p = StatusFlags::empty();
+        p.insert(StatusFlags::CARRY);
+        p.set(StatusFlags::NEGATIVE, true);
+        assert!(p.get(StatusFlags::CARRY));
+        assert!(p.get(StatusFlags::CARRY | StatusFlags::NEGATIVE));
+        assert!(!p.get(StatusFlags::CARRY | StatusFlags::ZERO));
+        assert_eq!(p.bits(), 0b1000_0001);
+
+        p.remove(StatusFlags::CARRY);
+        p.set(StatusFlags::NEGATIVE, false);
+        assert_eq!(p, StatusFlags::empty());
+        assert_eq!(
+            StatusFlags::from_bits(0xff) & !StatusFlags::BREAK,
+            StatusFlags::from_bits(0xef)
+        );
+    }
+}
@@ -0,0 +1,182 @@
+/*
+ * `nes testrom <dir>`: runs every .nes file in a directory headless and
+ * prints a table of results, for tracking compatibility over time. ROMs
+ * using blargg's $6000 protocol report their own result; anything else
+ * runs until the instruction limit and counts as a timeout. Frame limits
+ * and screen hash matching come with the PPU.
+ */
+
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::blargg::{self, Outcome, RunError};
+use crate::rom::Rom;
+use crate::CPU;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Fail(Outcome),
+    Timeout,
+    /* didn't load, or died before reporting */
+    Error(String),
+}
+
+impl Status {
+    pub fn passed(&self) -> bool {
+        *self == Status::Pass
+    }
+}
+
+pub fn run_rom(path: &Path, max_steps: u64) -> Status {
+    let loaded = Rom::load(path).and_then(|rom| {
+        let mut cpu = CPU::new();
+        cpu.load_rom(&rom)?;
+        Ok(cpu)
+    });
+    let mut cpu = match loaded {
+        Ok(cpu) => cpu,
+        Err(e) => return Status::Error(e),
+    };
+    match cpu.run_blargg(max_steps) {
+        Ok(outcome) if outcome.passed() => Status::Pass,
+        Ok(outcome) => Status::Fail(outcome),
+        Err(RunError::Timeout { .. }) => Status::Timeout,
+        Err(e) => Status::Error(e.to_string()),
+    }
+}
+
+/* results by file name, sorted */
+pub fn run_dir(dir: impl AsRef<Path>) -> Result<Vec<(String, Status)>, String> {
+    let dir = dir.as_ref();
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let mut roms = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("{}: {}", dir.display(), e))?
+            .path();
+        if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("nes"))
+        {
+            roms.push(path);
+        }
+    }
+    roms.sort();
+    Ok(roms
+        .iter()
+        .map(|path| {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, run_rom(path, blargg::DEFAULT_MAX_STEPS))
+        })
+        .collect())
+}
+
+pub fn table(results: &[(String, Status)]) -> String {
+    let width = results
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0)
+        .max("ROM".len());
+    let mut out = String::new();
+    writeln!(out, "{:<width$}  RESULT", "ROM", width = width).unwrap();
+    let mut counts = [0; 4];
+    for (name, status) in results {
+        let result = match status {
+            Status::Pass => "pass".to_string(),
+            Status::Fail(outcome) => format!("fail ({})", outcome),
+            Status::Timeout => "timeout".to_string(),
+            Status::Error(e) => format!("error: {}", e),
+        };
+        counts[match status {
+            Status::Pass => 0,
+            Status::Fail(_) => 1,
+            Status::Timeout => 2,
+            Status::Error(_) => 3,
+        }] += 1;
+        writeln!(out, "{:<width$}  {}", name, result, width = width).unwrap();
+    }
+    writeln!(
+        out,
+        "{} ROMs: {} passed, {} failed, {} timed out, {} errors",
+        results.len(),
+        counts[0],
+        counts[1],
+        counts[2],
+        counts[3]
+    )
+    .unwrap();
+    out
+}
+
+/* entry point for `nes testrom`, returns whether every ROM passed */
+pub fn run(dir: &str) -> Result<bool, String> {
+    let results = run_dir(dir)?;
+    print!("{}", table(&results));
+    Ok(results.iter().all(|(_, status)| status.passed()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_table() {
+        let results = vec![
+            ("01-implied.nes".to_string(), Status::Pass),
+            (
+                "02-immediate.nes".to_string(),
+                Status::Fail(Outcome {
+                    code: 2,
+                    text: "ADC #n\n".to_string(),
+                }),
+            ),
+            ("long.nes".to_string(), Status::Timeout),
+            (
+                "mmc1.nes".to_string(),
+                Status::Error("mapper 1 isn't supported".to_string()),
+            ),
+        ];
+        assert_eq!(
+            table(&results),
+            "ROM               RESULT\n\
+             01-implied.nes    pass\n\
+             02-immediate.nes  fail (failed with code 2: ADC #n)\n\
+             long.nes          timeout\n\
+             mmc1.nes          error: mapper 1 isn't supported\n\
+             4 ROMs: 1 passed, 1 failed, 1 timed out, 1 errors\n"
+        );
+    }
+
+    #[test]
+    fn test_run_dir() {
+        let dir = std::env::temp_dir().join(format!("nes-testrom-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a rom").unwrap();
+        std::fs::write(dir.join("bad.nes"), "not a rom either").unwrap();
+
+        /* NROM with LDX #$07 then JAM at the reset vector */
+        let mut prg = vec![0; 0x4000];
+        prg[..3].copy_from_slice(&[0xA2, 0x07, 0x02]);
+        prg[0x3FFC..0x3FFE].copy_from_slice(&[0x00, 0x80]);
+        let mut rom = b"NES\x1A\x01\x01\x00\x00".to_vec();
+        rom.resize(16, 0);
+        rom.extend(prg);
+        rom.extend(vec![0; 0x2000]);
+        std::fs::write(dir.join("jam.nes"), rom).unwrap();
+
+        let results = run_dir(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, "bad.nes");
+        assert!(matches!(&results[0].1, Status::Error(e) if e.ends_with("not an iNES file")));
+        assert_eq!(
+            results[1],
+            (
+                "jam.nes".to_string(),
+                Status::Error("crashed at $8002, output so far: \"\"".to_string())
+            )
+        );
+    }
+}
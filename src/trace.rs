@@ -0,0 +1,156 @@
+/*
+ * Traces in the nestest.log format, one line per instruction before it runs:
+ * C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7
+ * There's no PPU yet so its column is left off, which tracediff knows to
+ * skip, but CYC is compared. Unlike nestest the disassembly doesn't show
+ * the value at the operand address. Any watches set go on the end of the
+ * line, after CYC.
+ */
+
+use crate::opcodes::{self, Opcode};
+use crate::AddressingMode::*;
+use crate::CPU;
+
+fn disassemble(op: &Opcode, pc: u16, operand: &[u8]) -> String {
+    let byte = operand.first().copied().unwrap_or(0);
+    let word = u16::from_le_bytes([byte, operand.get(1).copied().unwrap_or(0)]);
+    let text = match op.mode {
+        Immediate => format!("#${:02X}", byte),
+        ZeroPage => format!("${:02X}", byte),
+        ZeroPage_X => format!("${:02X},X", byte),
+        ZeroPage_Y => format!("${:02X},Y", byte),
+        Absolute => format!("${:04X}", word),
+        Absolute_X => format!("${:04X},X", word),
+        Absolute_Y => format!("${:04X},Y", word),
+        Indirect => format!("(${:04X})", word),
+        Indirect_X => format!("(${:02X},X)", byte),
+        Indirect_Y => format!("(${:02X}),Y", byte),
+        Relative => format!(
+            "${:04X}",
+            pc.wrapping_add(2).wrapping_add(byte as i8 as u16)
+        ),
+        Accumulator => "A".to_string(),
+        NoneAddressing => String::new(),
+    };
+    if text.is_empty() {
+        op.mnemonic.to_string()
+    } else {
+        format!("{} {}", op.mnemonic, text)
+    }
+}
+
+impl CPU {
+    /* the trace line for the instruction about to run */
+    pub fn trace_line(&self) -> String {
+        let pc = self.program_counter;
+        let op = opcodes::lookup(self.peek(pc));
+        let bytes: Vec<u8> = (0..op.len as u16)
+            .map(|i| self.peek(pc.wrapping_add(i)))
+            .collect();
+        let hex = bytes
+            .iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let line = format!(
+            "{:04X}  {:<8} {}{:<31} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            pc,
+            hex,
+            if op.official { ' ' } else { '*' },
+            disassemble(op, pc, &bytes[1..]),
+            self.register_a.0,
+            self.register_x.0,
+            self.register_y.0,
+            self.status.bits(),
+            self.stack_pointer,
+            self.cycles
+        );
+        if self.watches.is_empty() {
+            line
+        } else {
+            format!("{} {}", line, self.watch_values())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rom::Rom;
+    use crate::status::StatusFlags;
+    use crate::tracediff;
+
+    #[test]
+    fn test_trace_lines() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xa9, 0x05, 0x8e, 0x00, 0x02, 0xd0, 0xfb, 0x0a, 0x00])
+            .unwrap();
+        assert_eq!(
+            cpu.trace_line(),
+            "8000  A9 05     LDA #$05                        A:00 X:00 Y:00 P:00 SP:FF CYC:0"
+        );
+        cpu.step().unwrap();
+        assert_eq!(
+            cpu.trace_line(),
+            "8002  8E 00 02  STX $0200                       A:05 X:00 Y:00 P:00 SP:FF CYC:2"
+        );
+        cpu.program_counter = 0x8005;
+        assert!(cpu.trace_line().starts_with("8005  D0 FB     BNE $8002 "));
+        cpu.program_counter = 0x8007;
+        assert!(cpu.trace_line().starts_with("8007  0A        ASL A "));
+
+        cpu.poke(0x8010, 0x04);
+        cpu.program_counter = 0x8010;
+        assert!(cpu.trace_line().starts_with("8010  04 00    *NOP $00 "));
+    }
+
+    #[test]
+    fn test_trace_parses_as_nestest() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xa2, 0x07, 0xe8, 0x00]).unwrap();
+        let line = tracediff::parse_line(&cpu.trace_line(), 1).unwrap();
+        assert_eq!(line.pc, 0x8000);
+        assert_eq!(line.bytes, vec![0xa2, 0x07]);
+        assert_eq!(line.text, "LDX #$07");
+        assert_eq!(line.sp, 0xFF);
+        assert_eq!(line.cyc, Some(0));
+    }
+
+    /*
+     * Runs nestest.nes in automation mode from $C000 and compares against
+     * the published log. Needs NES_NESTEST_ROM and NES_NESTEST_LOG pointing
+     * at the two files, otherwise it's skipped.
+     */
+    #[test]
+    fn test_nestest() {
+        let (Ok(rom), Ok(log)) = (
+            std::env::var("NES_NESTEST_ROM"),
+            std::env::var("NES_NESTEST_LOG"),
+        ) else {
+            return;
+        };
+        let golden = tracediff::parse_log(&std::fs::read_to_string(log).unwrap()).unwrap();
+        let mut cpu = CPU::new();
+        cpu.load_rom(&Rom::load(rom).unwrap()).unwrap();
+        cpu.program_counter = 0xC000;
+        cpu.stack_pointer = 0xFD;
+        cpu.status = StatusFlags::INTERRUPT_DISABLE | StatusFlags::UNUSED;
+        /* the log starts after the 7 cycles of the reset sequence */
+        cpu.cycles = 7;
+
+        let mut trace = String::new();
+        for _ in 0..golden.len() {
+            trace.push_str(&cpu.trace_line());
+            trace.push('\n');
+            /* an unimplemented opcode ends the trace, the diff shows where */
+            if cpu.step().is_err() {
+                break;
+            }
+        }
+        let mine = tracediff::parse_log(&trace).unwrap();
+        if let Some(divergence) = tracediff::first_divergence(&mine, &golden) {
+            panic!("{}", tracediff::report(&mine, &golden, &divergence));
+        }
+        assert_eq!(mine.len(), golden.len(), "trace stopped early");
+    }
+}
@@ -0,0 +1,338 @@
+use std::fmt::Write;
+
+/* how many matching lines to show before the divergence */
+const CONTEXT_LINES: usize = 5;
+
+/*
+ * One line of a nestest-style trace:
+ * C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+ * The PPU and CYC columns are optional since not every emulator logs them.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceLine {
+    pub line_no: usize,
+    pub pc: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub ppu: Option<(u16, u16)>,
+    pub cyc: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDiff {
+    pub field: &'static str,
+    pub mine: String,
+    pub golden: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /* indexes into the parsed logs, not line numbers */
+    pub mine: usize,
+    pub golden: usize,
+    pub fields: Vec<FieldDiff>,
+}
+
+fn parse_hex_u8(s: &str, line_no: usize) -> Result<u8, String> {
+    u8::from_str_radix(s, 16).map_err(|_| format!("line {}: bad hex byte '{}'", line_no, s))
+}
+
+fn register(line: &str, name: &str, line_no: usize) -> Result<u8, String> {
+    let key = format!(" {}:", name);
+    let start = line
+        .find(&key)
+        .ok_or_else(|| format!("line {}: missing {}", line_no, name))?
+        + key.len();
+    let value = line[start..].get(..2).unwrap_or("");
+    parse_hex_u8(value, line_no)
+}
+
+pub fn parse_line(line: &str, line_no: usize) -> Result<TraceLine, String> {
+    let pc = line
+        .get(..4)
+        .and_then(|s| u16::from_str_radix(s, 16).ok())
+        .ok_or_else(|| format!("line {}: expected a 4 digit PC", line_no))?;
+
+    let regs_at = line
+        .find(" A:")
+        .ok_or_else(|| format!("line {}: missing registers", line_no))?;
+    let instr = &line[4..regs_at];
+
+    /* instruction bytes come first, the disassembly starts at the first non-hex-pair token */
+    let mut bytes = Vec::new();
+    let mut rest = instr.trim_start();
+    while bytes.len() < 3 {
+        let token = rest.split_whitespace().next().unwrap_or("");
+        if token.len() != 2 || !token.chars().all(|c| c.is_ascii_hexdigit()) {
+            break;
+        }
+        bytes.push(parse_hex_u8(token, line_no)?);
+        rest = rest[2..].trim_start();
+    }
+    let text = rest.trim_start_matches('*').trim().to_string();
+
+    let regs = &line[regs_at..];
+    let ppu = regs.find("PPU:").and_then(|at| {
+        let mut parts = regs[at + 4..].split(',');
+        let scanline = parts.next()?.trim().parse().ok()?;
+        let dot = parts.next()?.split_whitespace().next()?.parse().ok()?;
+        Some((scanline, dot))
+    });
+    let cyc = regs
+        .find("CYC:")
+        .and_then(|at| regs[at + 4..].split_whitespace().next()?.parse().ok());
+
+    Ok(TraceLine {
+        line_no,
+        pc,
+        bytes,
+        text,
+        a: register(regs, "A", line_no)?,
+        x: register(regs, "X", line_no)?,
+        y: register(regs, "Y", line_no)?,
+        p: register(regs, "P", line_no)?,
+        sp: register(regs, "SP", line_no)?,
+        ppu,
+        cyc,
+    })
+}
+
+pub fn parse_log(log: &str) -> Result<Vec<TraceLine>, String> {
+    log.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| parse_line(line, i + 1))
+        .collect()
+}
+
+fn diff_field<T: PartialEq>(
+    fields: &mut Vec<FieldDiff>,
+    field: &'static str,
+    mine: T,
+    golden: T,
+    show: impl Fn(T) -> String,
+) {
+    if mine != golden {
+        fields.push(FieldDiff {
+            field,
+            mine: show(mine),
+            golden: show(golden),
+        });
+    }
+}
+
+pub fn compare(mine: &TraceLine, golden: &TraceLine) -> Vec<FieldDiff> {
+    let mut fields = Vec::new();
+    let hex8 = |v: u8| format!("{:02X}", v);
+    diff_field(&mut fields, "PC", mine.pc, golden.pc, |v| {
+        format!("{:04X}", v)
+    });
+    diff_field(&mut fields, "bytes", &mine.bytes, &golden.bytes, |v| {
+        v.iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<_>>()
+            .join(" ")
+    });
+    diff_field(&mut fields, "A", mine.a, golden.a, hex8);
+    diff_field(&mut fields, "X", mine.x, golden.x, hex8);
+    diff_field(&mut fields, "Y", mine.y, golden.y, hex8);
+    diff_field(&mut fields, "P", mine.p, golden.p, hex8);
+    diff_field(&mut fields, "SP", mine.sp, golden.sp, hex8);
+    /* optional columns only count when both logs have them */
+    if let (Some(m), Some(g)) = (mine.ppu, golden.ppu) {
+        diff_field(&mut fields, "PPU", m, g, |(s, d)| format!("{},{}", s, d));
+    }
+    if let (Some(m), Some(g)) = (mine.cyc, golden.cyc) {
+        diff_field(&mut fields, "CYC", m, g, |v| v.to_string());
+    }
+    fields
+}
+
+/*
+ * Logs don't always start at the same instruction (reset vector vs. $C000 for
+ * nestest), so skip ahead in the golden log to the first line with our
+ * starting PC before comparing line by line.
+ */
+pub fn align(mine: &[TraceLine], golden: &[TraceLine]) -> usize {
+    mine.first()
+        .and_then(|first| golden.iter().position(|g| g.pc == first.pc))
+        .unwrap_or(0)
+}
+
+pub fn first_divergence(mine: &[TraceLine], golden: &[TraceLine]) -> Option<Divergence> {
+    let offset = align(mine, golden);
+    for (i, m) in mine.iter().enumerate() {
+        let Some(g) = golden.get(offset + i) else {
+            /* golden log ran out, nothing left to compare against */
+            return None;
+        };
+        let fields = compare(m, g);
+        if !fields.is_empty() {
+            return Some(Divergence {
+                mine: i,
+                golden: offset + i,
+                fields,
+            });
+        }
+    }
+    None
+}
+
+pub fn report(mine: &[TraceLine], golden: &[TraceLine], divergence: &Divergence) -> String {
+    let mut out = String::new();
+    let m = &mine[divergence.mine];
+    let g = &golden[divergence.golden];
+    writeln!(
+        out,
+        "first divergence at mine:{} golden:{} (instruction {})",
+        m.line_no,
+        g.line_no,
+        divergence.mine + 1
+    )
+    .unwrap();
+
+    let context = divergence.mine.min(CONTEXT_LINES);
+    for line in &mine[divergence.mine - context..divergence.mine] {
+        writeln!(out, "    {:04X}  {}", line.pc, line.text).unwrap();
+    }
+    writeln!(out, "mine   {:04X}  {}", m.pc, m.text).unwrap();
+    writeln!(out, "golden {:04X}  {}", g.pc, g.text).unwrap();
+    for field in &divergence.fields {
+        writeln!(
+            out,
+            "  {:<5} mine={} golden={}",
+            field.field, field.mine, field.golden
+        )
+        .unwrap();
+    }
+    out
+}
+
+/* entry point for `nes tracediff`, returns whether the logs matched */
+pub fn run(mine_path: &str, golden_path: &str) -> Result<bool, String> {
+    let read = |path: &str| std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e));
+    let mine = parse_log(&read(mine_path)?).map_err(|e| format!("{}: {}", mine_path, e))?;
+    let golden = parse_log(&read(golden_path)?).map_err(|e| format!("{}: {}", golden_path, e))?;
+
+    match first_divergence(&mine, &golden) {
+        Some(divergence) => {
+            print!("{}", report(&mine, &golden, &divergence));
+            Ok(false)
+        }
+        None => {
+            println!("no divergence in {} instructions", mine.len());
+            Ok(true)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const GOLDEN: &str = "\
+C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+C5F5  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 30 CYC:10
+C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 36 CYC:12
+C5F9  86 10     STX $10 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 45 CYC:15
+C5FB  86 11     STX $11 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 54 CYC:18
+";
+
+    #[test]
+    fn test_parse_nestest_line() {
+        let line = parse_line(GOLDEN.lines().next().unwrap(), 1).unwrap();
+        assert_eq!(line.pc, 0xC000);
+        assert_eq!(line.bytes, vec![0x4C, 0xF5, 0xC5]);
+        assert_eq!(line.text, "JMP $C5F5");
+        assert_eq!(line.p, 0x24);
+        assert_eq!(line.sp, 0xFD);
+        assert_eq!(line.ppu, Some((0, 21)));
+        assert_eq!(line.cyc, Some(7));
+    }
+
+    #[test]
+    fn test_parse_without_optional_columns() {
+        let line = parse_line(
+            "C72D  EA        NOP                             A:00 X:00 Y:00 P:26 SP:FB",
+            1,
+        )
+        .unwrap();
+        assert_eq!(line.bytes, vec![0xEA]);
+        assert_eq!(line.text, "NOP");
+        assert_eq!(line.ppu, None);
+        assert_eq!(line.cyc, None);
+    }
+
+    #[test]
+    fn test_parse_illegal_opcode_marker() {
+        let line = parse_line(
+            "E595  04 A9    *NOP $A9 = 00                    A:AA X:97 Y:4E P:EF SP:F5",
+            1,
+        )
+        .unwrap();
+        assert_eq!(line.bytes, vec![0x04, 0xA9]);
+        assert_eq!(line.text, "NOP $A9 = 00");
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert!(parse_line("garbage", 3).unwrap_err().contains("line 3"));
+        assert!(parse_log("C000  EA  NOP  A:00 X:00 Y:00 P:ZZ SP:FD").is_err());
+    }
+
+    #[test]
+    fn test_identical_logs() {
+        let golden = parse_log(GOLDEN).unwrap();
+        assert_eq!(first_divergence(&golden, &golden), None);
+    }
+
+    #[test]
+    fn test_register_divergence() {
+        let golden = parse_log(GOLDEN).unwrap();
+        let mine =
+            parse_log(&GOLDEN.replace("P:26 SP:FD PPU:  0, 45", "P:24 SP:FD PPU:  0, 45")).unwrap();
+
+        let divergence = first_divergence(&mine, &golden).unwrap();
+        assert_eq!(divergence.mine, 3);
+        assert_eq!(
+            divergence.fields,
+            vec![FieldDiff {
+                field: "P",
+                mine: "24".to_string(),
+                golden: "26".to_string(),
+            }]
+        );
+
+        let report = report(&mine, &golden, &divergence);
+        assert!(report.starts_with("first divergence at mine:4 golden:4"));
+        assert!(report.contains("  P     mine=24 golden=26"));
+    }
+
+    #[test]
+    fn test_missing_columns_are_not_compared() {
+        let golden = parse_log(GOLDEN).unwrap();
+        let mine: Vec<TraceLine> = golden
+            .iter()
+            .cloned()
+            .map(|mut line| {
+                line.ppu = None;
+                line.cyc = None;
+                line
+            })
+            .collect();
+        assert_eq!(first_divergence(&mine, &golden), None);
+    }
+
+    #[test]
+    fn test_alignment_skips_golden_prefix() {
+        let golden = parse_log(GOLDEN).unwrap();
+        let mine = golden[2..].to_vec();
+        assert_eq!(align(&mine, &golden), 2);
+        assert_eq!(first_divergence(&mine, &golden), None);
+    }
+}
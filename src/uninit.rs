@@ -0,0 +1,196 @@
+use crate::CPU;
+
+/* internal RAM plus its mirrors */
+const RAM_END: usize = 0x2000;
+const RAM_SIZE: usize = 0x0800;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UninitRead {
+    pub addr: u16,
+    pub pc: u16,
+}
+
+/*
+ * Strict mode for homebrew: real hardware powers up with garbage in RAM, so
+ * reading a byte nobody wrote is almost always a bug even if it happens to
+ * work here because our memory starts zeroed. Only reads made while an
+ * instruction executes are checked, so debugger and test peeks are ignored.
+ */
+pub struct UninitTracker {
+    written: Vec<bool>,
+    reported: Vec<bool>,
+    reads: Vec<UninitRead>,
+    break_on_read: bool,
+    current_pc: Option<u16>,
+    hit: bool,
+}
+
+impl UninitTracker {
+    pub fn new(break_on_read: bool) -> Self {
+        UninitTracker {
+            written: vec![false; RAM_SIZE],
+            reported: vec![false; RAM_SIZE],
+            reads: Vec::new(),
+            break_on_read,
+            current_pc: None,
+            hit: false,
+        }
+    }
+
+    /* each byte of RAM is reported once, on its first uninitialized read */
+    pub fn reads(&self) -> &[UninitRead] {
+        &self.reads
+    }
+
+    pub fn is_written(&self, addr: u16) -> bool {
+        (addr as usize) >= RAM_END || self.written[addr as usize % RAM_SIZE]
+    }
+
+    pub(crate) fn begin(&mut self, pc: u16) {
+        self.current_pc = Some(pc);
+        self.hit = false;
+    }
+
+    pub(crate) fn end(&mut self) {
+        self.current_pc = None;
+    }
+
+    pub(crate) fn should_break(&self) -> bool {
+        self.break_on_read && self.hit
+    }
+
+    pub(crate) fn record_write(&mut self, addr: u16) {
+        if (addr as usize) < RAM_END {
+            self.written[addr as usize % RAM_SIZE] = true;
+        }
+    }
+
+    pub(crate) fn record_read(&mut self, addr: u16) {
+        let Some(pc) = self.current_pc else {
+            return;
+        };
+        if addr as usize >= RAM_END {
+            return;
+        }
+        let idx = addr as usize % RAM_SIZE;
+        if self.written[idx] || self.reported[idx] {
+            return;
+        }
+        self.reported[idx] = true;
+        self.reads.push(UninitRead { addr, pc });
+        self.hit = true;
+    }
+}
+
+impl CPU {
+    /* enable before loading anything into RAM, writes made earlier aren't seen */
+    pub fn enable_uninit_tracking(&mut self, break_on_read: bool) {
+        self.uninit = Some(UninitTracker::new(break_on_read));
+    }
+
+    pub fn uninit_tracker(&self) -> Option<&UninitTracker> {
+        self.uninit.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Mem;
+
+    #[test]
+    fn test_read_of_uninitialized_ram() {
+        let mut cpu = CPU::new();
+        cpu.enable_uninit_tracking(false);
+        cpu.init(vec![0xa5, 0x10, 0x00]).unwrap();
+        cpu.run();
+
+        let reads = cpu.uninit_tracker().unwrap().reads();
+        assert_eq!(
+            reads,
+            &[UninitRead {
+                addr: 0x10,
+                pc: 0x8000
+            }]
+        );
+    }
+
+    #[test]
+    fn test_written_ram_is_not_reported() {
+        let mut cpu = CPU::new();
+        cpu.enable_uninit_tracking(false);
+        cpu.init(vec![0xa9, 0x01, 0x85, 0x10, 0xa5, 0x10, 0x00])
+            .unwrap();
+        cpu.run();
+
+        assert!(cpu.uninit_tracker().unwrap().reads().is_empty());
+        assert!(cpu.uninit_tracker().unwrap().is_written(0x10));
+    }
+
+    #[test]
+    fn test_host_writes_count_as_initialized() {
+        let mut cpu = CPU::new();
+        cpu.enable_uninit_tracking(false);
+        cpu.mem_write(0x10, 0x55);
+        cpu.init(vec![0xa5, 0x10, 0x00]).unwrap();
+        cpu.run();
+
+        assert!(cpu.uninit_tracker().unwrap().reads().is_empty());
+    }
+
+    #[test]
+    fn test_mirrors_share_initialization() {
+        let mut cpu = CPU::new();
+        cpu.enable_uninit_tracking(false);
+        /* written through $0810, read back through $0010 and $1010 */
+        cpu.init(vec![
+            0xa9, 0x42, 0x8d, 0x10, 0x08, 0xa5, 0x10, 0xad, 0x10, 0x10, 0x00,
+        ])
+        .unwrap();
+        cpu.run();
+
+        assert_eq!(cpu.register_a.0, 0x42);
+        assert!(cpu.uninit_tracker().unwrap().reads().is_empty());
+        assert!(cpu.uninit_tracker().unwrap().is_written(0x1810));
+    }
+
+    #[test]
+    fn test_reads_outside_ram_are_ignored() {
+        let mut cpu = CPU::new();
+        cpu.enable_uninit_tracking(false);
+        cpu.init(vec![0xad, 0x00, 0x30, 0x00]).unwrap();
+        cpu.run();
+
+        assert!(cpu.uninit_tracker().unwrap().reads().is_empty());
+    }
+
+    #[test]
+    fn test_each_address_reported_once() {
+        let mut cpu = CPU::new();
+        cpu.enable_uninit_tracking(false);
+        cpu.init(vec![0xa5, 0x10, 0xa5, 0x10, 0xa5, 0x11, 0x00])
+            .unwrap();
+        cpu.run();
+
+        let reads = cpu.uninit_tracker().unwrap().reads();
+        assert_eq!(reads.len(), 2);
+        assert_eq!(
+            reads[1],
+            UninitRead {
+                addr: 0x11,
+                pc: 0x8004
+            }
+        );
+    }
+
+    #[test]
+    fn test_break_on_read() {
+        let mut cpu = CPU::new();
+        cpu.enable_uninit_tracking(true);
+        cpu.init(vec![0xa5, 0x10, 0xe8, 0x00]).unwrap();
+        cpu.run();
+
+        assert_eq!(cpu.program_counter, 0x8002);
+        assert_eq!(cpu.register_x.0, 0);
+    }
+}
@@ -0,0 +1,151 @@
+use crate::status::StatusFlags;
+use crate::CPU;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watch {
+    A,
+    X,
+    Y,
+    P,
+    SP,
+    PC,
+    /* a single status flag */
+    Flag(char, StatusFlags),
+    Memory(u16),
+}
+
+const FLAGS: [(char, StatusFlags); 7] = [
+    ('C', StatusFlags::CARRY),
+    ('Z', StatusFlags::ZERO),
+    ('I', StatusFlags::INTERRUPT_DISABLE),
+    ('D', StatusFlags::DECIMAL),
+    ('B', StatusFlags::BREAK),
+    ('V', StatusFlags::OVERFLOW),
+    ('N', StatusFlags::NEGATIVE),
+];
+
+impl Watch {
+    /* registers (A X Y P SP PC), flags (C Z I D B V N) or a memory read like [$00D0] */
+    pub fn parse(expr: &str) -> Result<Watch, String> {
+        let expr = expr.trim();
+        let upper = expr.to_ascii_uppercase();
+        let watch = match upper.as_str() {
+            "A" => Watch::A,
+            "X" => Watch::X,
+            "Y" => Watch::Y,
+            "P" => Watch::P,
+            "SP" => Watch::SP,
+            "PC" => Watch::PC,
+            _ => {
+                if let Some(&(name, mask)) =
+                    FLAGS.iter().find(|(name, _)| upper == name.to_string())
+                {
+                    return Ok(Watch::Flag(name, mask));
+                }
+                let addr = upper
+                    .strip_prefix('[')
+                    .and_then(|s| s.strip_suffix(']'))
+                    .map(|s| s.trim().trim_start_matches('$'))
+                    .and_then(|s| u16::from_str_radix(s, 16).ok())
+                    .ok_or_else(|| format!("bad watch expression '{}'", expr))?;
+                Watch::Memory(addr)
+            }
+        };
+        Ok(watch)
+    }
+
+    pub fn eval(&self, cpu: &CPU) -> u16 {
+        match *self {
+            Watch::A => cpu.register_a.0 as u16,
+            Watch::X => cpu.register_x.0 as u16,
+            Watch::Y => cpu.register_y.0 as u16,
+            Watch::P => cpu.status.bits() as u16,
+            Watch::SP => cpu.stack_pointer as u16,
+            Watch::PC => cpu.program_counter,
+            Watch::Flag(_, flag) => cpu.status.get(flag) as u16,
+            Watch::Memory(addr) => cpu.peek(addr) as u16,
+        }
+    }
+
+    pub fn format(&self, cpu: &CPU) -> String {
+        let value = self.eval(cpu);
+        match *self {
+            Watch::A => format!("A:{:02X}", value),
+            Watch::X => format!("X:{:02X}", value),
+            Watch::Y => format!("Y:{:02X}", value),
+            Watch::P => format!("P:{:02X}", value),
+            Watch::SP => format!("SP:{:02X}", value),
+            Watch::PC => format!("PC:{:04X}", value),
+            Watch::Flag(name, _) => format!("{}:{}", name, value),
+            Watch::Memory(addr) => format!("[${:04X}]:{:02X}", addr, value),
+        }
+    }
+}
+
+impl CPU {
+    pub fn add_watch(&mut self, expr: &str) -> Result<(), String> {
+        self.watches.push(Watch::parse(expr)?);
+        Ok(())
+    }
+
+    pub fn clear_watches(&mut self) {
+        self.watches.clear();
+    }
+
+    /* every watch formatted and space separated, e.g. "A:05 [$00D0]:12" */
+    pub fn watch_values(&self) -> String {
+        self.watches
+            .iter()
+            .map(|watch| watch.format(self))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Mem;
+
+    #[test]
+    fn test_parse_watches() {
+        assert_eq!(Watch::parse("a"), Ok(Watch::A));
+        assert_eq!(Watch::parse("SP"), Ok(Watch::SP));
+        assert_eq!(Watch::parse("Z"), Ok(Watch::Flag('Z', StatusFlags::ZERO)));
+        assert_eq!(Watch::parse("[$00D0]"), Ok(Watch::Memory(0x00D0)));
+        assert_eq!(Watch::parse(" [ d0 ] "), Ok(Watch::Memory(0x00D0)));
+        assert!(Watch::parse("[$10000]").is_err());
+        assert!(Watch::parse("Q").is_err());
+    }
+
+    #[test]
+    fn test_watch_values() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xa9, 0x00, 0x85, 0xd0, 0x00]).unwrap();
+        cpu.mem_write(0xd0, 0x12);
+        cpu.add_watch("A").unwrap();
+        cpu.add_watch("Z").unwrap();
+        cpu.add_watch("[$00D0]").unwrap();
+        assert_eq!(cpu.watch_values(), "A:00 Z:0 [$00D0]:12");
+
+        cpu.run();
+        assert_eq!(cpu.watch_values(), "A:00 Z:1 [$00D0]:00");
+    }
+
+    #[test]
+    fn test_watches_appended_to_trace() {
+        let mut cpu = CPU::new();
+        cpu.init(vec![0xa9, 0x07, 0x85, 0xd0, 0x00]).unwrap();
+        cpu.add_watch("[$00D0]").unwrap();
+        let mut trace = Vec::new();
+        cpu.run_with_callback(|cpu| trace.push(cpu.trace_line()));
+
+        assert_eq!(trace.len(), 3);
+        assert!(trace[0].ends_with("CYC:0 [$00D0]:00"));
+        assert!(trace[1].ends_with("CYC:2 [$00D0]:00"));
+        assert!(trace[2].ends_with("CYC:5 [$00D0]:07"));
+
+        cpu.clear_watches();
+        assert!(cpu.trace_line().ends_with(" CYC:12"));
+    }
+}